@@ -0,0 +1,121 @@
+#![allow(unused)]
+
+// MITS Altair 8800: CPU card, RAM of a chosen size from 0000H, an 88-2SIO
+// serial board wired to the host terminal, an 88-DCDD disk controller and
+// the front panel's sense switches.
+//
+// Ports:
+//   08H-0AH  88-DCDD, see dcdd.rs
+//   10H  2SIO port A control (out) / status (in)
+//   11H  2SIO port A data
+//   FFH  sense switches A15-A8 (in) / programmed output LEDs (out)
+//
+// Every other port reads 0FFH, as on a real bus.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::Cpu;
+use crate::dcdd::{self, Dcdd};
+use crate::device::{OpenBus, PortDevice};
+use crate::error::Error;
+use crate::loader::Segment;
+use crate::serial::Line;
+
+pub const SIO_PORTS: [u8; 2] = [0x10, 0x11];
+pub const SENSE_PORT: u8 = 0xff;
+
+// MC6850 status bits.
+const RDRF: u8 = 0x01;
+const TDRE: u8 = 0x02;
+const MASTER_RESET: u8 = 0x03;
+
+// One MC6850 ACIA. Output has the parity bit stripped.
+pub struct Acia {
+    control: u8,
+    pub line: Line,
+}
+
+impl Acia {
+    pub fn new(line: Line) -> Self {
+        Self { control: 0, line }
+    }
+}
+
+impl PortDevice for Acia {
+    fn read_port(&mut self, port: u8) -> u8 {
+        if port & 1 == 0 {
+            let tdre = if self.line.tx_ready() { TDRE } else { 0 };
+            if self.line.ready() { tdre | RDRF } else { tdre }
+        } else {
+            self.line.read().unwrap_or(0)
+        }
+    }
+
+    fn write_port(&mut self, port: u8, byte: u8) {
+        if port & 1 == 0 {
+            self.control = byte;
+            if byte & 0x03 == MASTER_RESET {
+                self.line.clear();
+            }
+        } else {
+            self.line.write(byte & 0x7f);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FrontPanel {
+    pub switches: u8,
+    pub leds: u8,
+}
+
+impl PortDevice for FrontPanel {
+    fn read_port(&mut self, _port: u8) -> u8 {
+        self.switches
+    }
+
+    fn write_port(&mut self, _port: u8, byte: u8) {
+        self.leds = byte;
+    }
+}
+
+pub struct Altair {
+    pub cpu: Cpu,
+    pub sio: Rc<RefCell<Acia>>,
+    pub disk: Rc<RefCell<Dcdd>>,
+    pub panel: Rc<RefCell<FrontPanel>>,
+}
+
+impl Altair {
+    // `memory` is the installed RAM in bytes.
+    pub fn new(memory: usize, console: Line) -> Self {
+        let mut cpu = Cpu::new();
+        cpu.ram.set_size(memory);
+        for device in cpu.devices.iter_mut() {
+            *device = Some(Box::new(OpenBus));
+        }
+        let sio = Rc::new(RefCell::new(Acia::new(console)));
+        let disk = Rc::new(RefCell::new(Dcdd::new()));
+        let panel = Rc::new(RefCell::new(FrontPanel::default()));
+        cpu.attach(SIO_PORTS, &sio);
+        cpu.attach(dcdd::PORTS, &disk);
+        cpu.clock(&disk);
+        cpu.attach([SENSE_PORT], &panel);
+        Self { cpu, sio, disk, panel }
+    }
+
+    pub fn load(&mut self, segments: &[Segment]) {
+        for (addr, data) in segments {
+            self.cpu.ram.load_at(*addr, data);
+        }
+    }
+
+    // Runs until the CPU halts or waits on input that has run out.
+    pub fn run(&mut self) -> Result<(), Error> {
+        while !self.cpu.halted && !self.sio.borrow().line.is_idle() {
+            self.cpu.next()?;
+        }
+        Ok(())
+    }
+}
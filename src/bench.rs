@@ -0,0 +1,194 @@
+#![allow(unused)]
+
+// Benchmarks, run with `i8080 bench`. Times are wall clock and depend on
+// the host, so compare runs made on the same machine.
+//
+// The workloads run as CP/M programs at 0100H and report instructions per
+// second and the clock rate the emulation keeps up with, as a multiple of
+// the real `CLOCK_RATE` too. Each runs on the interpreter, then through
+// the block cache.
+
+use std::hint::black_box;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::cpu::{decode, decode_by_rules, Cpu, CLOCK_RATE};
+use crate::error::Error;
+use crate::instruction::Instruction;
+
+// Passes over all 256 opcodes per decoder.
+const DECODE_ROUNDS: u32 = 20_000;
+
+// Runs of 8080PRE, which alone is over too quickly to time.
+const PRELIMINARY_RUNS: u32 = 1000;
+
+// Arithmetic and logic on registers: 8 x 65536 passes of an 11
+// instruction loop.
+const ALU_LOOP: &[u8] = &[
+    0x3e, 0x00,       // 0100 MVI A,0
+    0x16, 0x08,       // 0102 MVI D,8
+    0x01, 0x00, 0x00, // 0104 LXI B,0
+    0x80,             // 0107 ADD B
+    0xa9,             // 0108 XRA C
+    0xe6, 0x5a,       // 0109 ANI 5AH
+    0xb2,             // 010B ORA D
+    0xd6, 0x03,       // 010C SUI 3
+    0x07,             // 010E RLC
+    0x0b,             // 010F DCX B
+    0x5f,             // 0110 MOV E,A
+    0x78,             // 0111 MOV A,B
+    0xb1,             // 0112 ORA C
+    0x7b,             // 0113 MOV A,E
+    0xc2, 0x07, 0x01, // 0114 JNZ 0107H
+    0x15,             // 0117 DCR D
+    0xc2, 0x04, 0x01, // 0118 JNZ 0104H
+    0xc3, 0x00, 0x00, // 011B JMP 0
+];
+
+// Copies 16 KB from 4000H to 8000H, 32 times.
+const MEMORY_COPY: &[u8] = &[
+    0x3e, 0x20,       // 0100 MVI A,32
+    0x32, 0x80, 0x01, // 0102 STA 0180H
+    0x21, 0x00, 0x40, // 0105 LXI H,4000H
+    0x11, 0x00, 0x80, // 0108 LXI D,8000H
+    0x01, 0x00, 0x40, // 010B LXI B,4000H
+    0x7e,             // 010E MOV A,M
+    0x12,             // 010F STAX D
+    0x23,             // 0110 INX H
+    0x13,             // 0111 INX D
+    0x0b,             // 0112 DCX B
+    0x78,             // 0113 MOV A,B
+    0xb1,             // 0114 ORA C
+    0xc2, 0x0e, 0x01, // 0115 JNZ 010EH
+    0x3a, 0x80, 0x01, // 0118 LDA 0180H
+    0x3d,             // 011B DCR A
+    0x32, 0x80, 0x01, // 011C STA 0180H
+    0xc2, 0x05, 0x01, // 011F JNZ 0105H
+    0xc3, 0x00, 0x00, // 0122 JMP 0
+];
+
+// Console output through BDOS functions 2 and 9, 65536 times each. The
+// string sits at 0140H.
+const BDOS_CALLS: &[u8] = &[
+    0x21, 0x00, 0x00, // 0100 LXI H,0
+    0xe5,             // 0103 PUSH H
+    0x0e, 0x02,       // 0104 MVI C,2
+    0x1e, 0x2a,       // 0106 MVI E,'*'
+    0xcd, 0x05, 0x00, // 0108 CALL 5
+    0x0e, 0x09,       // 010B MVI C,9
+    0x11, 0x40, 0x01, // 010D LXI D,0140H
+    0xcd, 0x05, 0x00, // 0110 CALL 5
+    0xe1,             // 0113 POP H
+    0x2b,             // 0114 DCX H
+    0x7c,             // 0115 MOV A,H
+    0xb5,             // 0116 ORA L
+    0xc2, 0x03, 0x01, // 0117 JNZ 0103H
+    0xc3, 0x00, 0x00, // 011A JMP 0
+];
+
+const BDOS_STRING: &[u8] = b"hello, world\r\n$";
+
+struct Workload {
+    instructions: u64,
+    cycles: u64,
+    elapsed: Duration,
+}
+
+// Runs a workload, through the block cache if asked.
+type Runner = dyn Fn(bool) -> Result<Workload, Error>;
+
+pub fn run(out: &mut dyn Write) -> io::Result<()> {
+    decoders(out)?;
+    writeln!(out)?;
+    workloads(out)
+}
+
+fn workloads(out: &mut dyn Write) -> io::Result<()> {
+    let clock = CLOCK_RATE as f64 / 1e6;
+    writeln!(out, "workload            instructions  seconds   MIPS      MHz  x {clock} MHz")?;
+    let workloads: [(&str, &Runner); 4] = [
+        ("8080PRE", &preliminary),
+        ("ALU loop", &|blocks| program(ALU_LOOP, &[], blocks)),
+        ("memory copy", &|blocks| program(MEMORY_COPY, &[], blocks)),
+        ("BDOS calls", &|blocks| program(BDOS_CALLS, &[(0x0140, BDOS_STRING)], blocks)),
+    ];
+    for (name, run) in workloads {
+        let interp = run(false).map_err(io::Error::other)?;
+        let blocks = run(true).map_err(io::Error::other)?;
+        report(out, name, &interp)?;
+        // The same instructions ran; the block cache only counts blocks.
+        report(out, "  block cache", &Workload { instructions: interp.instructions, ..blocks })?;
+    }
+    Ok(())
+}
+
+fn report(out: &mut dyn Write, name: &str, workload: &Workload) -> io::Result<()> {
+    let clock = CLOCK_RATE as f64 / 1e6;
+    let seconds = workload.elapsed.as_secs_f64();
+    let mhz = workload.cycles as f64 / seconds / 1e6;
+    writeln!(out, "{name:<18} {:>13} {seconds:>8.3} {:>6.1} {mhz:>8.1} {:>10.1}",
+        workload.instructions, workload.instructions as f64 / seconds / 1e6, mhz / clock)
+}
+
+fn preliminary(blocks: bool) -> Result<Workload, Error> {
+    let image = include_bytes!("../test_roms/8080PRE.COM");
+    let mut total = Workload { instructions: 0, cycles: 0, elapsed: Duration::ZERO };
+    for _ in 0..PRELIMINARY_RUNS {
+        let run = program(image, &[], blocks)?;
+        total.instructions += run.instructions;
+        total.cycles += run.cycles;
+        total.elapsed += run.elapsed;
+    }
+    Ok(total)
+}
+
+// Runs `image` to its warm boot, BDOS console output going nowhere.
+// `instructions` counts steps, which are whole blocks through the cache.
+fn program(image: &[u8], data: &[(u16, &[u8])], blocks: bool) -> Result<Workload, Error> {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0x0100, image);
+    for &(addr, bytes) in data {
+        cpu.ram.load_at(addr, bytes);
+    }
+    cpu.prepare_cpm();
+    if blocks {
+        cpu.translate_blocks();
+    }
+    let mut instructions = 0;
+    let start = Instant::now();
+    while !cpu.halted {
+        cpu.next_block()?;
+        instructions += 1;
+        if cpu.cpm_hook(&mut io::sink()) {
+            break;
+        }
+    }
+    Ok(Workload { instructions, cycles: cpu.cycles, elapsed: start.elapsed() })
+}
+
+// The decode table against walking the decode rules for every opcode.
+fn decoders(out: &mut dyn Write) -> io::Result<()> {
+    let table = time_decode(|bytes| {
+        let mut bytes = bytes.iter().copied();
+        decode(|| bytes.next().unwrap_or(0))
+    });
+    let rules = time_decode(|bytes| {
+        let mut bytes = bytes.iter().copied();
+        decode_by_rules(|| bytes.next().unwrap_or(0))
+    });
+    writeln!(out, "decode, table   {table:7.2} ns/instruction")?;
+    writeln!(out, "decode, rules   {rules:7.2} ns/instruction")?;
+    writeln!(out, "                {:7.2}x faster", rules / table)
+}
+
+// Nanoseconds per decoded instruction.
+fn time_decode(decode: impl Fn(&[u8]) -> Result<Instruction, Error>) -> f64 {
+    let program: Vec<[u8; 3]> = (0..=255).map(|op| [op, 0x34, 0x12]).collect();
+    let start = Instant::now();
+    for _ in 0..DECODE_ROUNDS {
+        for bytes in &program {
+            black_box(decode(black_box(bytes)).ok());
+        }
+    }
+    start.elapsed().as_nanos() as f64 / (DECODE_ROUNDS as f64 * program.len() as f64)
+}
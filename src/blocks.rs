@@ -0,0 +1,184 @@
+#![allow(unused)]
+
+// Basic-block translation cache, an optional engine for long runs.
+// `Cpu::next_block` decodes the straight-line code at PC once, up to and
+// including the next jump, call, return, RST or HLT, and from then on runs
+// it from the cache without fetching or decoding. Each instruction still
+// executes, counts its T-states and ticks the clocked devices on its own.
+//
+// A write to translated code drops every block holding the byte written,
+// and a block that writes over translated code stops after that
+// instruction. Whatever the cache can't reproduce exactly goes to the
+// interpreter: the Z80, wait states, recording for step back, a pending
+// interrupt or HOLD, and HLT.
+
+use std::rc::Rc;
+
+use crate::cpu::{decode_for, Cpu, Variant, RAM_SIZE};
+use crate::error::Error;
+use crate::instruction::Instruction;
+
+// Instructions per block at most, so a long run of straight code still
+// comes back to the caller now and then.
+const MAX_BLOCK: usize = 64;
+
+// A pre-decoded instruction: what to run, where PC goes after the fetch,
+// and the T-states the interpreter would count before executing it.
+#[derive(Debug, Clone, Copy)]
+struct Op {
+    ins: Instruction,
+    next: u16,
+    cycles: u8,
+}
+
+#[derive(Debug)]
+pub struct BlockCache {
+    // By start address.
+    blocks: Vec<Option<Rc<[Op]>>>,
+    // Start addresses of the blocks on each page.
+    pages: Vec<Vec<u16>>,
+    pub translated: u64,
+    pub invalidated: u64,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self {
+            blocks: vec![None; RAM_SIZE],
+            pages: vec![Vec::new(); 256],
+            translated: 0,
+            invalidated: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.iter().flatten().count()
+    }
+
+    // Drops the blocks holding `addr`.
+    fn invalidate(&mut self, addr: u16) {
+        let blocks = &mut self.blocks;
+        let mut invalidated = 0;
+        self.pages[addr as usize >> 8].retain(|&start| {
+            let Some(block) = &blocks[start as usize] else { return false };
+            if !(start..block[block.len() - 1].next).contains(&addr) {
+                return true;
+            }
+            blocks[start as usize] = None;
+            invalidated += 1;
+            false
+        });
+        self.invalidated += invalidated;
+    }
+}
+
+impl Cpu {
+    // Runs through the block cache from now on; see `next_block`.
+    pub fn translate_blocks(&mut self) {
+        self.blocks = Some(BlockCache::new());
+    }
+
+    // Runs the basic block at PC from the cache, or the next instruction
+    // through the interpreter when the cache is off or can't run it.
+    pub fn next_block(&mut self) -> Result<(), Error> {
+        if !self.block_ready() {
+            return self.next();
+        }
+        let block = match self.cached_block() {
+            Some(block) => block,
+            None => return self.next(),
+        };
+        for op in block.iter() {
+            if self.wants_bus() {
+                break;
+            }
+            // What `dispatch` does for an instruction with no interrupt.
+            self.ei_delay = false;
+            self.cycles += op.cycles as u64;
+            self.pc = op.next;
+            self.excecute(op.ins)?;
+            self.tick();
+            if self.pc != op.next || self.ram.code_written() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn block_ready(&self) -> bool {
+        self.blocks.is_some()
+            && self.variant != Variant::Z80
+            && self.journal.is_none()
+            && self.pending_cycles.is_empty()
+            && !self.halted
+            && !self.ram.has_wait_states()
+            && !self.wants_bus()
+    }
+
+    // Whether the interpreter would do something else than run the next
+    // instruction: give the bus away or take an interrupt.
+    fn wants_bus(&self) -> bool {
+        if self.hold.as_ref().is_some_and(|master| master.borrow().hold()) {
+            return true;
+        }
+        if self.variant == Variant::I8085 && self.pins.borrow().pending(self.inte && !self.ei_delay).is_some() {
+            return true;
+        }
+        !self.ei_delay && self.inte && self.intr.as_ref().is_some_and(|source| source.borrow().requested())
+    }
+
+    // The block at PC, translated if it isn't cached, after dropping those
+    // written over. None if PC starts with an instruction the interpreter
+    // has to see.
+    fn cached_block(&mut self) -> Option<Rc<[Op]>> {
+        let written = self.ram.take_code_writes();
+        let cache = self.blocks.as_mut()?;
+        for addr in written {
+            cache.invalidate(addr);
+        }
+        if let Some(block) = &cache.blocks[self.pc as usize] {
+            return Some(block.clone());
+        }
+
+        let block: Rc<[Op]> = self.translate(self.pc).into();
+        let (start, end) = (self.pc, block.last()?.next);
+        self.ram.mark_code(start as usize..end as usize);
+        let cache = self.blocks.as_mut()?;
+        for page in start >> 8..=(end - 1) >> 8 {
+            let starts = &mut cache.pages[page as usize];
+            if !starts.contains(&start) {
+                starts.push(start);
+            }
+        }
+        cache.blocks[start as usize] = Some(block.clone());
+        cache.translated += 1;
+        Some(block)
+    }
+
+    // Decodes from `start` to the end of its basic block. Stops short of
+    // an unknown opcode and of wrapping past FFFFH, which the interpreter
+    // deals with.
+    fn translate(&self, start: u16) -> Vec<Op> {
+        let timings = self.timings();
+        let mut ops = Vec::new();
+        let mut addr = start;
+        while ops.len() < MAX_BLOCK {
+            let mut next = addr as usize;
+            let ins = decode_for(self.variant, || {
+                next += 1;
+                self.ram.peek((next - 1) as u16)
+            });
+            let Ok(ins) = ins else { break };
+            if next > 0xffff {
+                break;
+            }
+            let cycles = timings[self.ram.peek(addr) as usize];
+            ops.push(Op { ins, next: next as u16, cycles });
+            addr = next as u16;
+            if ins.ends_block() {
+                break;
+            }
+        }
+        ops
+    }
+}
@@ -0,0 +1,223 @@
+#![allow(unused)]
+
+// Machine-cycle stepping. `Cpu::step_cycle` hands out one bus cycle at a
+// time: the status word the 8080 puts on the data bus at SYNC, the
+// address, the data that went across, and the T-states it lasted.
+//
+// Instructions still execute whole. The first cycle of each runs it and
+// the rest replay what it did on the bus, but `cycles` and the clocked
+// devices advance cycle by cycle, so a device watching either sees them in
+// order. Internal states (the long M1 of INX, the idle cycles of DAD) are
+// counted in the first cycle, and wait states in the cycle that met them.
+// The Z80 personality reports its cycles with the 8080 status word for
+// the same kind of access.
+
+use crate::cpu::{Cpu, Variant, decode_for};
+use crate::dram::Access;
+use crate::error::Error;
+use crate::instruction::Instruction;
+use crate::utils::get_u16;
+
+// Status word bits.
+pub const INTA: u8 = 0x01;
+pub const WO: u8 = 0x02; // active low: high unless writing or output
+pub const STACK: u8 = 0x04;
+pub const HLTA: u8 = 0x08;
+pub const OUT: u8 = 0x10;
+pub const M1: u8 = 0x20;
+pub const INP: u8 = 0x40;
+pub const MEMR: u8 = 0x80;
+
+// The status words of the ten machine cycle types.
+pub const FETCH: u8 = MEMR | M1 | WO;
+pub const MEMORY_READ: u8 = MEMR | WO;
+pub const MEMORY_WRITE: u8 = 0;
+pub const STACK_READ: u8 = MEMR | STACK | WO;
+pub const STACK_WRITE: u8 = STACK;
+pub const INPUT_READ: u8 = INP | WO;
+pub const OUTPUT_WRITE: u8 = OUT;
+pub const INTERRUPT_ACK: u8 = INTA | M1 | WO;
+pub const HALT_ACK: u8 = MEMR | HLTA | WO;
+pub const HALT_INTERRUPT_ACK: u8 = INTA | HLTA | M1 | WO;
+
+// T-states of every cycle but the first.
+const CYCLE_STATES: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineCycle {
+    pub status: u8,
+    pub address: u16,
+    pub data: u8,
+    pub states: u64,
+}
+
+// What an instruction did on the bus besides memory accesses, which the
+// memory trace already has.
+#[derive(Debug, Default)]
+pub struct BusLog {
+    acknowledged: Vec<u8>,
+    // An 8085 TRAP or RST 5.5-7.5 was taken, with no acknowledge.
+    restarted: bool,
+    io: Vec<MachineCycle>,
+}
+
+impl Cpu {
+    // Advances one machine cycle, after giving the bus to HOLD if it is
+    // asserted. Don't mix with `next` part way through an instruction:
+    // `step_instruction_cycles` finishes one.
+    pub fn step_cycle(&mut self) -> Result<MachineCycle, Error> {
+        if self.pending_cycles.is_empty() {
+            self.trace_instruction()?;
+        } else {
+            self.grant_hold();
+        }
+        let cycle = self.pending_cycles.pop_front().unwrap();
+        self.cycles += cycle.states;
+        self.tick();
+        Ok(cycle)
+    }
+
+    // Steps the cycles left of the current instruction, or all of the
+    // next one.
+    pub fn step_instruction_cycles(&mut self) -> Result<Vec<MachineCycle>, Error> {
+        let mut cycles = vec![self.step_cycle()?];
+        while !self.pending_cycles.is_empty() {
+            cycles.push(self.step_cycle()?);
+        }
+        Ok(cycles)
+    }
+
+    // Runs the next instruction and queues its machine cycles, holding
+    // the cycle count back for `step_cycle` to advance.
+    fn trace_instruction(&mut self) -> Result<(), Error> {
+        self.grant_hold();
+        let (start, pc, sp, halted) = (self.cycles, self.pc, self.sp, self.halted);
+        self.bus_log = Some(BusLog::default());
+        let mark = self.ram.begin_trace();
+        let res = self.instruction();
+        let accesses = self.ram.end_trace(mark);
+        let log = self.bus_log.take().unwrap_or_default();
+        res?;
+
+        let mut cycles = Vec::new();
+        let acknowledged = !log.acknowledged.is_empty();
+        let status = if halted { HALT_INTERRUPT_ACK } else { INTERRUPT_ACK };
+        for &data in &log.acknowledged {
+            cycles.push(MachineCycle { status, address: pc, data, states: CYCLE_STATES });
+        }
+        let stack = self.stack_range(pc, sp, acknowledged || log.restarted);
+        // The 8085 skips the high address byte of a jump or call it doesn't
+        // take.
+        let op = self.ram.peek(pc);
+        let short = self.variant == Variant::I8085 && !acknowledged && !log.restarted
+            && (matches!(op & 0xc7, 0xc2 | 0xc4) || matches!(op, 0xdd | 0xfd))
+            && self.pc == pc.wrapping_add(3);
+        let mut fetched = acknowledged;
+        for (i, access) in accesses.iter().enumerate() {
+            if short && *access == Access::Read(pc.wrapping_add(2)) {
+                continue;
+            }
+            let (address, status) = match *access {
+                Access::Read(address) if !fetched && address == pc => {
+                    fetched = true;
+                    (address, FETCH)
+                },
+                Access::Read(address) if stack(address) => (address, STACK_READ),
+                Access::Read(address) => (address, MEMORY_READ),
+                Access::Write(address, _) if stack(address) => (address, STACK_WRITE),
+                Access::Write(address, _) => (address, MEMORY_WRITE),
+            };
+            let data = value_after(&accesses[i + 1..], address).unwrap_or_else(|| self.ram.peek(address));
+            let states = CYCLE_STATES + self.ram.wait_states(address);
+            cycles.push(MachineCycle { status, address, data, states });
+        }
+        cycles.extend(log.io);
+        if self.halted && !halted || cycles.is_empty() {
+            cycles.push(MachineCycle { status: HALT_ACK, address: self.pc, data: 0, states: CYCLE_STATES });
+        }
+
+        // The first cycle takes whatever the others leave.
+        let states = self.cycles - start;
+        let rest: u64 = cycles[1..].iter().map(|cycle| cycle.states).sum();
+        cycles[0].states = states.saturating_sub(rest);
+        self.cycles = start;
+        self.pending_cycles.extend(cycles);
+        Ok(())
+    }
+
+    // Which addresses the instruction reached through SP: the span SP
+    // moved over, or the top of the stack for XTHL and EX (SP),IX/IY,
+    // which leave it alone. Only an interrupt and the instructions that
+    // work the stack count; LXI SP or SPHL just move it.
+    fn stack_range(&self, pc: u16, sp: u16, interrupted: bool) -> impl Fn(u16) -> bool + use<> {
+        let (lo, hi) = (sp.min(self.sp), sp.max(self.sp));
+        let op = self.ram.peek(pc);
+        let prefixed = self.variant == Variant::Z80 && matches!(op, 0xdd | 0xfd);
+        let exchange = !interrupted && (op == 0xe3 || prefixed && self.ram.peek(pc.wrapping_add(1)) == 0xe3);
+        let stack = interrupted || self.works_stack(pc);
+        move |address| {
+            if !stack {
+                false
+            } else if exchange {
+                address.wrapping_sub(sp) < 2
+            } else {
+                address.wrapping_sub(lo) < hi.wrapping_sub(lo)
+            }
+        }
+    }
+
+    // PUSH, POP, calls, returns, RST and XTHL, or the Z80's own forms of
+    // them.
+    fn works_stack(&self, pc: u16) -> bool {
+        use Instruction::*;
+
+        let peek = |offset| self.ram.peek(pc.wrapping_add(offset));
+        if self.variant == Variant::Z80 {
+            match peek(0) {
+                // PUSH, POP and EX (SP) of IX and IY
+                0xdd | 0xfd => return matches!(peek(1), 0xe1 | 0xe3 | 0xe5),
+                // RETN and RETI
+                0xed => return peek(1) & 0xc7 == 0x45,
+                0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0xcb | 0xd9 => return false,
+                _ => (),
+            }
+        }
+        let mut offset = 0;
+        let ins = decode_for(self.variant, || {
+            offset += 1;
+            peek(offset - 1)
+        });
+        matches!(ins, Ok(PUSH(_) | POP(_) | XTHL | RST(_) | RSTV
+            | CALL(..) | CC(..) | CNC(..) | CZ(..) | CNZ(..) | CM(..) | CP(..) | CPE(..) | CPO(..)
+            | RET | RC | RNC | RZ | RNZ | RM | RP | RPE | RPO))
+    }
+
+    pub(crate) fn log_acknowledge(&mut self, bus: &[u8]) {
+        if let Some(log) = &mut self.bus_log {
+            log.acknowledged = bus.to_vec();
+        }
+    }
+
+    pub(crate) fn log_restart(&mut self) {
+        if let Some(log) = &mut self.bus_log {
+            log.restarted = true;
+        }
+    }
+
+    pub(crate) fn log_io(&mut self, status: u8, port: u8, data: u8, waits: u64) {
+        if let Some(log) = &mut self.bus_log {
+            // The port number goes out on both halves of the address bus.
+            let address = get_u16(port, port);
+            log.io.push(MachineCycle { status, address, data, states: CYCLE_STATES + waits });
+        }
+    }
+}
+
+// The byte at `address` once an access is done: what the next write there
+// found, if there is one.
+fn value_after(later: &[Access], address: u16) -> Option<u8> {
+    later.iter().find_map(|access| match *access {
+        Access::Write(a, old) if a == address => Some(old),
+        _ => None,
+    })
+}
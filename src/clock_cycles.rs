@@ -1,5 +1,7 @@
 #![allow(unused)]
 
+// 8080 T-states. Conditional calls and returns are charged as not
+// taken; the CPU adds 6 when they are.
 pub const CLOCK_CYCLES: [u8; 256] = [
     4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5,
     5, 7, 4, 4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 13, 5, 10, 10, 10, 4,
@@ -7,8 +9,23 @@ pub const CLOCK_CYCLES: [u8; 256] = [
     7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, 7, 7, 7, 7,
     7, 7, 7, 7, 5, 5, 5, 5, 5, 5, 7, 5, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4,
     4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
-    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 11, 10, 10, 10, 17, 11, 7, 11, 11, 10, 10,
-    10, 10, 17, 7, 11, 11, 10, 10, 10, 17, 11, 7, 11, 11, 10, 10, 10, 10, 17, 7, 11, 11, 10,
-    10, 18, 17, 11, 7, 11, 11, 5, 10, 5, 17, 17, 7, 11, 11, 10, 10, 4, 17, 11, 7, 11, 11, 5,
-    10, 4, 17, 17, 7, 11,
-];
\ No newline at end of file
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 5, 10, 10, 10, 11, 11, 7, 11, 5, 10, 10,
+    10, 11, 17, 7, 11, 5, 10, 10, 10, 11, 11, 7, 11, 5, 10, 10, 10, 11, 17, 7, 11, 5, 10,
+    10, 18, 11, 11, 7, 11, 5, 5, 10, 5, 11, 17, 7, 11, 5, 10, 10, 4, 11, 11, 7, 11, 5, 5,
+    10, 4, 11, 17, 7, 11,
+];
+
+// 8085 T-states. Conditional jumps, calls and returns (RSTV among them)
+// are charged as not taken; the CPU adds the rest when they are.
+pub const CLOCK_CYCLES_8085: [u8; 256] = [
+    4, 10, 7, 6, 4, 4, 7, 4, 10, 10, 7, 6, 4, 4, 7, 4, 7, 10, 7, 6, 4, 4, 7, 4, 10, 10, 7,
+    6, 4, 4, 7, 4, 4, 10, 16, 6, 4, 4, 7, 4, 10, 10, 16, 6, 4, 4, 7, 4, 4, 10, 13, 6, 10,
+    10, 10, 4, 10, 10, 13, 6, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4,
+    4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4,
+    7, 4, 7, 7, 7, 7, 7, 7, 5, 7, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4,
+    4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4,
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, 6, 10, 7, 10,
+    9, 12, 7, 12, 6, 10, 7, 6, 9, 18, 7, 12, 6, 10, 7, 10, 9, 12, 7, 12, 6, 10, 7,
+    10, 9, 7, 7, 12, 6, 10, 7, 16, 9, 12, 7, 12, 6, 6, 7, 4, 9, 10, 7, 12, 6, 10,
+    7, 4, 9, 12, 7, 12, 6, 6, 7, 4, 9, 7, 7, 12,
+];
@@ -0,0 +1,184 @@
+#![allow(unused)]
+
+use std::io::{self, Write};
+
+use crate::cpu::{Cpu, RAM_SIZE, Variant};
+use crate::disasm::{disassemble, flow_at, hex_bytes, took_interrupt};
+use crate::dram::{Access, Dram};
+use crate::error::Error;
+use crate::instruction::hex8;
+use crate::symbols::Symbols;
+
+const EXECUTED: u8 = 1 << 0;    // an opcode was fetched from here
+const OPERAND: u8 = 1 << 1;     // fetched as part of an instruction
+const READ: u8 = 1 << 2;
+const WRITTEN: u8 = 1 << 3;
+const TAKEN: u8 = 1 << 4;
+const NOT_TAKEN: u8 = 1 << 5;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub bytes: u32,
+    pub executed: u32,
+    pub read: u32,
+    pub written: u32,
+    pub untouched: u32,
+    pub branches: u32,
+    pub both_ways: u32,
+}
+
+pub struct Coverage {
+    marks: Vec<u8>,
+    // Of the CPU stepped, for the listing.
+    variant: Variant,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self {
+            marks: vec![0; RAM_SIZE],
+            variant: Variant::I8080,
+        }
+    }
+
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<(), Error> {
+        let pc = cpu.pc;
+        let sp = cpu.sp;
+        self.variant = cpu.variant;
+        let flow = flow_at(&cpu.ram, pc, cpu.variant);
+
+        let mark = cpu.ram.begin_trace();
+        let res = cpu.next();
+        let accesses = cpu.ram.end_trace(mark);
+        res?;
+
+        // An interrupt taken instead fetches nothing from `pc`.
+        let interrupted = took_interrupt(cpu, pc, sp, flow.len);
+        let len = if interrupted { 0 } else { flow.len };
+        if !interrupted {
+            self.marks[pc as usize] |= EXECUTED;
+        }
+        for i in 1..len {
+            self.marks[pc.wrapping_add(i) as usize] |= OPERAND;
+        }
+        for access in accesses {
+            match access {
+                // Skip the fetch of the instruction itself.
+                Access::Read(addr) if addr.wrapping_sub(pc) < len => (),
+                Access::Read(addr) => self.marks[addr as usize] |= READ,
+                Access::Write(addr, _) => self.marks[addr as usize] |= WRITTEN,
+            }
+        }
+        if flow.conditional && !interrupted {
+            self.marks[pc as usize] |= if cpu.pc == pc.wrapping_add(len) { NOT_TAKEN } else { TAKEN };
+        }
+        Ok(())
+    }
+
+    pub fn executed(&self, addr: u16) -> bool {
+        self.marks[addr as usize] & (EXECUTED | OPERAND) != 0
+    }
+
+    pub fn read(&self, addr: u16) -> bool {
+        self.marks[addr as usize] & READ != 0
+    }
+
+    pub fn written(&self, addr: u16) -> bool {
+        self.marks[addr as usize] & WRITTEN != 0
+    }
+
+    // (taken, not taken) for a conditional branch that was executed.
+    pub fn branch(&self, addr: u16) -> Option<(bool, bool)> {
+        let marks = self.marks[addr as usize];
+        if marks & (TAKEN | NOT_TAKEN) == 0 {
+            None
+        } else {
+            Some((marks & TAKEN != 0, marks & NOT_TAKEN != 0))
+        }
+    }
+
+    pub fn summary(&self, start: u16, end: u16) -> Summary {
+        let mut summary = Summary::default();
+        for addr in start..=end {
+            let marks = self.marks[addr as usize];
+            summary.bytes += 1;
+            if self.executed(addr) {
+                summary.executed += 1;
+            }
+            if self.read(addr) {
+                summary.read += 1;
+            }
+            if self.written(addr) {
+                summary.written += 1;
+            }
+            if marks == 0 {
+                summary.untouched += 1;
+            }
+            if let Some((taken, not_taken)) = self.branch(addr) {
+                summary.branches += 1;
+                if taken && not_taken {
+                    summary.both_ways += 1;
+                }
+            }
+        }
+        summary
+    }
+
+    // Tab separated, one line per range.
+    pub fn write_summary(&self, w: &mut impl Write, ranges: &[(u16, u16)]) -> io::Result<()> {
+        writeln!(w, "start\tend\tbytes\texecuted\tread\twritten\tuntouched\tbranches\tboth_ways")?;
+        for &(start, end) in ranges {
+            let s = self.summary(start, end);
+            writeln!(w, "{:04x}\t{:04x}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                start, end, s.bytes, s.executed, s.read, s.written, s.untouched, s.branches, s.both_ways)?;
+        }
+        Ok(())
+    }
+
+    // Disassembly of `start..=end` following the executed instructions.
+    // Data bytes are listed as DB and untouched runs are collapsed.
+    pub fn write_listing(&self, w: &mut impl Write, ram: &Dram, start: u16, end: u16, symbols: &Symbols) -> io::Result<()> {
+        let end = end as u32;
+        let mut addr = start as u32;
+        while addr <= end {
+            let a = addr as u16;
+            let marks = self.marks[addr as usize];
+            if let Some(label) = symbols.name_of(a).filter(|_| marks != 0) {
+                writeln!(w, "{label}:")?;
+            }
+            if marks & EXECUTED != 0 {
+                let (text, len) = disassemble(ram, a, symbols, self.variant);
+                let note = match self.branch(a) {
+                    Some((true, true)) => "; taken both ways",
+                    Some((true, false)) => "; always taken",
+                    Some((false, true)) => "; never taken",
+                    _ => "",
+                };
+                let line = format!("  {:04x}  {:<9} {:<16} {}", a, hex_bytes(ram, a, len), text, note);
+                writeln!(w, "{}", line.trim_end())?;
+                addr += len as u32;
+            } else if marks != 0 {
+                let mut uses = Vec::new();
+                if marks & OPERAND != 0 {
+                    uses.push("operand");
+                }
+                if marks & READ != 0 {
+                    uses.push("read");
+                }
+                if marks & WRITTEN != 0 {
+                    uses.push("written");
+                }
+                writeln!(w, "  {:04x}  {:<9} {:<16} ; {}", a, hex_bytes(ram, a, 1),
+                    format!("DB {}", hex8(ram.load_byte(a))), uses.join(", "))?;
+                addr += 1;
+            } else {
+                let first = addr;
+                while addr <= end && self.marks[addr as usize] == 0 {
+                    addr += 1;
+                }
+                writeln!(w, "; {:04x}-{:04x} not executed ({} bytes)", first, addr - 1, addr - first)?;
+            }
+        }
+        Ok(())
+    }
+}
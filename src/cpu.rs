@@ -2,17 +2,30 @@
 
 use std::arch::x86_64::_SIDD_CMP_EQUAL_ANY;
 use std::fmt::DebugStruct;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
 use std::time;
 
-use crate::device::Device;
-use crate::dram::Dram;
+use crate::blocks::BlockCache;
+use crate::bus::{self, BusLog, MachineCycle};
+use crate::clock_cycles::{CLOCK_CYCLES, CLOCK_CYCLES_8085};
+use crate::dma::BusMaster;
+use crate::device::{Clocked, Device, Port, PortDevice};
+use crate::dram::{Access, Dram};
 use crate::error::Error;
+use crate::flags::{LazyFlags, LAZY_BITS};
+use crate::i8085::Pins;
 use crate::instruction::{Instruction, RegPair, Src};
+use crate::interrupt::InterruptSource;
+use crate::journal::{Entry, Journal, Registers};
 use crate::utils::*;
+use crate::z80;
 
 pub const RAM_SIZE: usize = 65536;
 
-const CLOCK_RATE: u32 = 2_000_000; // 2.0MHz
+pub const CLOCK_RATE: u32 = 2_000_000; // 2.0MHz
 const PORT_NUM: usize = 256;   // i8080 adopts PMIO.
 
 pub const CARRY_BIT: u8 = 0;
@@ -20,6 +33,82 @@ pub const PARITY_BIT: u8 = 2;
 pub const AUXILIARY_CARRY_BIT: u8 = 4;
 pub const ZERO_BIT: u8 = 6;
 pub const SIGN_BIT: u8 = 7;
+// 8085 only, undocumented: two's complement overflow, and K (X5), set by
+// INX/DCX wrapping around and otherwise sign xor overflow.
+pub const OVERFLOW_BIT: u8 = 1;
+pub const K_BIT: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    #[default]
+    I8080,
+    // RIM/SIM, the extra interrupt and serial pins, its own timings, and
+    // the undocumented instructions and flags.
+    I8085,
+    // The Soviet KR580VM80A, a die-level copy of the 8080A. It runs the
+    // 8080 instruction set as is, timings and undefined opcodes included;
+    // no difference at the instruction level is documented, and the
+    // die-traced model (github.com/1801BM1/vm80a) shows none. Kept as its
+    // own variant for the machines built around it.
+    Kr580Vm80a,
+    // The Z80, run by its own executor in z80.rs over the same registers,
+    // memory and ports.
+    Z80,
+}
+
+impl Variant {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "8080" => Some(Self::I8080),
+            "8085" => Some(Self::I8085),
+            "580" | "kr580" | "kr580vm80a" => Some(Self::Kr580Vm80a),
+            "z80" => Some(Self::Z80),
+            _ => None,
+        }
+    }
+}
+
+// What registers and RAM hold at power on. Real parts come up with
+// whatever the cells settle to, and some software notices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerOn {
+    #[default]
+    Zeros,
+    Ones,
+    Random(u64),
+}
+
+impl PowerOn {
+    // `zeros`, `ones` or `random[:seed]`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.split_once(':') {
+            None if name == "zeros" => Some(Self::Zeros),
+            None if name == "ones" => Some(Self::Ones),
+            None if name == "random" => Some(Self::Random(1)),
+            Some(("random", seed)) => seed.parse().ok().map(Self::Random),
+            _ => None,
+        }
+    }
+
+    // The pattern as a stream of bytes.
+    fn bytes(self) -> impl FnMut() -> u8 {
+        // xorshift64; a zero state would stay zero.
+        let mut state = match self {
+            Self::Random(seed) => seed.max(1),
+            _ => 0,
+        };
+        move || match self {
+            Self::Zeros => 0,
+            Self::Ones => 0xff,
+            Self::Random(_) => {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            },
+        }
+    }
+}
 
 pub struct Cpu {
     pub a: u8,  // accumulator
@@ -34,10 +123,25 @@ pub struct Cpu {
     pub sp: u16,
     pub pc: u16,
     pub halted: bool,
-    pub flag: u8,
+    // S, Z, P and AC are stale while `lazy` holds a result; read through
+    // `flags` or `get_flag`.
+    pub(crate) flag: u8,
+    lazy: Option<LazyFlags>,
     pub inte: bool,
+    pub cycles: u64,    // T-states elapsed
     pub ram: Dram,
     pub devices: [Option<Box<dyn Device>>; PORT_NUM],
+    pub clocked: Vec<Rc<RefCell<dyn Clocked>>>,
+    pub intr: Option<Rc<RefCell<dyn InterruptSource>>>,
+    pub hold: Option<Rc<RefCell<dyn BusMaster>>>,
+    pub variant: Variant,
+    pub pins: Rc<RefCell<Pins>>,   // 8085 only
+    pub z80: z80::Regs,            // Z80 only
+    pub(crate) ei_delay: bool,     // EI takes effect after the next instruction
+    pub(crate) bus_log: Option<BusLog>,
+    pub(crate) pending_cycles: VecDeque<MachineCycle>,
+    pub journal: Option<Journal>,
+    pub blocks: Option<BlockCache>,
 }
 
 // interface
@@ -56,9 +160,69 @@ impl Cpu {
             halted: false,
             ram: Dram::new(),
             devices: [const { None }; PORT_NUM],
+            clocked: Vec::new(),
+            intr: None,
+            hold: None,
+            variant: Variant::I8080,
+            pins: Rc::new(RefCell::new(Pins::new())),
+            z80: z80::Regs::default(),
+            ei_delay: false,
+            bus_log: None,
+            pending_cycles: VecDeque::new(),
             flag: 2, // 0bsz0c0p1c
+            lazy: None,
             inte: false, 
+            cycles: 0,
+            journal: None,
+            blocks: None,
+        }
+    }
+
+    pub fn with_variant(variant: Variant) -> Self {
+        Self { variant, ..Self::new() }
+    }
+
+    // Pulls RESET: PC goes to 0, interrupts are disabled and HLT is left.
+    // The other registers keep what they held.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+        self.inte = false;
+        self.ei_delay = false;
+        self.halted = false;
+        self.pending_cycles.clear();
+        match self.variant {
+            Variant::I8085 => self.pins.borrow_mut().reset(),
+            Variant::Z80 => {
+                self.z80.iff2 = false;
+                self.z80.im = 0;
+                self.z80.i = 0;
+                self.z80.r = 0;
+            },
+            _ => (),
+        }
+    }
+
+    // Fills the registers and all of RAM with `pattern`, then resets. Load
+    // programs after this.
+    pub fn power_on(&mut self, pattern: PowerOn) {
+        let mut next = pattern.bytes();
+        let mut word = || get_u16(next(), next());
+        (self.a, self.flag) = split_u16(word());
+        self.lazy = None;
+        (self.b, self.c) = split_u16(word());
+        (self.d, self.e) = split_u16(word());
+        (self.h, self.l) = split_u16(word());
+        self.sp = word();
+        if self.variant == Variant::Z80 {
+            let z80 = &mut self.z80;
+            (z80.af_, z80.bc_, z80.de_, z80.hl_) = (word(), word(), word(), word());
+            (z80.ix, z80.iy) = (word(), word());
+        } else {
+            // Bits 1, 3 and 5 of the 8080's flags are wired.
+            self.flag = self.flag & 0xd7 | 0x02;
         }
+        self.ram.fill_with(next);
+        self.reset();
     }
 
     pub fn load(mut self, data: &[u8]) -> Self {
@@ -74,220 +238,313 @@ impl Cpu {
             if self.pc as usize >= RAM_SIZE {
                 return Err(Error::PcOutofRange);
             }
-            let instruc = self.fetch()?;
-            self.excecute(instruc)?;
+            self.next()?;
         }
     }
 
     pub fn next(&mut self) -> Result<(), Error> {
-        let ins = self.fetch()?;
-        self.excecute(ins)?;
-        Ok(())
+        self.grant_hold();
+        let res = self.instruction();
+        self.tick();
+        res
+    }
+
+    // One instruction, or an interrupt taken in its place, with the wait
+    // states its memory accesses ran into.
+    pub(crate) fn instruction(&mut self) -> Result<(), Error> {
+        // Whatever looked at memory in between (hooks, the debugger) did
+        // not hold up the CPU.
+        self.ram.take_wait_states();
+        let res = self.dispatch();
+        self.cycles += self.ram.take_wait_states();
+        res
+    }
+
+    fn dispatch(&mut self) -> Result<(), Error> {
+        if self.variant == Variant::Z80 {
+            return self.z80_step();
+        }
+        if let Some(vector) = self.internal_interrupt() {
+            return self.restart(vector);
+        }
+        match self.acknowledge() {
+            Some(bus) => self.interrupt(&bus).map(|_| ()),
+            // Halted, the CPU idles until an interrupt comes.
+            None if self.halted => {
+                self.ei_delay = false;
+                self.cycles += 4;
+                Ok(())
+            },
+            None => {
+                self.cycles += self.timings()[self.ram.peek(self.pc) as usize] as u64;
+                self.journaled(|cpu| {
+                    cpu.ei_delay = false;
+                    let ins = cpu.fetch()?;
+                    cpu.excecute(ins)
+                })
+            },
+        }
+    }
+
+    pub(crate) fn tick(&self) {
+        for device in &self.clocked {
+            device.borrow_mut().tick(self.cycles);
+        }
+    }
+
+    // Gives the bus away for as long as HOLD is asserted. `next` samples
+    // HOLD between instructions, `step_cycle` between machine cycles; the
+    // stolen cycles still count, and clocked devices keep time meanwhile.
+    pub(crate) fn grant_hold(&mut self) {
+        let Some(master) = self.hold.clone() else { return };
+        while master.borrow().hold() {
+            self.cycles += master.borrow_mut().bus_cycle(&mut self.ram);
+            self.cycles += self.ram.take_wait_states();
+            self.tick();
+        }
+    }
+
+    // Connects the HOLD input, sampled before every instruction, or every
+    // machine cycle when stepping by cycle.
+    pub fn connect_hold<M: BusMaster + 'static>(&mut self, master: &Rc<RefCell<M>>) {
+        self.hold = Some(master.clone());
+    }
+
+    // Accepts an interrupt if they are enabled, executing the instruction
+    // the interrupting device puts on the bus (normally RST n). Returns
+    // whether it was taken.
+    pub fn interrupt(&mut self, bus: &[u8]) -> Result<bool, Error> {
+        if !self.inte {
+            return Ok(false);
+        }
+        self.inte = false;
+        self.halted = false;
+        self.cycles += self.timings()[bus[0] as usize] as u64;
+        let mut bytes = bus.iter().copied();
+        let variant = self.variant;
+        self.journaled(|cpu| {
+            let ins = decode_for(variant, || bytes.next().unwrap_or(0))?;
+            cpu.excecute(ins)
+        })?;
+        Ok(true)
+    }
+
+    pub fn timings(&self) -> &'static [u8; 256] {
+        match self.variant {
+            // The Z80 counts its own; this only serves interrupt mode 0.
+            Variant::I8080 | Variant::Kr580Vm80a | Variant::Z80 => &CLOCK_CYCLES,
+            Variant::I8085 => &CLOCK_CYCLES_8085,
+        }
+    }
+
+    // An 8085 TRAP or RST 5.5-7.5 to take now, accepted.
+    fn internal_interrupt(&mut self) -> Option<u16> {
+        if self.variant != Variant::I8085 {
+            return None;
+        }
+        let vector = self.pins.borrow().pending(self.inte && !self.ei_delay)?;
+        self.pins.borrow_mut().accept(vector, self.inte);
+        Some(vector)
+    }
+
+    // Calls `vector` the way the 8085 does for its own interrupt inputs.
+    fn restart(&mut self, vector: u16) -> Result<(), Error> {
+        self.log_restart();
+        self.inte = false;
+        self.halted = false;
+        self.cycles += 12;
+        self.journaled(|cpu| {
+            cpu.ei_delay = false;
+            cpu.push(cpu.pc);
+            cpu.pc = vector;
+            Ok(())
+        })
+    }
+
+    // Connects the INT input, polled before every instruction.
+    pub fn connect_interrupts<S: InterruptSource + 'static>(&mut self, source: &Rc<RefCell<S>>) {
+        self.intr = Some(source.clone());
+    }
+
+    // The bus contents for a pending interrupt request, if one can be
+    // taken now. The instruction that runs instead clears `ei_delay`, inside
+    // the journal so that stepping back restores it.
+    pub(crate) fn acknowledge(&mut self) -> Option<Vec<u8>> {
+        if self.ei_delay || !self.inte {
+            return None;
+        }
+        let source = self.intr.as_ref()?;
+        if !source.borrow().requested() {
+            return None;
+        }
+        let bus = source.borrow_mut().acknowledge();
+        self.log_acknowledge(&bus);
+        Some(bus)
+    }
+
+    // Plugs a multi-port device into each of `ports`.
+    pub fn attach<D: PortDevice + 'static>(&mut self, ports: impl IntoIterator<Item = u8>, device: &Rc<RefCell<D>>) {
+        for port in ports {
+            self.devices[port as usize] = Some(Box::new(Port::new(device.clone(), port)));
+        }
+    }
+
+    // Has `device` follow the cycle count.
+    pub fn clock<D: Clocked + 'static>(&mut self, device: &Rc<RefCell<D>>) {
+        self.clocked.push(device.clone());
+    }
+
+    // Runs `f`, adding what it did to the journal when recording.
+    pub(crate) fn journaled(&mut self, f: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+        if self.journal.is_none() {
+            return f(self);
+        }
+
+        let regs = self.registers();
+        let mark = self.ram.begin_trace();
+        let res = f(self);
+        let writes = self.ram.end_trace(mark).into_iter()
+            .filter_map(|access| match access {
+                Access::Write(addr, old) => Some((addr, old)),
+                Access::Read(_) => None,
+            })
+            .collect();
+        if let (Ok(()), Some(journal)) = (&res, &mut self.journal) {
+            journal.push(Entry { regs, writes });
+        }
+        res
+    }
+
+    // Keep the last `capacity` instructions so they can be undone.
+    pub fn record(&mut self, capacity: usize) {
+        self.journal = Some(Journal::new(capacity));
+    }
+
+    // Undo the most recently recorded instruction. Port I/O is not undone.
+    pub fn step_back(&mut self) -> Option<Entry> {
+        let entry = self.journal.as_mut()?.pop()?;
+        for &(addr, byte) in entry.writes.iter().rev() {
+            self.ram.save_byte(addr, byte);
+        }
+        self.set_registers(entry.regs);
+        Some(entry)
+    }
+
+    // The flag byte as PUSH PSW would store it.
+    pub fn flags(&self) -> u8 {
+        match self.lazy {
+            Some(lazy) => self.flag & !LAZY_BITS | lazy.bits(),
+            None => self.flag,
+        }
+    }
+
+    pub fn set_flag_byte(&mut self, flag: u8) {
+        self.flag = flag;
+        self.lazy = None;
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.a,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+            flag: self.flags(),
+            halted: self.halted,
+            inte: self.inte,
+            ei_delay: self.ei_delay,
+            cycles: self.cycles,
+            z80: self.z80,
+        }
+    }
+
+    pub fn set_registers(&mut self, regs: Registers) {
+        self.a = regs.a;
+        self.b = regs.b;
+        self.c = regs.c;
+        self.d = regs.d;
+        self.e = regs.e;
+        self.h = regs.h;
+        self.l = regs.l;
+        self.sp = regs.sp;
+        self.pc = regs.pc;
+        self.set_flag_byte(regs.flag);
+        self.halted = regs.halted;
+        self.inte = regs.inte;
+        self.ei_delay = regs.ei_delay;
+        self.cycles = regs.cycles;
+        self.z80 = regs.z80;
     }
 
     pub fn test(&mut self) -> Result<(), Error> {
+        self.run_cpm_with(|cpu| cpu.next())
+    }
+
+    // Runs a CP/M program, calling `step` to execute each instruction.
+    pub fn run_cpm_with(&mut self, mut step: impl FnMut(&mut Cpu) -> Result<(), Error>) -> Result<(), Error> {
         println!("*******************");
-        self.ram.save_byte(0x0005, 0xc9);
-        // Because tests used the pseudo instruction ORG 0x0100
-        self.pc = 0x0100;
+        self.prepare_cpm();
         loop {
             if self.halted {
                 break Ok(());
             }
-            let ins = self.fetch()?;
-            //println!("{:?}", ins);
-            self.excecute(ins)?;
-            if self.pc == 0x05 {
-                if self.c == 0x09 {
-                    let mut a = self.get_de_addr();
-                    loop {
-                        let c = self.ram.load_byte(a);
-                        if c as char == '$' {
-                            break;
-                        } else {
-                            a += 1;
-                        }
-                        print!("{}", c as char);
-                    }
-                }
-                if self.c == 0x02 {
-                    print!("{}", self.e as char);
-                }
-            }
-            if self.pc == 0x00 {
-                println!("");
-                println!("");
+            step(self)?;
+            if self.cpm_hook(&mut io::stdout()) {
+                println!();
+                println!();
                 break Ok(());
             }
         }
     }
 
+    // Minimal CP/M environment: BDOS entry at 0x0005 is a RET, and the
+    // program starts at 0x0100.
+    pub fn prepare_cpm(&mut self) {
+        self.ram.save_byte(0x0005, 0xc9);
+        // Because tests used the pseudo instruction ORG 0x0100
+        self.pc = 0x0100;
+    }
 
+    // Services BDOS console calls after a step, writing to `console`.
+    // Returns true once the program has warm booted (jumped to 0x0000).
+    pub fn cpm_hook(&mut self, console: &mut dyn Write) -> bool {
+        if self.pc == 0x05 {
+            if self.c == 0x09 {
+                let mut a = self.get_de_addr();
+                loop {
+                    let c = self.ram.load_byte(a);
+                    if c as char == '$' {
+                        break;
+                    } else {
+                        a += 1;
+                    }
+                    write!(console, "{}", c as char).ok();
+                }
+            }
+            if self.c == 0x02 {
+                write!(console, "{}", self.e as char).ok();
+            }
+            console.flush().ok();
+        }
+        self.pc == 0x00
+    }
 }
 
 // utils
 impl Cpu {
+    // An unknown opcode comes back as the error, for the caller to report:
+    // stdout may be carrying a debugger protocol.
     fn fetch(&mut self) -> Result<Instruction, Error> {
-        use Instruction::*;
-
-        let first_byte = self.ram.load_byte(self.pc);
-        self.pc += 1;
-
-    
-        match first_byte {
-            0 => Ok(NOP),
-
-            0b00111111 => Ok(CMC),
-            0b00110111 => Ok(STC),
-
-            _ if bitmatch(first_byte, 0b00000100, 0b11000111) => 
-            Ok(INR(idx2src((first_byte & 0b00111000) >> 3))),
-            _ if bitmatch(first_byte, 0b00000101, 0b11000111) =>
-            Ok(DCR(idx2src((first_byte & 0b00111000) >> 3))),
-            
-            0b00101111 => Ok(CMA),
-            0b00100111 => Ok(DAA),
-
-            _ if bitmatch(first_byte, 0b01000000, 0b11000000) => {
-                let dst = idx2src((first_byte & 0b00111000) >> 3);
-                let src = idx2src(first_byte & 0b00000111);
-                Ok(MOV(dst, src))
-            },
-            _ if bitmatch(first_byte, 0b00000010, 0b11100111) => {
-                let pair = idx2rp_psw((first_byte & 0b00010000) >> 4);
-                if bittest(first_byte, 3) {
-                    Ok(LDAX(pair))
-                } else {
-                    Ok(SATX(pair))
-                }
-            },
-
-            _ if bitmatch(first_byte, 0b10000000, 0b11000000) => {
-                let op = (first_byte & 0b00111000) >> 3;
-                let reg = idx2src(first_byte & 0b00000111);
-                Ok(match op {
-                    0 => ADD(reg),
-                    1 => ADC(reg),
-                    2 => SUB(reg),
-                    3 => SBB(reg),
-                    4 => ANA(reg),
-                    5 => XRA(reg),
-                    6 => ORA(reg),
-                    7 => CMP(reg),
-                    _ => unreachable!(),
-                })
-            },
-
-
-            0b00000111 => Ok(RLC),
-            0b00001111 => Ok(RRC),
-            0b00010111 => Ok(RAL),
-            0b00011111 => Ok(RAR),
-
-            _ if bitmatch(first_byte, 0b11000101, 0b11001111) => {
-                let rp = idx2rp_psw((first_byte & 0b00110000) >> 4);
-                Ok(PUSH(rp))
-            },
-            _ if bitmatch(first_byte, 0b11000001, 0b11001111) => {
-                let rp = idx2rp_psw((first_byte & 0b00110000) >> 4);
-                Ok(POP(rp))
-            },
-            _ if bitmatch(first_byte, 0b00001001, 0b11001111) => {
-                let rp = idx2rp_sp((first_byte & 0b00110000) >> 4);
-                Ok(DAD(rp))
-            },
-            _ if bitmatch(first_byte, 0b00000011, 0b11001111) => {
-                let rp = idx2rp_sp((first_byte & 0b00110000) >> 4);
-                Ok(INX(rp))
-            }, 
-            _ if bitmatch(first_byte, 0b00001011, 0b11001111) => {
-                let rp = idx2rp_sp((first_byte & 0b00110000) >> 4);
-                Ok(DCX(rp))
-            },
-            _ if bitmatch(first_byte, 0b11101011, 255) =>
-            Ok(XCHG),
-            _ if bitmatch(first_byte, 0b11100011, 255) =>
-            Ok(XTHL),
-            _ if bitmatch(first_byte, 0b11111001, 255) =>
-            Ok(SPHL),
-
-            _ if bitmatch(first_byte, 0b00000001, 0b11001111) => {
-                let low_data = self.next_byte();
-                let high_data = self.next_byte();
-                let rp = (first_byte & 0b00110000) >> 4;
-                Ok(LXI(idx2rp_sp(rp), low_data, high_data))
-            },
-            _ if bitmatch(first_byte, 0b00000110, 0b11000111) => {
-                let reg = (first_byte & 0b00111000) >> 3;
-                let data = self.next_byte();
-                Ok(MVI(idx2src(reg), data))
-            },
-
-            0b11000110 => Ok(ADI(self.next_byte())),
-            0b11001110 => Ok(ACI(self.next_byte())),
-            0b11010110 => Ok(SUI(self.next_byte())),
-            0b11011110 => Ok(SBI(self.next_byte())),
-            0b11100110 => Ok(ANI(self.next_byte())),
-            0b11101110 => Ok(XRI(self.next_byte())),
-            0b11110110 => Ok(ORI(self.next_byte())),
-            0b11111110 => Ok(CPI(self.next_byte())),
-
-            0b00110010 => Ok(STA(self.next_byte(), self.next_byte())),
-            0b00111010 => Ok(LDA(self.next_byte(), self.next_byte())),
-            0b00100010 => Ok(SHLD(self.next_byte(), self.next_byte())),
-            0b00101010 => Ok(LHLD(self.next_byte(), self.next_byte())),
-
-            0b11101001 => Ok(PCHL),
-            0b11000011 => Ok(JMP(self.next_byte(), self.next_byte())),
-            0b11011010 => Ok(JC(self.next_byte(), self.next_byte())),
-            0b11010010 => Ok(JNC(self.next_byte(), self.next_byte())),
-            0b11001010 => Ok(JZ(self.next_byte(), self.next_byte())),
-            0b11000010 => Ok(JNZ(self.next_byte(), self.next_byte())),
-            0b11111010 => Ok(JM(self.next_byte(), self.next_byte())),
-            0b11110010 => Ok(JP(self.next_byte(), self.next_byte())),
-            0b11101010 => Ok(JPE(self.next_byte(), self.next_byte())),
-            0b11100010 => Ok(JPO(self.next_byte(), self.next_byte())),
-
-            0b11001101 => Ok(CALL(self.next_byte(), self.next_byte())),
-            0b11011100 => Ok(CC(self.next_byte(), self.next_byte())),
-            0b11010100 => Ok(CNC(self.next_byte(), self.next_byte())),
-            0b11001100 => Ok(CZ(self.next_byte(), self.next_byte())),
-            0b11000100 => Ok(CNZ(self.next_byte(), self.next_byte())),
-            0b11111100 => Ok(CM(self.next_byte(), self.next_byte())),
-            0b11110100 => Ok(CP(self.next_byte(), self.next_byte())),
-            0b11101100 => Ok(CPE(self.next_byte(), self.next_byte())),
-            0b11100100 => Ok(CPO(self.next_byte(), self.next_byte())),
-
-            0b11001001 => Ok(RET),
-            0b11011000 => Ok(RC),
-            0b11010000 => Ok(RNC),
-            0b11001000 => Ok(RZ),
-            0b11000000 => Ok(RNZ),
-            0b11111000 => Ok(RM),
-            0b11110000 => Ok(RP),
-            0b11101000 => Ok(RPE),
-            0b11100000 => Ok(RPO),
-
-            _ if bitmatch(first_byte, 0b11000111, 0b11000111) => {
-                let exp = (first_byte & 0b00111000) >> 3;
-                Ok(RST(exp))
-            },
-            0b11111011 => Ok(EI),
-            0b11110011 => Ok(DI),
-
-            0b11011011 => Ok(IN(self.next_byte())),
-            0b11010011 => Ok(OUT(self.next_byte())),
-
-            0b01110110 => Ok(HLT),
-
-            _ => {
-                println!("unknown ins");
-                Err(Error::UnknownOpcode(first_byte))
-            }
-        }
+        let variant = self.variant;
+        decode_for(variant, || self.next_byte())
     }
 
-    fn excecute(&mut self, instruction: Instruction) -> Result<(), Error> {
+    pub(crate) fn excecute(&mut self, instruction: Instruction) -> Result<(), Error> {
         use Instruction::*;
         
         //println!(
@@ -312,15 +569,19 @@ impl Cpu {
             STC => self.set_flag(CARRY_BIT, true),
             INR(src) => {
                 let src = self.get_src(src);
-                let (res, _, parity, auxiliary_carry, zero, sign) = flagged_add(*src, 1);
-                *src = res;
-                self.set_flags(None, Some(parity), Some(auxiliary_carry), Some(zero), Some(sign));
+                let lhs = *src;
+                let (lazy, _) = LazyFlags::arith(lhs, 1, false, false);
+                *src = lazy.res;
+                self.lazy = Some(lazy);
+                self.set_overflow(lhs, 1, false, false);
             },
             DCR(src) => {
                 let src = self.get_src(src);
-                let (res, _, parity, auxiliary_carry, zero, sign) = flagged_sub(*src, 1);
-                *src = res;
-                self.set_flags(None, Some(parity), Some(auxiliary_carry), Some(zero), Some(sign));
+                let lhs = *src;
+                let (lazy, _) = LazyFlags::arith(lhs, 1, false, true);
+                *src = lazy.res;
+                self.lazy = Some(lazy);
+                self.set_overflow(lhs, 1, false, true);
             },
             CMA => self.a = !self.a,
             DAA => {
@@ -340,16 +601,14 @@ impl Cpu {
                     c = true;
                 }
 
-                let (res, carry, parity, aux, zero, sign) = flagged_add(self.a, a);
-                self.a = res;
-                self.set_flags(Some(carry), Some(parity), Some(aux), Some(zero), Some(sign));
-
+                let (lazy, _) = LazyFlags::arith(self.a, a, false, false);
+                self.a = lazy.res;
+                self.lazy = Some(lazy);
                 self.set_flag(CARRY_BIT, c);
             },
             MOV(dst, src) => {
-                let src = *self.get_src(src);
-                let dst = self.get_src(dst);
-                *dst = src;
+                let src = self.read_src(src);
+                self.write_src(dst, src);
             },
             SATX(rp) => {
                 match rp {
@@ -365,56 +624,26 @@ impl Cpu {
                     _ => unreachable!(),
                 };
             },
-            ADD(reg) => {
-                let src = *self.get_src(reg);
-                let (res, carry, parity, aux, zero, sign) = flagged_add(self.a, src);
-                self.a = res;
-                self.set_flags(Some(carry), Some(parity), Some(aux), Some(zero), Some(sign));
-            },
-            ADC(reg) => {
-                let a = self.a;
-                let c = u8::from(self.get_flag(CARRY_BIT));
-                let src = (*self.get_src(reg));
-                let (res, _, parity, _, zero, sign) = flagged_add(self.a, src.wrapping_add(c));
-                self.a = res;
-                self.set_flags(Some(u16::from(a) + u16::from(c) + u16::from(src) > 0xff), 
-                Some(parity), Some((a & 0xf) + (src & 0xf) + c > 0xf), 
-                Some(zero), Some(sign));
-            },
-            SUB(reg) => {
-                let src = *self.get_src(reg);
-                let (res, carry, parity, aux, zero, sign) = flagged_sub(self.a, src);
-                self.a = res;
-                self.set_flags(Some(carry), Some(parity), Some(aux), Some(zero), Some(sign));
-            },
-            SBB(reg) => {
-                let a = self.a;
-                let c = u8::from(self.get_flag(CARRY_BIT));
-                let src = *self.get_src(reg);
-                let (res, _, parity, _, zero, sign) = flagged_sub(self.a, src.wrapping_add(c));
-                self.a = res;
-                self.set_flags(Some(u16::from(a) < u16::from(src) + u16::from(c)),
-                Some(parity), Some((a as i8 & 0x0f) - (src as i8 & 0x0f) - (c as i8) >= 0x00),
-                Some(zero), Some(sign));
-            },
+            ADD(reg) => self.a = self.arith(self.a, self.read_src(reg), false, false),
+            ADC(reg) => self.a = self.arith(self.a, self.read_src(reg), self.get_flag(CARRY_BIT), false),
+            SUB(reg) => self.a = self.arith(self.a, self.read_src(reg), false, true),
+            SBB(reg) => self.a = self.arith(self.a, self.read_src(reg), self.get_flag(CARRY_BIT), true),
             ANA(reg) => {
                 let a = self.a;
-                let src = *self.get_src(reg);
+                let src = self.read_src(reg);
                 self.a &= src;
-                self.set_logical_flag();
-                self.set_flag(AUXILIARY_CARRY_BIT, ((a | src) & 0x08) != 0);
+                self.set_logical_flag(self.and_aux(a, src));
             },
             XRA(reg) => {
-                self.a ^= *self.get_src(reg);
-                self.set_logical_flag();
+                self.a ^= self.read_src(reg);
+                self.set_logical_flag(false);
             },
             ORA(reg) => {
-                self.a |= *self.get_src(reg);
-                self.set_logical_flag();
+                self.a |= self.read_src(reg);
+                self.set_logical_flag(false);
             },
             CMP(reg) => {
-                let (_, carry, parity, aux, zero, sign) = flagged_sub(self.a, *self.get_src(reg));
-                self.set_flags(Some(carry), Some(parity), Some(aux), Some(zero), Some(sign));
+                self.arith(self.a, self.read_src(reg), false, true);
             },
             RLC => {
                 let c = bittest(self.a, 7);
@@ -443,7 +672,7 @@ impl Cpu {
                     RegPair::BC => (self.b, self.c),
                     RegPair::DE => (self.d, self.e),
                     RegPair::HL => (self.h, self.l),
-                    RegPair::PSW => (self.a, self.flag),
+                    RegPair::PSW => (self.a, self.flags()),
                     _ => unreachable!(),
                 };
                 self.push(get_u16(b1, b2));
@@ -454,11 +683,19 @@ impl Cpu {
                     RegPair::BC => (&mut self.b, &mut self.c),
                     RegPair::DE => (&mut self.d, &mut self.e),
                     RegPair::HL => (&mut self.h, &mut self.l),
-                    RegPair::PSW => (&mut self.a, &mut self.flag),
+                    RegPair::PSW => {
+                        self.lazy = None;
+                        (&mut self.a, &mut self.flag)
+                    },
                     _ => unreachable!(),
                 };
                 *src1 = hi;
                 *src2 = lo;
+                // Bits 1, 3 and 5 of the flags are wired to 1, 0 and 0; the
+                // 8085 keeps V and K in two of them.
+                if matches!(rp, RegPair::PSW) && self.variant != Variant::I8085 {
+                    self.flag = self.flag & 0xd7 | 0x02;
+                }
             },
             DAD(rp) => {
                 let x = self.get_rp_val(rp);
@@ -468,6 +705,9 @@ impl Cpu {
             },
             INX(rp) => {
                 let x = self.get_rp_val(rp).wrapping_add(1);
+                if self.variant == Variant::I8085 {
+                    self.set_flag(K_BIT, x == 0);
+                }
                 match rp {
                     RegPair::BC => (self.b, self.c) = split_u16(x),
                     RegPair::DE => (self.d, self.e) = split_u16(x),
@@ -478,6 +718,9 @@ impl Cpu {
             },
             DCX(rp) => {
                 let x = self.get_rp_val(rp).wrapping_sub(1);
+                if self.variant == Variant::I8085 {
+                    self.set_flag(K_BIT, x == 0xffff);
+                }
                 match rp {
                     RegPair::BC => (self.b, self.c) = split_u16(x),
                     RegPair::DE => (self.d, self.e) = split_u16(x),
@@ -506,50 +749,26 @@ impl Cpu {
                     _ => unreachable!(),
                 };
             },
-            MVI(src, data) => *self.get_src(src) = data,
-            ADI(data) => {
-                let (res, carry, parity, aux, zero, sign) = flagged_add(self.a, data);
-                self.a = res;
-                self.set_flags(Some(carry), Some(parity), Some(aux), Some(zero), Some(sign));               
-            },
-            ACI(data) => {
-                let a = self.a;
-                let c = u8::from(self.get_flag(CARRY_BIT));
-                let (res, _, parity, _, zero, sign) = flagged_add(self.a, data.wrapping_add(c));
-                self.a = res;
-                self.set_flags(Some(u16::from(a) + u16::from(c) + u16::from(data) > 0xff), 
-                Some(parity), Some((a & 0xf) + (data & 0xf) + c > 0xf), 
-                Some(zero), Some(sign));
-            },
-            SUI(data) => {
-                let (res, carry, parity, aux, zero, sign) = flagged_sub(self.a, data);
-                self.a = res;
-                self.set_flags(Some(carry), Some(parity), Some(aux), Some(zero), Some(sign));
-            },
-            SBI(data) => {
-                let a = self.a;
-                let c = u8::from(self.get_flag(CARRY_BIT));
-                let (res, _, parity, _, zero, sign) = flagged_sub(self.a, data.wrapping_add(c));
-                self.a = res;
-                self.set_flags(Some(u16::from(a) < u16::from(data) + u16::from(c)),
-                Some(parity), Some((a as i8 & 0x0f) - (data as i8 & 0x0f) - (c as i8) >= 0x00),
-                Some(zero), Some(sign));
-            },
+            MVI(src, data) => self.write_src(src, data),
+            ADI(data) => self.a = self.arith(self.a, data, false, false),
+            ACI(data) => self.a = self.arith(self.a, data, self.get_flag(CARRY_BIT), false),
+            SUI(data) => self.a = self.arith(self.a, data, false, true),
+            SBI(data) => self.a = self.arith(self.a, data, self.get_flag(CARRY_BIT), true),
             ANI(data) => {
+                let a = self.a;
                 self.a &= data;
-                self.set_logical_flag();
+                self.set_logical_flag(self.and_aux(a, data));
             },
             XRI(data) => {
                 self.a ^= data;
-                self.set_logical_flag();
+                self.set_logical_flag(false);
             },
             ORI(data) => {
                 self.a |= data;
-                self.set_logical_flag();
+                self.set_logical_flag(false);
             },
             CPI(data) => {
-                let (res, carry, parity, aux, zero, sign) = flagged_sub(self.a, data);
-                self.set_flags(Some(carry), Some(parity), Some(aux), Some(zero), Some(sign));
+                self.arith(self.a, data, false, true);
             },
 
             STA(low_add, hi_add) => self.ram.save_byte(get_u16(hi_add, low_add), self.a),
@@ -559,88 +778,54 @@ impl Cpu {
 
             PCHL => self.pc = get_u16(self.h, self.l),
             JMP(low_add, hi_add) => self.pc = get_u16(hi_add, low_add),
-            JC(low_add, hi_add) => if self.get_flag(CARRY_BIT) { self.pc = get_u16(hi_add, low_add) },
-            JNC(low_add, hi_add) => if !self.get_flag(CARRY_BIT) { self.pc = get_u16(hi_add, low_add) },
-            JZ(low_add, hi_add) => if self.get_flag(ZERO_BIT) { self.pc = get_u16(hi_add, low_add) },
-            JNZ(low_add, hi_add) => if !self.get_flag(ZERO_BIT) {  self.pc = get_u16(hi_add, low_add) },
-            JM(low_add, hi_add) => if self.get_flag(SIGN_BIT) { self.pc = get_u16(hi_add, low_add) },
-            JP(low_add, hi_add) => if !self.get_flag(SIGN_BIT) { self.pc = get_u16(hi_add, low_add) },
-            JPE(low_add, hi_add) => if self.get_flag(PARITY_BIT) { self.pc = get_u16(hi_add, low_add) },
-            JPO(low_add, hi_add) => if !self.get_flag(PARITY_BIT) { self.pc = get_u16(hi_add, low_add) },
+            JC(low_add, hi_add) => self.jump_if(self.get_flag(CARRY_BIT), get_u16(hi_add, low_add)),
+            JNC(low_add, hi_add) => self.jump_if(!self.get_flag(CARRY_BIT), get_u16(hi_add, low_add)),
+            JZ(low_add, hi_add) => self.jump_if(self.get_flag(ZERO_BIT), get_u16(hi_add, low_add)),
+            JNZ(low_add, hi_add) => self.jump_if(!self.get_flag(ZERO_BIT), get_u16(hi_add, low_add)),
+            JM(low_add, hi_add) => self.jump_if(self.get_flag(SIGN_BIT), get_u16(hi_add, low_add)),
+            JP(low_add, hi_add) => self.jump_if(!self.get_flag(SIGN_BIT), get_u16(hi_add, low_add)),
+            JPE(low_add, hi_add) => self.jump_if(self.get_flag(PARITY_BIT), get_u16(hi_add, low_add)),
+            JPO(low_add, hi_add) => self.jump_if(!self.get_flag(PARITY_BIT), get_u16(hi_add, low_add)),
 
             CALL(low_add, hi_add) => {
                 self.push(self.pc);
                 self.pc = get_u16(hi_add, low_add);
             },
-            CC(low_add, hi_add) => {
-                if self.get_flag(CARRY_BIT) {
-                    self.push(self.pc);
-                    self.pc = get_u16(hi_add, low_add);
-                }
-            },
-            CNC(low_add, hi_add) => {
-                if !self.get_flag(CARRY_BIT) {
-                    self.push(self.pc);
-                    self.pc = get_u16(hi_add, low_add);
-                }
-            },    
-            CZ(low_add, hi_add) => {
-                if self.get_flag(ZERO_BIT) {
-                    self.push(self.pc);
-                    self.pc = get_u16(hi_add, low_add);
-                }
-            },  
-            CNZ(low_add, hi_add) => {
-                if !self.get_flag(ZERO_BIT) {
-                    self.push(self.pc);
-                    self.pc = get_u16(hi_add, low_add);
-                }
-            },  
-            CM(low_add, hi_add) => {
-                if self.get_flag(SIGN_BIT) {
-                    self.push(self.pc);
-                    self.pc = get_u16(hi_add, low_add);
-                }
-            },  
-            CP(low_add, hi_add) => {
-                if !self.get_flag(SIGN_BIT) {
-                    self.push(self.pc);
-                    self.pc = get_u16(hi_add, low_add);
-                }
-            },
-            CPE(low_add, hi_add) => {
-                if self.get_flag(PARITY_BIT) {
-                    self.push(self.pc);
-                    self.pc = get_u16(hi_add, low_add);
-                }
-            },
-            CPO(low_add, hi_add) => {
-                if !self.get_flag(PARITY_BIT) {
-                    self.push(self.pc);
-                    self.pc = get_u16(hi_add, low_add);
-                }
-            },
+            CC(low_add, hi_add) => self.call_if(self.get_flag(CARRY_BIT), get_u16(hi_add, low_add)),
+            CNC(low_add, hi_add) => self.call_if(!self.get_flag(CARRY_BIT), get_u16(hi_add, low_add)),
+            CZ(low_add, hi_add) => self.call_if(self.get_flag(ZERO_BIT), get_u16(hi_add, low_add)),
+            CNZ(low_add, hi_add) => self.call_if(!self.get_flag(ZERO_BIT), get_u16(hi_add, low_add)),
+            CM(low_add, hi_add) => self.call_if(self.get_flag(SIGN_BIT), get_u16(hi_add, low_add)),
+            CP(low_add, hi_add) => self.call_if(!self.get_flag(SIGN_BIT), get_u16(hi_add, low_add)),
+            CPE(low_add, hi_add) => self.call_if(self.get_flag(PARITY_BIT), get_u16(hi_add, low_add)),
+            CPO(low_add, hi_add) => self.call_if(!self.get_flag(PARITY_BIT), get_u16(hi_add, low_add)),
 
             RET => self.pc = self.pop(),
-            RC => if self.get_flag(CARRY_BIT) { self.pc = self.pop() },
-            RNC => if !self.get_flag(CARRY_BIT) { self.pc = self.pop() },
-            RZ => if self.get_flag(ZERO_BIT) { self.pc = self.pop() },
-            RNZ => if !self.get_flag(ZERO_BIT) { self.pc = self.pop() },
-            RM => if self.get_flag(SIGN_BIT) { self.pc = self.pop() },
-            RP => if !self.get_flag(SIGN_BIT) { self.pc = self.pop() },
-            RPE => if self.get_flag(PARITY_BIT) { self.pc = self.pop() },
-            RPO => if !self.get_flag(PARITY_BIT) { self.pc = self.pop() },
+            RC => self.ret_if(self.get_flag(CARRY_BIT)),
+            RNC => self.ret_if(!self.get_flag(CARRY_BIT)),
+            RZ => self.ret_if(self.get_flag(ZERO_BIT)),
+            RNZ => self.ret_if(!self.get_flag(ZERO_BIT)),
+            RM => self.ret_if(self.get_flag(SIGN_BIT)),
+            RP => self.ret_if(!self.get_flag(SIGN_BIT)),
+            RPE => self.ret_if(self.get_flag(PARITY_BIT)),
+            RPO => self.ret_if(!self.get_flag(PARITY_BIT)),
 
             RST(exp) => {
                 self.push(self.pc);
                 self.pc = exp.wrapping_mul(8) as u16;
             },
-            EI => self.inte = true,
+            EI => {
+                self.inte = true;
+                self.ei_delay = true;
+            },
             DI => self.inte = false,
 
             IN(device_no) => {
                 if let Some(device) = &mut self.devices[device_no as usize] {
                     self.a = device.read();
+                    let waits = device.wait_states();
+                    self.cycles += waits;
+                    self.log_io(bus::INPUT_READ, device_no, self.a, waits);
                 } else {
                     eprintln!("No such device.");
                     self.halted = true;
@@ -649,6 +834,9 @@ impl Cpu {
             OUT(device_no) => {
                 if let Some(device) = &mut self.devices[device_no as usize] {
                     device.write(self.a);
+                    let waits = device.wait_states();
+                    self.cycles += waits;
+                    self.log_io(bus::OUTPUT_WRITE, device_no, self.a, waits);
                 } else {
                     eprintln!("No such device.");
                     self.halted = true;
@@ -656,11 +844,89 @@ impl Cpu {
             },
 
             HLT => self.halted = true,
+
+            RIM => self.a = self.pins.borrow_mut().rim(self.inte),
+            SIM => self.pins.borrow_mut().sim(self.a),
+            DSUB => {
+                let (hl, bc) = (get_u16(self.h, self.l), get_u16(self.b, self.c));
+                let res = hl.wrapping_sub(bc);
+                let overflow = (hl ^ bc) & (hl ^ res) & 0x8000 != 0;
+                let (_, _, parity, aux, _, _) = flagged_sub(self.l, self.c);
+                (self.h, self.l) = split_u16(res);
+                self.set_flags(Some(hl < bc), Some(parity), Some(aux), Some(res == 0), Some(res & 0x8000 != 0));
+                self.set_flag(OVERFLOW_BIT, overflow);
+                self.set_flag(K_BIT, (res & 0x8000 != 0) != overflow);
+            },
+            ARHL => {
+                let hl = get_u16(self.h, self.l);
+                self.set_flag(CARRY_BIT, hl & 1 != 0);
+                (self.h, self.l) = split_u16(hl & 0x8000 | hl >> 1);
+            },
+            RDEL => {
+                let de = get_u16(self.d, self.e);
+                let c = self.get_flag(CARRY_BIT);
+                self.set_flag(CARRY_BIT, de & 0x8000 != 0);
+                self.set_flag(OVERFLOW_BIT, (de ^ de << 1) & 0x8000 != 0);
+                (self.d, self.e) = split_u16(de << 1 | u16::from(c));
+            },
+            LDHI(data) => (self.d, self.e) = split_u16(get_u16(self.h, self.l).wrapping_add(data as u16)),
+            LDSI(data) => (self.d, self.e) = split_u16(self.sp.wrapping_add(data as u16)),
+            RSTV => {
+                if self.get_flag(OVERFLOW_BIT) {
+                    self.push(self.pc);
+                    self.pc = 0x40;
+                    self.cycles += 6;
+                }
+            },
+            SHLX => self.ram.save_word(self.get_de_addr(), get_u16(self.h, self.l)),
+            LHLX => (self.h, self.l) = split_u16(self.ram.load_word(self.get_de_addr())),
+            JNK(low_add, hi_add) => self.jump_if(!self.get_flag(K_BIT), get_u16(hi_add, low_add)),
+            JK(low_add, hi_add) => self.jump_if(self.get_flag(K_BIT), get_u16(hi_add, low_add)),
         };
 
         Ok(())
     }
 
+    // The timing tables charge conditional jumps, calls and returns as not
+    // taken; these add what taking them costs. Only the 8085 makes a jump
+    // not taken any shorter.
+    fn jump_if(&mut self, condition: bool, addr: u16) {
+        if condition {
+            self.pc = addr;
+            if self.variant == Variant::I8085 {
+                self.cycles += 3;
+            }
+        }
+    }
+
+    fn call_if(&mut self, condition: bool, addr: u16) {
+        if condition {
+            self.push(self.pc);
+            self.pc = addr;
+            self.cycles += if self.variant == Variant::I8085 { 9 } else { 6 };
+        }
+    }
+
+    fn ret_if(&mut self, condition: bool) {
+        if condition {
+            self.pc = self.pop();
+            self.cycles += 6;
+        }
+    }
+
+    // The 8085's V and K after an 8-bit add or subtract.
+    fn set_overflow(&mut self, lhs: u8, rhs: u8, carry: bool, subtract: bool) {
+        if self.variant != Variant::I8085 {
+            return;
+        }
+        // A subtraction adds the complement, borrow inverted.
+        let rhs = if subtract { !rhs } else { rhs };
+        let res = lhs.wrapping_add(rhs).wrapping_add((carry != subtract) as u8);
+        let overflow = (lhs ^ res) & (rhs ^ res) & 0x80 != 0;
+        self.set_flag(OVERFLOW_BIT, overflow);
+        self.set_flag(K_BIT, (res & 0x80 != 0) != overflow);
+    }
+
     fn get_src(&mut self, src: Src) -> &mut u8 {
         match src {
             Src::B => &mut self.b,
@@ -674,50 +940,102 @@ impl Cpu {
         }
     }
 
-    fn push(&mut self, word: u16) {
+    fn read_src(&self, src: Src) -> u8 {
+        match src {
+            Src::B => self.b,
+            Src::C => self.c,
+            Src::D => self.d,
+            Src::E => self.e,
+            Src::H => self.h,
+            Src::L => self.l,
+            Src::A => self.a,
+            Src::Mem => self.ram.load_byte(self.get_hl_addr()),
+        }
+    }
+
+    fn write_src(&mut self, src: Src, val: u8) {
+        match src {
+            Src::Mem => self.ram.save_byte(self.get_hl_addr(), val),
+            _ => *self.get_src(src) = val,
+        }
+    }
+
+    // High byte first, in the order the bus sees the writes.
+    pub(crate) fn push(&mut self, word: u16) {
+        let (hi, lo) = split_u16(word);
         self.sp = self.sp.wrapping_sub(2);
-        self.ram.save_word(self.sp, word);
+        self.ram.save_byte(self.sp.wrapping_add(1), hi);
+        self.ram.save_byte(self.sp, lo);
     }
 
-    fn pop(&mut self) -> u16 {
+    pub(crate) fn pop(&mut self) -> u16 {
         self.sp = self.sp.wrapping_add(2);
         self.ram.load_word(self.sp.wrapping_sub(2))
     }
 
-    fn set_logical_flag(&mut self) {
+    // Adds or subtracts, setting carry and leaving the other flags to be
+    // worked out from the result.
+    fn arith(&mut self, lhs: u8, rhs: u8, carry: bool, subtract: bool) -> u8 {
+        let (lazy, carry_out) = LazyFlags::arith(lhs, rhs, carry, subtract);
+        self.lazy = Some(lazy);
+        self.set_flag(CARRY_BIT, carry_out);
+        self.set_overflow(lhs, rhs, carry, subtract);
+        lazy.res
+    }
+
+    // AC after an AND: bit 3 of either operand on the 8080, always set on
+    // the 8085.
+    fn and_aux(&self, a: u8, operand: u8) -> bool {
+        self.variant == Variant::I8085 || ((a | operand) & 0x08) != 0
+    }
+
+    fn set_logical_flag(&mut self, aux: bool) {
         self.set_flag(CARRY_BIT, false);
-        self.set_flag(PARITY_BIT, self.a.count_ones() % 2 == 0);
-        self.set_flag(AUXILIARY_CARRY_BIT, false);
-        self.set_flag(ZERO_BIT, self.a == 0);
-        self.set_flag(SIGN_BIT, self.a >= 0x80);
+        self.lazy = Some(LazyFlags::logical(self.a, aux));
+        if self.variant == Variant::I8085 {
+            self.set_flag(OVERFLOW_BIT, false);
+            self.set_flag(K_BIT, false);
+        }
     }
 
     fn set_flags(&mut self, carry: Option<bool>, parity: Option<bool>, aux: Option<bool>, zero: Option<bool>, sign: Option<bool>) {
-        carry.map(|carry| bitset(&mut self.flag, CARRY_BIT, carry));
-        parity.map(|parity| bitset(&mut self.flag, PARITY_BIT, parity));
-        aux.map(|aux| bitset(&mut self.flag, AUXILIARY_CARRY_BIT, aux));
-        zero.map(|zero| bitset(&mut self.flag, ZERO_BIT, zero));
-        sign.map(|sign| bitset(&mut self.flag, SIGN_BIT, sign));
+        self.materialize_flags();
+        if let Some(carry) = carry { bitset(&mut self.flag, CARRY_BIT, carry); }
+        if let Some(parity) = parity { bitset(&mut self.flag, PARITY_BIT, parity); }
+        if let Some(aux) = aux { bitset(&mut self.flag, AUXILIARY_CARRY_BIT, aux); }
+        if let Some(zero) = zero { bitset(&mut self.flag, ZERO_BIT, zero); }
+        if let Some(sign) = sign { bitset(&mut self.flag, SIGN_BIT, sign); }
     }
 
     pub(crate) fn set_flag(&mut self, bit: u8, flag: bool) {
+        if LAZY_BITS & 1 << bit != 0 {
+            self.materialize_flags();
+        }
         bitset(&mut self.flag, bit, flag);
     }
 
     pub(crate) fn get_flag(&self, bit: u8) -> bool {
-        let v = (self.flag & (1 << bit)) >> bit;
-        v == 1
+        match self.lazy {
+            Some(lazy) if LAZY_BITS & 1 << bit != 0 => lazy.get(bit),
+            _ => bittest(self.flag, bit),
+        }
+    }
+
+    // Folds a pending result into the flag byte.
+    pub(crate) fn materialize_flags(&mut self) {
+        self.flag = self.flags();
+        self.lazy = None;
     }
 
     pub(crate) fn get_hl_addr(&self) -> u16 {
         get_u16(self.h, self.l)
     }
 
-    fn get_bc_addr(&self) -> u16 {
+    pub(crate) fn get_bc_addr(&self) -> u16 {
         get_u16(self.b, self.c)
     }
 
-    fn get_de_addr(&self) -> u16 {
+    pub(crate) fn get_de_addr(&self) -> u16 {
         get_u16(self.d, self.e)
     }
 
@@ -726,13 +1044,233 @@ impl Cpu {
             RegPair::BC => get_u16(self.b, self.c),
             RegPair::DE => get_u16(self.d, self.e),
             RegPair::HL => get_u16(self.h, self.l),
-            RegPair::PSW => get_u16(self.a, self.flag),
+            RegPair::PSW => get_u16(self.a, self.flags()),
             RegPair::SP => self.sp,
         }
     }
 
-    fn next_byte(&mut self) -> u8 {
+    pub(crate) fn next_byte(&mut self) -> u8 {
         self.pc = self.pc.wrapping_add(1);
         self.ram.load_byte(self.pc - 1)
     }
-}
\ No newline at end of file
+}
+
+// Decodes one instruction of `variant`: the 8085 gives meaning to opcodes
+// the 8080 leaves undefined.
+pub fn decode_for(variant: Variant, mut next: impl FnMut() -> u8) -> Result<Instruction, Error> {
+    use Instruction::*;
+
+    let first_byte = next();
+    if variant == Variant::I8085 {
+        match first_byte {
+            0x20 => return Ok(RIM),
+            0x30 => return Ok(SIM),
+            0x08 => return Ok(DSUB),
+            0x10 => return Ok(ARHL),
+            0x18 => return Ok(RDEL),
+            0x28 => return Ok(LDHI(next())),
+            0x38 => return Ok(LDSI(next())),
+            0xcb => return Ok(RSTV),
+            0xd9 => return Ok(SHLX),
+            0xed => return Ok(LHLX),
+            0xdd => return Ok(JNK(next(), next())),
+            0xfd => return Ok(JK(next(), next())),
+            _ => (),
+        }
+    }
+    let mut first = Some(first_byte);
+    decode(|| first.take().unwrap_or_else(&mut next))
+}
+
+// Decodes one 8080 instruction, pulling its bytes from `next`.
+pub fn decode(mut next: impl FnMut() -> u8) -> Result<Instruction, Error> {
+    let first_byte = next();
+    let ins = DECODE[first_byte as usize].ok_or(Error::UnknownOpcode(first_byte))?;
+    Ok(match ins.operand_len() {
+        0 => ins,
+        1 => ins.with_operands(next(), 0),
+        _ => ins.with_operands(next(), next()),
+    })
+}
+
+// `decode` without the table, walking the rules for every opcode. For
+// the decode benchmark.
+pub fn decode_by_rules(mut next: impl FnMut() -> u8) -> Result<Instruction, Error> {
+    let first_byte = next();
+    let ins = decode_opcode(first_byte).ok_or(Error::UnknownOpcode(first_byte))?;
+    Ok(match ins.operand_len() {
+        0 => ins,
+        1 => ins.with_operands(next(), 0),
+        _ => ins.with_operands(next(), next()),
+    })
+}
+
+static DECODE: [Option<Instruction>; 256] = {
+    let mut table = [None; 256];
+    let mut op = 0;
+    while op < 256 {
+        table[op] = decode_opcode(op as u8);
+        op += 1;
+    }
+    table
+};
+
+// What `first_byte` decodes to, operands left zero: the rules the decode
+// table is built from at compile time.
+const fn decode_opcode(first_byte: u8) -> Option<Instruction> {
+    use Instruction::*;
+
+
+    match first_byte {
+        0 => Some(NOP),
+
+        0b00111111 => Some(CMC),
+        0b00110111 => Some(STC),
+
+        _ if bitmatch(first_byte, 0b00000100, 0b11000111) => 
+        Some(INR(idx2src((first_byte & 0b00111000) >> 3))),
+        _ if bitmatch(first_byte, 0b00000101, 0b11000111) =>
+        Some(DCR(idx2src((first_byte & 0b00111000) >> 3))),
+        
+        0b00101111 => Some(CMA),
+        0b00100111 => Some(DAA),
+
+        // Where MOV M,M would be.
+        0b01110110 => Some(HLT),
+        _ if bitmatch(first_byte, 0b01000000, 0b11000000) => {
+            let dst = idx2src((first_byte & 0b00111000) >> 3);
+            let src = idx2src(first_byte & 0b00000111);
+            Some(MOV(dst, src))
+        },
+        _ if bitmatch(first_byte, 0b00000010, 0b11100111) => {
+            let pair = idx2rp_psw((first_byte & 0b00010000) >> 4);
+            if bittest(first_byte, 3) {
+                Some(LDAX(pair))
+            } else {
+                Some(SATX(pair))
+            }
+        },
+
+        _ if bitmatch(first_byte, 0b10000000, 0b11000000) => {
+            let op = (first_byte & 0b00111000) >> 3;
+            let reg = idx2src(first_byte & 0b00000111);
+            Some(match op {
+                0 => ADD(reg),
+                1 => ADC(reg),
+                2 => SUB(reg),
+                3 => SBB(reg),
+                4 => ANA(reg),
+                5 => XRA(reg),
+                6 => ORA(reg),
+                7 => CMP(reg),
+                _ => unreachable!(),
+            })
+        },
+
+
+        0b00000111 => Some(RLC),
+        0b00001111 => Some(RRC),
+        0b00010111 => Some(RAL),
+        0b00011111 => Some(RAR),
+
+        _ if bitmatch(first_byte, 0b11000101, 0b11001111) => {
+            let rp = idx2rp_psw((first_byte & 0b00110000) >> 4);
+            Some(PUSH(rp))
+        },
+        _ if bitmatch(first_byte, 0b11000001, 0b11001111) => {
+            let rp = idx2rp_psw((first_byte & 0b00110000) >> 4);
+            Some(POP(rp))
+        },
+        _ if bitmatch(first_byte, 0b00001001, 0b11001111) => {
+            let rp = idx2rp_sp((first_byte & 0b00110000) >> 4);
+            Some(DAD(rp))
+        },
+        _ if bitmatch(first_byte, 0b00000011, 0b11001111) => {
+            let rp = idx2rp_sp((first_byte & 0b00110000) >> 4);
+            Some(INX(rp))
+        }, 
+        _ if bitmatch(first_byte, 0b00001011, 0b11001111) => {
+            let rp = idx2rp_sp((first_byte & 0b00110000) >> 4);
+            Some(DCX(rp))
+        },
+        _ if bitmatch(first_byte, 0b11101011, 255) =>
+        Some(XCHG),
+        _ if bitmatch(first_byte, 0b11100011, 255) =>
+        Some(XTHL),
+        _ if bitmatch(first_byte, 0b11111001, 255) =>
+        Some(SPHL),
+
+        _ if bitmatch(first_byte, 0b00000001, 0b11001111) => {
+            let rp = (first_byte & 0b00110000) >> 4;
+            Some(LXI(idx2rp_sp(rp), 0, 0))
+        },
+        _ if bitmatch(first_byte, 0b00000110, 0b11000111) => {
+            let reg = (first_byte & 0b00111000) >> 3;
+            Some(MVI(idx2src(reg), 0))
+        },
+
+        0b11000110 => Some(ADI(0)),
+        0b11001110 => Some(ACI(0)),
+        0b11010110 => Some(SUI(0)),
+        0b11011110 => Some(SBI(0)),
+        0b11100110 => Some(ANI(0)),
+        0b11101110 => Some(XRI(0)),
+        0b11110110 => Some(ORI(0)),
+        0b11111110 => Some(CPI(0)),
+
+        0b00110010 => Some(STA(0, 0)),
+        0b00111010 => Some(LDA(0, 0)),
+        0b00100010 => Some(SHLD(0, 0)),
+        0b00101010 => Some(LHLD(0, 0)),
+
+        0b11101001 => Some(PCHL),
+        0b11000011 => Some(JMP(0, 0)),
+        0b11011010 => Some(JC(0, 0)),
+        0b11010010 => Some(JNC(0, 0)),
+        0b11001010 => Some(JZ(0, 0)),
+        0b11000010 => Some(JNZ(0, 0)),
+        0b11111010 => Some(JM(0, 0)),
+        0b11110010 => Some(JP(0, 0)),
+        0b11101010 => Some(JPE(0, 0)),
+        0b11100010 => Some(JPO(0, 0)),
+
+        0b11001101 => Some(CALL(0, 0)),
+        0b11011100 => Some(CC(0, 0)),
+        0b11010100 => Some(CNC(0, 0)),
+        0b11001100 => Some(CZ(0, 0)),
+        0b11000100 => Some(CNZ(0, 0)),
+        0b11111100 => Some(CM(0, 0)),
+        0b11110100 => Some(CP(0, 0)),
+        0b11101100 => Some(CPE(0, 0)),
+        0b11100100 => Some(CPO(0, 0)),
+
+        0b11001001 => Some(RET),
+        0b11011000 => Some(RC),
+        0b11010000 => Some(RNC),
+        0b11001000 => Some(RZ),
+        0b11000000 => Some(RNZ),
+        0b11111000 => Some(RM),
+        0b11110000 => Some(RP),
+        0b11101000 => Some(RPE),
+        0b11100000 => Some(RPO),
+
+        _ if bitmatch(first_byte, 0b11000111, 0b11000111) => {
+            let exp = (first_byte & 0b00111000) >> 3;
+            Some(RST(exp))
+        },
+        0b11111011 => Some(EI),
+        0b11110011 => Some(DI),
+
+        0b11011011 => Some(IN(0)),
+        0b11010011 => Some(OUT(0)),
+
+        // Undefined, and not decoded fully by the silicon: they run as the
+        // instructions they differ from in a don't-care bit.
+        0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => Some(NOP),
+        0xcb => Some(JMP(0, 0)),
+        0xd9 => Some(RET),
+        0xdd | 0xed | 0xfd => Some(CALL(0, 0)),
+
+        _ => None,
+    }
+}
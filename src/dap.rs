@@ -0,0 +1,655 @@
+#![allow(unused)]
+
+// Debug Adapter Protocol server, spoken over a Content-Length framed
+// stream (normally stdin/stdout).
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use crate::cpu::{AUXILIARY_CARRY_BIT, CARRY_BIT, Cpu, PARITY_BIT, SIGN_BIT, ZERO_BIT};
+use crate::debugger::{Debugger, Stop};
+use crate::disasm::{decode_at, disassemble, hex_bytes};
+use crate::error::Error;
+use crate::json::Value;
+use crate::linemap::LineMap;
+use crate::loader::read_image;
+use crate::utils::{get_u16, parse_hex};
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REF: i64 = 1;
+const FLAGS_REF: i64 = 2;
+
+#[derive(Debug, Clone, Copy)]
+enum Run {
+    Continue,
+    Next,
+    StepIn,
+    StepOut,
+    StepBack,
+    ReverseContinue,
+}
+
+// The program's console output, forwarded as `output` events.
+#[derive(Clone, Default)]
+struct Console(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct DapServer<W: Write> {
+    out: W,
+    seq: i64,
+    requests: Receiver<Value>,
+    // Requests that arrived while the program ran.
+    pending: VecDeque<Value>,
+    dbg: Option<Debugger>,
+    lines: LineMap,
+    source: Option<String>,
+    // By source path, as the client sets them a file at a time.
+    line_breakpoints: HashMap<String, Vec<u16>>,
+    instruction_breakpoints: Vec<u16>,
+    console: Console,
+    stop_on_entry: bool,
+}
+
+// Serves one session until the client disconnects.
+pub fn serve(input: impl Read + Send + 'static, out: impl Write) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || read_messages(input, tx));
+    let mut server = DapServer {
+        out,
+        seq: 0,
+        requests: rx,
+        pending: VecDeque::new(),
+        dbg: None,
+        lines: LineMap::new(),
+        source: None,
+        line_breakpoints: HashMap::new(),
+        instruction_breakpoints: Vec::new(),
+        console: Console::default(),
+        stop_on_entry: false,
+    };
+    server.run()
+}
+
+impl<W: Write> DapServer<W> {
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let request = match self.pending.pop_front() {
+                Some(request) => request,
+                None => match self.requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                },
+            };
+            if !self.handle(&request)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Returns false once the session is over.
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let command = request.get("command").as_str().unwrap_or("");
+        let args = request.get("arguments");
+        match command {
+            "initialize" => self.respond(request, Ok(capabilities()))?,
+            "launch" => {
+                let res = self.launch(args);
+                let ok = res.is_ok();
+                self.respond(request, res.map(|_| Value::Null))?;
+                if ok {
+                    self.event("initialized", Value::Null)?;
+                }
+            },
+            "configurationDone" => {
+                self.respond(request, Ok(Value::Null))?;
+                if self.stop_on_entry {
+                    self.stopped("entry", None)?;
+                } else {
+                    self.resume(Run::Continue)?;
+                }
+            },
+            "setBreakpoints" => {
+                let res = self.set_breakpoints(args);
+                self.respond(request, res)?;
+            },
+            "setInstructionBreakpoints" => {
+                let res = self.set_instruction_breakpoints(args);
+                self.respond(request, res)?;
+            },
+            "threads" => {
+                let thread = Value::object([("id", THREAD_ID.into()), ("name", "8080".into())]);
+                self.respond(request, Ok(Value::object([("threads", vec![thread].into())])))?;
+            },
+            "stackTrace" => {
+                let res = self.stack_trace();
+                self.respond(request, res)?;
+            },
+            "scopes" => {
+                let scope = |name: &str, reference: i64| Value::object([
+                    ("name", name.into()),
+                    ("variablesReference", reference.into()),
+                    ("expensive", false.into()),
+                ]);
+                let scopes = vec![scope("Registers", REGISTERS_REF), scope("Flags", FLAGS_REF)];
+                self.respond(request, Ok(Value::object([("scopes", scopes.into())])))?;
+            },
+            "variables" => {
+                let res = self.variables(args);
+                self.respond(request, res)?;
+            },
+            "evaluate" => {
+                let res = self.evaluate(args);
+                self.respond(request, res)?;
+            },
+            "readMemory" => {
+                let res = self.read_memory(args);
+                self.respond(request, res)?;
+            },
+            "disassemble" => {
+                let res = self.disassemble(args);
+                self.respond(request, res)?;
+            },
+            "continue" | "reverseContinue" => {
+                let body = Value::object([("allThreadsContinued", true.into())]);
+                self.respond(request, Ok(body))?;
+                self.resume(if command == "continue" { Run::Continue } else { Run::ReverseContinue })?;
+            },
+            "next" | "stepIn" | "stepOut" | "stepBack" => {
+                self.respond(request, Ok(Value::Null))?;
+                let run = match command {
+                    "next" => Run::Next,
+                    "stepIn" => Run::StepIn,
+                    "stepOut" => Run::StepOut,
+                    _ => Run::StepBack,
+                };
+                self.resume(run)?;
+            },
+            "pause" => {
+                self.respond(request, Ok(Value::Null))?;
+                self.stopped("pause", None)?;
+            },
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Value::Null))?;
+                return Ok(false);
+            },
+            _ => self.respond(request, Err(format!("unsupported request {command}")))?,
+        }
+        Ok(true)
+    }
+
+    // Arguments: `program` (.COM or .HEX), optional `listing` (.PRN) giving
+    // the line map and labels, `source` (defaults to the listing's .ASM),
+    // `symbols` (list of symbol files) and `stopOnEntry`.
+    fn launch(&mut self, args: &Value) -> Result<(), String> {
+        let program = args.get("program").as_str().ok_or("missing program")?;
+        let segments = read_image(program).map_err(|e| format!("{program}: {e}"))?;
+        let mut cpu = Cpu::new();
+        for (addr, data) in &segments {
+            cpu.ram.load_at(*addr, data);
+        }
+        cpu.prepare_cpm();
+
+        let mut dbg = Debugger::new(cpu);
+        dbg.cpm = true;
+        dbg.console = Box::new(self.console.clone());
+        if let Some(listing) = args.get("listing").as_str() {
+            let text = fs::read(listing).map_err(|e| format!("{listing}: {e}"))?;
+            let text = String::from_utf8_lossy(&text);
+            self.lines = LineMap::parse_listing(&text);
+            dbg.symbols.parse_prn(&text);
+            let source = match args.get("source").as_str() {
+                Some(source) => source.to_string(),
+                None => {
+                    let upper = listing.ends_with("PRN");
+                    let path = Path::new(listing).with_extension(if upper { "ASM" } else { "asm" });
+                    path.to_string_lossy().into_owned()
+                },
+            };
+            self.source = Some(source);
+        }
+        for file in args.get("symbols").as_array() {
+            let file = file.as_str().ok_or("symbols must be paths")?;
+            dbg.symbols.load(file).map_err(|e| format!("{file}: {e}"))?;
+        }
+        self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+        self.dbg = Some(dbg);
+        Ok(())
+    }
+
+    fn resume(&mut self, run: Run) -> io::Result<()> {
+        let Some(dbg) = self.dbg.as_mut() else {
+            return Ok(());
+        };
+        let requests = &self.requests;
+        let pending = &mut self.pending;
+        let mut interrupt = None;
+        let stop = match run {
+            Run::Continue => dbg.cont_with(|| poll(requests, pending, &mut interrupt)),
+            Run::Next => dbg.step_over_with(|| poll(requests, pending, &mut interrupt)),
+            Run::StepIn => dbg.step(),
+            Run::StepOut => dbg.step_out_with(|| poll(requests, pending, &mut interrupt)),
+            Run::StepBack => Ok(dbg.step_back()),
+            Run::ReverseContinue => Ok(dbg.reverse_cont()),
+        };
+
+        self.flush_output()?;
+        match interrupt {
+            Some(request) if request.get("command").as_str() == Some("pause") => {
+                self.respond(&request, Ok(Value::Null))?;
+            },
+            // Ends the session before whatever else was waiting.
+            Some(request) => {
+                self.pending.push_front(request);
+                return Ok(());
+            },
+            None => (),
+        }
+        match stop {
+            Ok(Stop::Step) => self.stopped("step", None),
+            Ok(Stop::Breakpoint(_)) => self.stopped("breakpoint", None),
+            Ok(Stop::Write(_)) => self.stopped("data breakpoint", None),
+            Ok(Stop::Interrupted) => self.stopped("pause", None),
+            Ok(Stop::Halted) => self.stopped("pause", Some("CPU halted".to_string())),
+            Ok(Stop::JournalStart) => self.stopped("step", Some("start of recorded history".to_string())),
+            Ok(Stop::Exited) => {
+                self.event("exited", Value::object([("exitCode", 0.into())]))?;
+                self.event("terminated", Value::Null)
+            },
+            Err(e) => self.stopped("exception", Some(e.to_string())),
+        }
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let dbg = self.dbg.as_mut().ok_or("not launched")?;
+        let path = args.get("source").get("path").as_str().unwrap_or("");
+        let ours = self.source.as_deref().is_some_and(|source| same_file(source, path));
+        let old = self.line_breakpoints.remove(path).unwrap_or_default();
+        let mut addrs = Vec::new();
+
+        let mut results = Vec::new();
+        for bp in args.get("breakpoints").as_array() {
+            let line = bp.get("line").as_i64().unwrap_or(0);
+            let found = self.lines.addr_of(line.max(0) as usize).filter(|_| ours);
+            results.push(match found {
+                Some((line, addr)) => {
+                    dbg.add_breakpoint(addr);
+                    addrs.push(addr);
+                    Value::object([
+                        ("verified", true.into()),
+                        ("line", (line as i64).into()),
+                        ("instructionReference", format!("0x{:04X}", addr).into()),
+                    ])
+                },
+                None => Value::object([
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "no code at this line".into()),
+                ]),
+            });
+        }
+        self.line_breakpoints.insert(path.to_string(), addrs);
+        self.release(old);
+        Ok(Value::object([("breakpoints", results.into())]))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let dbg = self.dbg.as_mut().ok_or("not launched")?;
+        let old = std::mem::take(&mut self.instruction_breakpoints);
+        let mut results = Vec::new();
+        for bp in args.get("breakpoints").as_array() {
+            let reference = bp.get("instructionReference").as_str().and_then(parse_reference);
+            let offset = bp.get("offset").as_i64().unwrap_or(0);
+            match reference {
+                Some(addr) => {
+                    let addr = (addr as i64 + offset) as u16;
+                    dbg.add_breakpoint(addr);
+                    self.instruction_breakpoints.push(addr);
+                    results.push(Value::object([("verified", true.into())]));
+                },
+                None => results.push(Value::object([("verified", false.into())])),
+            }
+        }
+        self.release(old);
+        Ok(Value::object([("breakpoints", results.into())]))
+    }
+
+    // Clears the breakpoints at `addrs` that no source or instruction
+    // breakpoint still asks for.
+    fn release(&mut self, addrs: Vec<u16>) {
+        let Some(dbg) = self.dbg.as_mut() else {
+            return;
+        };
+        for addr in addrs {
+            let wanted = self.line_breakpoints.values().flatten().chain(&self.instruction_breakpoints).any(|&a| a == addr);
+            if !wanted {
+                dbg.remove_breakpoint(addr);
+            }
+        }
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let dbg = self.dbg.as_ref().ok_or("not launched")?;
+        let pc = dbg.cpu.pc;
+        let mut frame = vec![
+            ("id".to_string(), 0.into()),
+            ("name".to_string(), dbg.symbols.describe(pc).into()),
+            ("instructionPointerReference".to_string(), format!("0x{:04X}", pc).into()),
+        ];
+        match (self.lines.line_of(pc), &self.source) {
+            (Some(line), Some(source)) => {
+                let name = Path::new(source).file_name().map(|n| n.to_string_lossy().into_owned());
+                frame.push(("source".to_string(), Value::object([
+                    ("name", name.unwrap_or_default().into()),
+                    ("path", source.as_str().into()),
+                ])));
+                frame.push(("line".to_string(), (line as i64).into()));
+                frame.push(("column".to_string(), 1.into()));
+            },
+            _ => {
+                frame.push(("line".to_string(), 0.into()));
+                frame.push(("column".to_string(), 0.into()));
+            },
+        }
+        Ok(Value::object([
+            ("stackFrames", vec![Value::Object(frame)].into()),
+            ("totalFrames", 1.into()),
+        ]))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let cpu = &self.dbg.as_ref().ok_or("not launched")?.cpu;
+        let byte = |name: &str, v: u8| variable(name, format!("0x{:02X}", v), None);
+        let word = |name: &str, v: u16| variable(name, format!("0x{:04X}", v), Some(v));
+        let flag = |name: &str, bit| variable(name, u8::from(cpu.get_flag(bit)).to_string(), None);
+        let vars = match args.get("variablesReference").as_i64() {
+            Some(REGISTERS_REF) => vec![
+                byte("A", cpu.a),
+                byte("F", cpu.flags()),
+                byte("B", cpu.b),
+                byte("C", cpu.c),
+                byte("D", cpu.d),
+                byte("E", cpu.e),
+                byte("H", cpu.h),
+                byte("L", cpu.l),
+                word("BC", get_u16(cpu.b, cpu.c)),
+                word("DE", get_u16(cpu.d, cpu.e)),
+                word("HL", get_u16(cpu.h, cpu.l)),
+                word("SP", cpu.sp),
+                word("PC", cpu.pc),
+            ],
+            Some(FLAGS_REF) => vec![
+                flag("S", SIGN_BIT),
+                flag("Z", ZERO_BIT),
+                flag("AC", AUXILIARY_CARRY_BIT),
+                flag("P", PARITY_BIT),
+                flag("CY", CARRY_BIT),
+            ],
+            _ => Vec::new(),
+        };
+        Ok(Value::object([("variables", vars.into())]))
+    }
+
+    // Register names, labels and hex addresses.
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let dbg = self.dbg.as_ref().ok_or("not launched")?;
+        let cpu = &dbg.cpu;
+        let expr = args.get("expression").as_str().unwrap_or("").trim();
+        let value = match expr.to_ascii_uppercase().as_str() {
+            "A" => cpu.a as u16,
+            "F" => cpu.flags() as u16,
+            "B" => cpu.b as u16,
+            "C" => cpu.c as u16,
+            "D" => cpu.d as u16,
+            "E" => cpu.e as u16,
+            "H" => cpu.h as u16,
+            "L" => cpu.l as u16,
+            "BC" => get_u16(cpu.b, cpu.c),
+            "DE" => get_u16(cpu.d, cpu.e),
+            "HL" => get_u16(cpu.h, cpu.l),
+            "SP" => cpu.sp,
+            "PC" => cpu.pc,
+            _ => dbg.resolve(expr).ok_or_else(|| format!("cannot evaluate {expr}"))?,
+        };
+        Ok(Value::object([
+            ("result", format!("0x{:04X}", value).into()),
+            ("variablesReference", 0.into()),
+            ("memoryReference", format!("0x{:04X}", value).into()),
+        ]))
+    }
+
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let cpu = &self.dbg.as_ref().ok_or("not launched")?.cpu;
+        let base = args.get("memoryReference").as_str().and_then(parse_reference).ok_or("bad memoryReference")?;
+        let start = base as i64 + args.get("offset").as_i64().unwrap_or(0);
+        let count = args.get("count").as_i64().unwrap_or(0);
+        if !(0..0x10000).contains(&start) {
+            return Ok(Value::object([
+                ("address", format!("0x{:04X}", base).into()),
+                ("unreadableBytes", count.into()),
+            ]));
+        }
+        let end = (start + count.max(0)).min(0x10000);
+        let data = (start..end).map(|a| cpu.ram.load_byte(a as u16)).collect::<Vec<_>>();
+        Ok(Value::object([
+            ("address", format!("0x{:04X}", start).into()),
+            ("data", base64(&data).into()),
+            ("unreadableBytes", (count - (end - start)).max(0).into()),
+        ]))
+    }
+
+    fn disassemble(&self, args: &Value) -> Result<Value, String> {
+        let dbg = self.dbg.as_ref().ok_or("not launched")?;
+        let base = args.get("memoryReference").as_str().and_then(parse_reference).ok_or("bad memoryReference")?;
+        let base = (base as i64 + args.get("offset").as_i64().unwrap_or(0)) as u16;
+        // No more than the address space either way.
+        let skip = args.get("instructionOffset").as_i64().unwrap_or(0).clamp(-0x10000, 0x10000);
+        let count = args.get("instructionCount").as_i64().unwrap_or(0).max(0) as usize;
+
+        // Instructions have no fixed size, so to go backwards decode forward
+        // from a little before and keep the ones that end up before `base`.
+        let mut addr = base;
+        if skip < 0 {
+            let back = (-skip) as usize;
+            let mut a = (base as i64 - 3 * back as i64).max(0) as u16;
+            let mut before = Vec::new();
+            while a < base {
+                before.push(a);
+                a = a.wrapping_add(disassemble(&dbg.cpu.ram, a, &dbg.symbols, dbg.cpu.variant).1);
+            }
+            addr = before.iter().rev().nth(back - 1).or(before.first()).copied().unwrap_or(base);
+        } else {
+            for _ in 0..skip {
+                addr = addr.wrapping_add(disassemble(&dbg.cpu.ram, addr, &dbg.symbols, dbg.cpu.variant).1);
+            }
+        }
+
+        let mut instructions = Vec::new();
+        for _ in 0..count {
+            let (text, len) = disassemble(&dbg.cpu.ram, addr, &dbg.symbols, dbg.cpu.variant);
+            let mut ins = vec![
+                ("address".to_string(), format!("0x{:04X}", addr).into()),
+                ("instructionBytes".to_string(), hex_bytes(&dbg.cpu.ram, addr, len).into()),
+                ("instruction".to_string(), text.into()),
+            ];
+            if let Some(name) = dbg.symbols.name_of(addr) {
+                ins.push(("symbol".to_string(), name.into()));
+            }
+            if let Some(line) = self.lines.line_of(addr) {
+                ins.push(("line".to_string(), (line as i64).into()));
+            }
+            instructions.push(Value::Object(ins));
+            addr = addr.wrapping_add(len);
+        }
+        Ok(Value::object([("instructions", instructions.into())]))
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body = vec![
+            ("reason".to_string(), reason.into()),
+            ("threadId".to_string(), THREAD_ID.into()),
+            ("allThreadsStopped".to_string(), true.into()),
+        ];
+        if let Some(text) = text {
+            body.push(("text".to_string(), text.into()));
+        }
+        self.event("stopped", Value::Object(body))
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        let output = std::mem::take(&mut *self.console.0.borrow_mut());
+        if output.is_empty() {
+            return Ok(());
+        }
+        self.event("output", Value::object([
+            ("category", "stdout".into()),
+            ("output", String::from_utf8_lossy(&output).into_owned().into()),
+        ]))
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut msg = vec![
+            ("type".to_string(), "response".into()),
+            ("request_seq".to_string(), request.get("seq").clone()),
+            ("command".to_string(), request.get("command").clone()),
+            ("success".to_string(), result.is_ok().into()),
+        ];
+        match result {
+            Ok(Value::Null) => (),
+            Ok(body) => msg.push(("body".to_string(), body)),
+            Err(message) => msg.push(("message".to_string(), message.into())),
+        }
+        self.send(msg)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut msg = vec![
+            ("type".to_string(), "event".into()),
+            ("event".to_string(), event.into()),
+        ];
+        if body != Value::Null {
+            msg.push(("body".to_string(), body));
+        }
+        self.send(msg)
+    }
+
+    fn send(&mut self, mut fields: Vec<(String, Value)>) -> io::Result<()> {
+        self.seq += 1;
+        fields.insert(0, ("seq".to_string(), self.seq.into()));
+        let body = Value::Object(fields).to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()
+    }
+}
+
+fn capabilities() -> Value {
+    Value::object([
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsStepBack", true.into()),
+        ("supportsReadMemoryRequest", true.into()),
+        ("supportsDisassembleRequest", true.into()),
+        ("supportsInstructionBreakpoints", true.into()),
+        ("supportsEvaluateForHovers", true.into()),
+        ("supportsTerminateRequest", true.into()),
+    ])
+}
+
+fn variable(name: &str, value: String, memory: Option<u16>) -> Value {
+    let mut fields = vec![
+        ("name".to_string(), name.into()),
+        ("value".to_string(), value.into()),
+        ("variablesReference".to_string(), 0.into()),
+    ];
+    if let Some(addr) = memory {
+        fields.push(("memoryReference".to_string(), format!("0x{:04X}", addr).into()));
+    }
+    Value::Object(fields)
+}
+
+// Checks for requests that came in while running. A pause, disconnect or
+// terminate stops the run; anything else waits until it is over.
+fn poll(requests: &Receiver<Value>, pending: &mut VecDeque<Value>, interrupt: &mut Option<Value>) -> bool {
+    loop {
+        match requests.try_recv() {
+            Ok(request) if matches!(request.get("command").as_str(), Some("pause" | "disconnect" | "terminate")) => {
+                *interrupt = Some(request);
+                return true;
+            },
+            Ok(request) => pending.push_back(request),
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => return true,
+        }
+    }
+}
+
+fn read_messages(input: impl Read, tx: Sender<Value>) {
+    let mut reader = BufReader::new(input);
+    loop {
+        let mut len = None;
+        loop {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => (),
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length:") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+        let Some(len) = len else { continue };
+        let mut body = vec![0; len];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        if let Some(msg) = Value::parse(&String::from_utf8_lossy(&body))
+            && tx.send(msg).is_err() {
+            return;
+        }
+    }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    let name = |p: &str| Path::new(p).file_name().map(|n| n.to_string_lossy().to_ascii_lowercase());
+    name(a).is_some() && name(a) == name(b)
+}
+
+// `0x01A3` as used for memory and instruction references.
+fn parse_reference(s: &str) -> Option<u16> {
+    parse_hex(s)
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
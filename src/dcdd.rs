@@ -0,0 +1,265 @@
+#![allow(unused)]
+
+// MITS 88-DCDD floppy disk controller with up to 16 drives of 8" hard
+// sectored disks: 77 tracks of 32 sectors of 137 bytes.
+//
+// Ports:
+//   08H  out: drive select (bit 7 deselects, bits 0-3 drive)
+//        in:  status, active low
+//   09H  out: control
+//        in:  sector position (bits 1-5 sector, bit 0 sector true)
+//   0AH  read/write data
+//
+// The disk turns at 360 RPM, so a new sector comes under the head every
+// 1/192 second; the sector position follows the CPU's cycle count.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cpu::CLOCK_RATE;
+use crate::device::{Clocked, PortDevice};
+
+pub const PORTS: [u8; 3] = [0x08, 0x09, 0x0a];
+pub const DRIVES: usize = 16;
+pub const TRACKS: usize = 77;
+pub const SECTORS: usize = 32;
+pub const SECTOR_SIZE: usize = 137;
+pub const DISK_SIZE: usize = TRACKS * SECTORS * SECTOR_SIZE;
+
+pub const SECTOR_CYCLES: u64 = CLOCK_RATE as u64 / (6 * SECTORS as u64);
+// Sector true stays asserted for the start of each sector, ahead of the
+// data.
+const SECTOR_TRUE_CYCLES: u64 = SECTOR_CYCLES / 16;
+
+// Status bits, all active low.
+const ENWD: u8 = 0x01;  // ready for the next byte to write
+const MOVE_HEAD: u8 = 0x02;
+const HEAD_STATUS: u8 = 0x04;
+const INTE: u8 = 0x20;
+const TRACK_0: u8 = 0x40;
+const NRDA: u8 = 0x80;  // a byte is ready to read
+
+// Control bits.
+const STEP_IN: u8 = 0x01;
+const STEP_OUT: u8 = 0x02;
+const HEAD_LOAD: u8 = 0x04;
+const HEAD_UNLOAD: u8 = 0x08;
+const INT_ENABLE: u8 = 0x10;
+const INT_DISABLE: u8 = 0x20;
+const WRITE_ENABLE: u8 = 0x80;
+
+#[derive(Debug, Default)]
+struct Drive {
+    image: Vec<u8>,
+    path: Option<PathBuf>,
+    dirty: bool,
+    track: usize,
+    head_loaded: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Dcdd {
+    drives: [Option<Drive>; DRIVES],
+    selected: Option<usize>,
+    interrupts: bool,
+    now: u64,
+    // The sector last seen under the head, and how far into it the
+    // transfer is.
+    sector: usize,
+    offset: usize,
+    writing: bool,
+}
+
+impl Dcdd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Inserts a .dsk image. Short images are padded out to a full disk;
+    // changes go back to the file on `flush`.
+    pub fn insert(&mut self, drive: usize, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut image = fs::read(path)?;
+        if image.len() > DISK_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an 88-DCDD disk image"));
+        }
+        let size = image.len();
+        image.resize(DISK_SIZE, 0xe5);
+        self.insert_image(drive, image);
+        let drive = self.drives[drive].as_mut().unwrap();
+        drive.path = Some(path.to_path_buf());
+        // Write back the padding too, so the file is a proper image.
+        drive.dirty = size != DISK_SIZE;
+        Ok(())
+    }
+
+    // Inserts an in-memory disk, which is not saved anywhere.
+    pub fn insert_image(&mut self, drive: usize, mut image: Vec<u8>) {
+        image.resize(DISK_SIZE, 0xe5);
+        self.drives[drive] = Some(Drive { image, ..Default::default() });
+    }
+
+    pub fn image(&self, drive: usize) -> Option<&[u8]> {
+        self.drives[drive].as_ref().map(|d| d.image.as_slice())
+    }
+
+    // Writes modified images back to their files.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for drive in self.drives.iter_mut().flatten() {
+            if let (true, Some(path)) = (drive.dirty, &drive.path) {
+                fs::write(path, &drive.image)?;
+                drive.dirty = false;
+            }
+        }
+        Ok(())
+    }
+
+    // The sector under the head and whether its start is passing now.
+    fn position(&self) -> (usize, bool) {
+        let turn = self.now % (SECTOR_CYCLES * SECTORS as u64);
+        ((turn / SECTOR_CYCLES) as usize, turn % SECTOR_CYCLES < SECTOR_TRUE_CYCLES)
+    }
+
+    fn drive(&mut self) -> Option<&mut Drive> {
+        self.drives[self.selected?].as_mut()
+    }
+
+    fn status(&mut self) -> u8 {
+        let interrupts = self.interrupts;
+        let writing = self.writing;
+        let offset = self.offset;
+        let Some(drive) = self.drive() else {
+            return 0xff;
+        };
+        let mut status = ENWD | HEAD_STATUS | INTE | TRACK_0 | NRDA;
+        if writing && offset < SECTOR_SIZE {
+            status &= !ENWD;
+        }
+        status &= !MOVE_HEAD;
+        if drive.head_loaded {
+            status &= !HEAD_STATUS;
+            if !writing {
+                status &= !NRDA;
+            }
+        }
+        if interrupts {
+            status &= !INTE;
+        }
+        if drive.track == 0 {
+            status &= !TRACK_0;
+        }
+        status
+    }
+
+    // Reading the position at the start of a sector syncs the transfer
+    // to it.
+    fn sector_position(&mut self) -> u8 {
+        let (sector, start) = self.position();
+        match self.drive() {
+            Some(drive) if drive.head_loaded => (),
+            _ => return 0xff,
+        }
+        if start && !self.writing {
+            self.sector = sector;
+            self.offset = 0;
+        }
+        0xc0 | (sector as u8) << 1 | !start as u8
+    }
+
+    fn control(&mut self, byte: u8) {
+        if byte & INT_ENABLE != 0 {
+            self.interrupts = true;
+        }
+        if byte & INT_DISABLE != 0 {
+            self.interrupts = false;
+        }
+        let Some(drive) = self.drive() else { return };
+        if byte & STEP_IN != 0 && drive.track < TRACKS - 1 {
+            drive.track += 1;
+        }
+        if byte & STEP_OUT != 0 && drive.track > 0 {
+            drive.track -= 1;
+        }
+        if byte & HEAD_LOAD != 0 {
+            drive.head_loaded = true;
+        }
+        if byte & HEAD_UNLOAD != 0 {
+            drive.head_loaded = false;
+        }
+        if byte & (STEP_IN | STEP_OUT) != 0 {
+            self.offset = 0;
+        }
+        if byte & WRITE_ENABLE != 0 {
+            // Writing starts with the sector last synced to.
+            self.writing = true;
+            self.offset = 0;
+        }
+    }
+
+    fn byte_index(&self, track: usize) -> usize {
+        (track * SECTORS + self.sector) * SECTOR_SIZE + self.offset
+    }
+
+    fn read_data(&mut self) -> u8 {
+        if self.offset >= SECTOR_SIZE {
+            return 0;
+        }
+        let Some(track) = self.drive().map(|d| d.track) else { return 0 };
+        let index = self.byte_index(track);
+        self.offset += 1;
+        self.drive().unwrap().image[index]
+    }
+
+    fn write_data(&mut self, byte: u8) {
+        if !self.writing || self.offset >= SECTOR_SIZE {
+            return;
+        }
+        let Some(track) = self.drive().map(|d| d.track) else { return };
+        let index = self.byte_index(track);
+        self.offset += 1;
+        let drive = self.drive().unwrap();
+        drive.image[index] = byte;
+        drive.dirty = true;
+        if self.offset == SECTOR_SIZE {
+            self.writing = false;
+        }
+    }
+}
+
+impl PortDevice for Dcdd {
+    fn read_port(&mut self, port: u8) -> u8 {
+        match port {
+            0x08 => self.status(),
+            0x09 => self.sector_position(),
+            _ => self.read_data(),
+        }
+    }
+
+    fn write_port(&mut self, port: u8, byte: u8) {
+        match port {
+            0x08 => {
+                let drive = (byte & 0x0f) as usize;
+                self.selected = (byte & 0x80 == 0 && self.drives[drive].is_some()).then_some(drive);
+                self.writing = false;
+                self.offset = 0;
+            },
+            0x09 => self.control(byte),
+            _ => self.write_data(byte),
+        }
+    }
+}
+
+impl Clocked for Dcdd {
+    fn tick(&mut self, now: u64) {
+        self.now = now;
+    }
+}
+
+impl Drop for Dcdd {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Error: {e}");
+        }
+    }
+}
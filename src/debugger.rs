@@ -0,0 +1,354 @@
+#![allow(unused)]
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use crate::cpu::Cpu;
+use crate::disasm::{decode_at, disassemble};
+use crate::error::Error;
+use crate::symbols::Symbols;
+use crate::utils::{is_call, is_ret, parse_hex};
+
+const JOURNAL_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Step,
+    Breakpoint(u16),
+    Halted,
+    Exited,
+    // Stopped on the instruction that last wrote this address.
+    Write(u16),
+    // Nothing older left in the journal.
+    JournalStart,
+    // The caller asked to stop.
+    Interrupted,
+}
+
+// Instructions run between calls to the `cont_with` poll.
+const POLL_INTERVAL: usize = 4096;
+
+pub struct Debugger {
+    pub cpu: Cpu,
+    pub cpm: bool,
+    pub symbols: Symbols,
+    // Where the program's CP/M console output goes.
+    pub console: Box<dyn Write>,
+    // Print every instruction as it executes.
+    pub trace: bool,
+    breakpoints: BTreeSet<u16>,
+}
+
+impl Debugger {
+    pub fn new(mut cpu: Cpu) -> Self {
+        if cpu.journal.is_none() {
+            cpu.record(JOURNAL_SIZE);
+        }
+        Self {
+            cpu,
+            cpm: false,
+            symbols: Symbols::new(),
+            console: Box::new(io::stdout()),
+            trace: false,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // A label or a hex address.
+    pub fn resolve(&self, s: &str) -> Option<u16> {
+        self.symbols.lookup(s).or_else(|| parse_hex(s))
+    }
+
+    pub fn step(&mut self) -> Result<Stop, Error> {
+        if self.cpu.halted {
+            return Ok(Stop::Halted);
+        }
+        if self.trace {
+            println!("{}", self.describe_pc());
+        }
+        self.cpu.next()?;
+        if self.cpm && self.cpu.cpm_hook(&mut self.console) {
+            return Ok(Stop::Exited);
+        }
+        if self.cpu.halted {
+            return Ok(Stop::Halted);
+        }
+        Ok(Stop::Step)
+    }
+
+    pub fn cont(&mut self) -> Result<Stop, Error> {
+        self.cont_with(|| false)
+    }
+
+    // Like `cont`, but every so often `poll` is asked whether to stop early.
+    pub fn cont_with(&mut self, mut poll: impl FnMut() -> bool) -> Result<Stop, Error> {
+        let mut steps = 0;
+        loop {
+            match self.step()? {
+                Stop::Step => (),
+                stop => return Ok(stop),
+            }
+            if self.breakpoints.contains(&self.cpu.pc) {
+                return Ok(Stop::Breakpoint(self.cpu.pc));
+            }
+            steps += 1;
+            if steps % POLL_INTERVAL == 0 && poll() {
+                return Ok(Stop::Interrupted);
+            }
+        }
+    }
+
+    // Runs a CALL or RST through to its return; anything else is a single step.
+    pub fn step_over(&mut self) -> Result<Stop, Error> {
+        self.step_over_with(|| false)
+    }
+
+    // Like `step_over`, polling as `cont_with` does.
+    pub fn step_over_with(&mut self, mut poll: impl FnMut() -> bool) -> Result<Stop, Error> {
+        let pc = self.cpu.pc;
+        let sp = self.cpu.sp;
+        if !is_call(self.cpu.ram.load_byte(pc)) {
+            return self.step();
+        }
+        let (_, len) = decode_at(&self.cpu.ram, pc, self.cpu.variant)?;
+        let ret = pc.wrapping_add(len);
+        let mut steps = 0;
+        loop {
+            match self.step()? {
+                Stop::Step => (),
+                stop => return Ok(stop),
+            }
+            if self.cpu.pc == ret && self.cpu.sp == sp {
+                return Ok(Stop::Step);
+            }
+            if self.breakpoints.contains(&self.cpu.pc) {
+                return Ok(Stop::Breakpoint(self.cpu.pc));
+            }
+            steps += 1;
+            if steps % POLL_INTERVAL == 0 && poll() {
+                return Ok(Stop::Interrupted);
+            }
+        }
+    }
+
+    // Runs until the current routine returns to its caller.
+    pub fn step_out(&mut self) -> Result<Stop, Error> {
+        self.step_out_with(|| false)
+    }
+
+    // Like `step_out`, polling as `cont_with` does.
+    pub fn step_out_with(&mut self, mut poll: impl FnMut() -> bool) -> Result<Stop, Error> {
+        let sp = self.cpu.sp;
+        let mut steps = 0;
+        loop {
+            let opcode = self.cpu.ram.load_byte(self.cpu.pc);
+            match self.step()? {
+                Stop::Step => (),
+                stop => return Ok(stop),
+            }
+            if is_ret(opcode) && (self.cpu.sp.wrapping_sub(sp) as i16) > 0 {
+                return Ok(Stop::Step);
+            }
+            if self.breakpoints.contains(&self.cpu.pc) {
+                return Ok(Stop::Breakpoint(self.cpu.pc));
+            }
+            steps += 1;
+            if steps % POLL_INTERVAL == 0 && poll() {
+                return Ok(Stop::Interrupted);
+            }
+        }
+    }
+
+    pub fn step_back(&mut self) -> Stop {
+        match self.cpu.step_back() {
+            Some(_) => Stop::Step,
+            None => Stop::JournalStart,
+        }
+    }
+
+    pub fn reverse_cont(&mut self) -> Stop {
+        while self.cpu.step_back().is_some() {
+            if self.breakpoints.contains(&self.cpu.pc) {
+                return Stop::Breakpoint(self.cpu.pc);
+            }
+        }
+        Stop::JournalStart
+    }
+
+    pub fn back_to_write(&mut self, addr: u16) -> Stop {
+        while let Some(entry) = self.cpu.step_back() {
+            if entry.wrote(addr) {
+                return Stop::Write(addr);
+            }
+        }
+        Stop::JournalStart
+    }
+
+    pub fn run_cli(&mut self, input: impl BufRead) -> Result<(), Error> {
+        self.print_regs();
+        prompt();
+        for line in input.lines() {
+            let Ok(line) = line else { break };
+            let mut words = line.split_whitespace();
+            let Some(cmd) = words.next() else {
+                prompt();
+                continue;
+            };
+            let arg = words.next();
+            let count = arg.and_then(|n| n.parse::<usize>().ok()).unwrap_or(1);
+
+            let stop = match cmd {
+                "s" | "step" => {
+                    let mut stop = Stop::Step;
+                    for _ in 0..count {
+                        stop = self.step()?;
+                        if stop != Stop::Step {
+                            break;
+                        }
+                    }
+                    Some(stop)
+                },
+                "c" | "continue" => Some(self.cont()?),
+                "n" | "next" => Some(self.step_over()?),
+                "f" | "finish" => Some(self.step_out()?),
+                "rs" | "reverse-step" => {
+                    let mut stop = Stop::Step;
+                    for _ in 0..count {
+                        stop = self.step_back();
+                        if stop != Stop::Step {
+                            break;
+                        }
+                    }
+                    Some(stop)
+                },
+                "rc" | "reverse-continue" => Some(self.reverse_cont()),
+                "rw" | "reverse-write" => match arg.and_then(|a| self.resolve(a)) {
+                    Some(addr) => Some(self.back_to_write(addr)),
+                    None => {
+                        println!("usage: rw ADDR");
+                        None
+                    },
+                },
+                "b" | "break" => {
+                    match arg.and_then(|a| self.resolve(a)) {
+                        Some(addr) => self.add_breakpoint(addr),
+                        None => self.breakpoints().for_each(|b| println!("{}", self.symbols.describe(b))),
+                    }
+                    None
+                },
+                "d" | "delete" => {
+                    match arg.and_then(|a| self.resolve(a)) {
+                        Some(addr) => if !self.remove_breakpoint(addr) {
+                            println!("no breakpoint at {:04x}", addr);
+                        },
+                        None => println!("usage: d ADDR"),
+                    }
+                    None
+                },
+                "l" | "list" => {
+                    let mut addr = arg.and_then(|a| self.resolve(a)).unwrap_or(self.cpu.pc);
+                    let count = words.next().and_then(|n| n.parse().ok()).unwrap_or(10);
+                    for _ in 0..count {
+                        if let Some(label) = self.symbols.name_of(addr) {
+                            println!("{label}:");
+                        }
+                        let (text, len) = disassemble(&self.cpu.ram, addr, &self.symbols, self.cpu.variant);
+                        println!("  {:04x}  {}", addr, text);
+                        addr = addr.wrapping_add(len);
+                    }
+                    None
+                },
+                "t" | "trace" => {
+                    self.trace = !self.trace;
+                    println!("trace {}", if self.trace { "on" } else { "off" });
+                    None
+                },
+                "sym" => {
+                    match arg.map(|path| self.symbols.load(path)) {
+                        Some(Ok(n)) => println!("{n} symbols loaded"),
+                        Some(Err(e)) => println!("Error: {e}"),
+                        None => println!("usage: sym FILE"),
+                    }
+                    None
+                },
+                "r" | "regs" => {
+                    self.print_regs();
+                    None
+                },
+                "x" => {
+                    match arg.and_then(|a| self.resolve(a)) {
+                        Some(addr) => {
+                            let len = words.next().and_then(|n| n.parse().ok()).unwrap_or(16);
+                            self.dump(addr, len);
+                        },
+                        None => println!("usage: x ADDR [LEN]"),
+                    }
+                    None
+                },
+                "q" | "quit" => break,
+                _ => {
+                    println!("commands: s [n], n, f, c, rs [n], rc, rw ADDR, b [ADDR], d ADDR, l [ADDR] [N], t, sym FILE, r, x ADDR [LEN], q");
+                    None
+                },
+            };
+
+            if let Some(stop) = stop {
+                match stop {
+                    Stop::Step => (),
+                    Stop::Breakpoint(addr) => println!("breakpoint at {}", self.symbols.describe(addr)),
+                    Stop::Halted => println!("halted"),
+                    Stop::Exited => println!("\nprogram exited"),
+                    Stop::Write(addr) => println!("last write to {}", self.symbols.describe(addr)),
+                    Stop::JournalStart => println!("start of journal"),
+                    Stop::Interrupted => println!("interrupted"),
+                }
+                self.print_regs();
+            }
+            prompt();
+        }
+        Ok(())
+    }
+
+    fn print_regs(&self) {
+        let cpu = &self.cpu;
+        println!(
+            "PC={:04x} SP={:04x} A={:02x} B={:02x} C={:02x} D={:02x} E={:02x} H={:02x} L={:02x} flag={:08b}",
+            cpu.pc, cpu.sp, cpu.a, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, cpu.flags(),
+        );
+        println!("{}", self.describe_pc());
+    }
+
+    // `PRINT_STR+3  0150  CALL BDOS`
+    fn describe_pc(&self) -> String {
+        let (text, _) = disassemble(&self.cpu.ram, self.cpu.pc, &self.symbols, self.cpu.variant);
+        format!("{:<16} {:04x}  {}", self.symbols.describe(self.cpu.pc), self.cpu.pc, text)
+    }
+
+    fn dump(&self, addr: u16, len: usize) {
+        for row in (0..len).step_by(16) {
+            let start = addr.wrapping_add(row as u16);
+            print!("{:04x}:", start);
+            for i in 0..16.min(len - row) {
+                print!(" {:02x}", self.cpu.ram.load_byte(start.wrapping_add(i as u16)));
+            }
+            println!();
+        }
+    }
+}
+
+fn prompt() {
+    print!("> ");
+    io::stdout().flush().ok();
+}
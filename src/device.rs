@@ -1,6 +1,83 @@
 #![allow(unused)]
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
 pub trait Device {
     fn read(&mut self) -> u8;
     fn write(&mut self, byte: u8);
-}
\ No newline at end of file
+    // T-states the device holds READY low for on each access.
+    fn wait_states(&self) -> u64 {
+        0
+    }
+}
+
+// What an unused port looks like on a bus with pull-ups.
+pub struct OpenBus;
+
+impl Device for OpenBus {
+    fn read(&mut self) -> u8 {
+        0xff
+    }
+
+    fn write(&mut self, _byte: u8) {}
+}
+
+// A device that keeps time with the CPU. `now` is `Cpu::cycles`, passed
+// after every instruction.
+pub trait Clocked {
+    fn tick(&mut self, now: u64);
+}
+
+// A device decoding several ports. It gets the full port number, so chips
+// wired to the low address lines just look at `port & mask`.
+pub trait PortDevice {
+    fn read_port(&mut self, port: u8) -> u8;
+    fn write_port(&mut self, port: u8, byte: u8);
+    fn wait_states(&self, port: u8) -> u64 {
+        0
+    }
+}
+
+// One port of a shared `PortDevice`, as plugged into `Cpu::devices`.
+pub struct Port<D: PortDevice> {
+    device: Rc<RefCell<D>>,
+    port: u8,
+}
+
+impl<D: PortDevice> Port<D> {
+    pub fn new(device: Rc<RefCell<D>>, port: u8) -> Self {
+        Self { device, port }
+    }
+}
+
+impl<D: PortDevice> Device for Port<D> {
+    fn read(&mut self) -> u8 {
+        self.device.borrow_mut().read_port(self.port)
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.device.borrow_mut().write_port(self.port, byte)
+    }
+
+    fn wait_states(&self) -> u64 {
+        self.device.borrow().wait_states(self.port)
+    }
+}
+
+// The peripheral side of a chip with parallel I/O lines, for whatever is
+// wired to it: keypads, LED matrices, another board. Ports are numbered
+// as the chip numbers them.
+pub trait Pins {
+    // Levels the outside world drives onto `port`. Lines nobody drives
+    // are pulled high.
+    fn drive(&mut self, port: u8, levels: u8);
+    fn driven(&self, port: u8) -> u8;
+    // Levels on the lines the chip drives; the rest read as driven.
+    fn sense(&self, port: u8) -> u8;
+
+    fn drive_line(&mut self, port: u8, bit: u8, level: bool) {
+        let levels = self.driven(port) & !(1 << bit) | (level as u8) << bit;
+        self.drive(port, levels);
+    }
+}
@@ -0,0 +1,97 @@
+#![allow(unused)]
+
+use crate::cpu::{Cpu, Variant, decode_for};
+use crate::dram::Dram;
+use crate::error::Error;
+use crate::instruction::{Instruction, hex8, hex16};
+use crate::symbols::Symbols;
+use crate::z80;
+
+// Decodes the instruction at `addr`, returning it with its length in bytes.
+pub fn decode_at(ram: &Dram, addr: u16, variant: Variant) -> Result<(Instruction, u16), Error> {
+    let mut len = 0u16;
+    let ins = decode_for(variant, || {
+        let byte = ram.load_byte(addr.wrapping_add(len));
+        len += 1;
+        byte
+    })?;
+    Ok((ins, len))
+}
+
+// What the instruction at `addr` does to the flow of control, for the
+// profiler and coverage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flow {
+    pub len: u16,
+    pub call: bool,
+    pub ret: bool,
+    pub conditional: bool,
+}
+
+pub fn flow_at(ram: &Dram, addr: u16, variant: Variant) -> Flow {
+    use Instruction::*;
+
+    let flow = |len, call, ret, conditional| Flow { len, call, ret, conditional };
+    // The Z80 keeps the 8080's jumps, calls and returns and puts its own
+    // instructions where the 8080 has none; only the branches among them
+    // are told apart. DD and FD change no branch, so the instruction
+    // behind them decides, while the length covers the whole of it.
+    let mut len = None;
+    let mut addr = addr;
+    if variant == Variant::Z80 {
+        let z80_len = z80::length(ram, addr);
+        len = Some(z80_len);
+        while matches!(ram.load_byte(addr), 0xdd | 0xfd) {
+            addr = addr.wrapping_add(1);
+        }
+        match ram.load_byte(addr) {
+            // DJNZ and JR cc
+            0x10 | 0x20 | 0x28 | 0x30 | 0x38 => return flow(z80_len, false, false, true),
+            0x08 | 0x18 | 0xcb | 0xd9 => return flow(z80_len, false, false, false),
+            // RETN and RETI
+            0xed => return flow(z80_len, false, matches!(ram.load_byte(addr.wrapping_add(1)) & 0xc7, 0x45), false),
+            _ => (),
+        }
+    }
+    match decode_at(ram, addr, variant) {
+        Ok((ins, n)) => flow(len.unwrap_or(n),
+            matches!(ins, CALL(..) | CC(..) | CNC(..) | CZ(..) | CNZ(..) | CM(..) | CP(..) | CPE(..) | CPO(..)
+                | RST(_) | RSTV),
+            matches!(ins, RET | RC | RNC | RZ | RNZ | RM | RP | RPE | RPO),
+            matches!(ins, JC(..) | JNC(..) | JZ(..) | JNZ(..) | JM(..) | JP(..) | JPE(..) | JPO(..) | JNK(..) | JK(..)
+                | CC(..) | CNC(..) | CZ(..) | CNZ(..) | CM(..) | CP(..) | CPE(..) | CPO(..) | RSTV
+                | RC | RNC | RZ | RNZ | RM | RP | RPE | RPO)),
+        Err(_) => flow(len.unwrap_or(1), false, false, false),
+    }
+}
+
+// Whether the step from `pc` and `sp` took an interrupt instead of the
+// instruction of `len` bytes there. The interrupt pushes `pc` itself, which
+// no instruction does but a PUSH of the same value, and that falls through.
+pub fn took_interrupt(cpu: &Cpu, pc: u16, sp: u16, len: u16) -> bool {
+    cpu.sp == sp.wrapping_sub(2) && cpu.ram.load_word(cpu.sp) == pc && cpu.pc != pc.wrapping_add(len)
+}
+
+// Mnemonic and length of the instruction at `addr`, with address operands
+// shown as labels where known. Bytes that are not a valid opcode come out as
+// a one byte `DB`.
+pub fn disassemble(ram: &Dram, addr: u16, symbols: &Symbols, variant: Variant) -> (String, u16) {
+    match decode_at(ram, addr, variant) {
+        Ok((ins, len)) => {
+            let text = ins.to_string();
+            match ins.address().and_then(|a| Some((a, symbols.name_of(a)?))) {
+                Some((a, name)) => (text.replace(&hex16(a), name), len),
+                None => (text, len),
+            }
+        },
+        Err(_) => (format!("DB {}", hex8(ram.load_byte(addr))), 1),
+    }
+}
+
+// Space separated hex bytes of an instruction, e.g. `cd a3 01`.
+pub fn hex_bytes(ram: &Dram, addr: u16, len: u16) -> String {
+    (0..len)
+        .map(|i| format!("{:02x}", ram.load_byte(addr.wrapping_add(i))))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,193 @@
+#![allow(unused)]
+
+// Intel 8257 DMA controller. Ports 0-7 hold the address (even) and
+// terminal count (odd) of channels 0-3, written and read LSB first through
+// a shared flip-flop; port 8 is the mode set register (out) and status
+// (in).
+//
+// The top two bits of a terminal count register pick the cycle type:
+// 01 writes memory from the device, 10 reads memory to the device, 00
+// verifies (no transfer). The low 14 bits are the number of cycles less
+// one.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::device::PortDevice;
+use crate::dram::Dram;
+
+// T-states one DMA cycle takes from the CPU.
+pub const DMA_CYCLE: u64 = 4;
+
+// Mode set bits; the low four enable channels 0-3.
+const ROTATING: u8 = 0x10;
+const TC_STOP: u8 = 0x40;
+const AUTOLOAD: u8 = 0x80;
+
+// Status bits; the low four are the channels' terminal count flags.
+const UPDATE: u8 = 0x10;
+
+const VERIFY: u16 = 0x0000;
+const WRITE: u16 = 0x4000;
+const READ: u16 = 0x8000;
+const COUNT_MASK: u16 = 0x3fff;
+
+// What drives the CPU's HOLD input. While `hold` is true the CPU gives
+// up the bus and `bus_cycle` runs instead of the next instruction.
+pub trait BusMaster {
+    fn hold(&self) -> bool;
+    // One machine cycle with HLDA high. Returns the T-states it took.
+    fn bus_cycle(&mut self, ram: &mut Dram) -> u64;
+}
+
+// A peripheral on a DMA channel. It raises DRQ and exchanges data with
+// the I/OR and I/OW strobes while DACK selects it; its port address plays
+// no part.
+pub trait DmaDevice {
+    fn dreq(&self) -> bool;
+    fn io_read(&mut self) -> u8;
+    fn io_write(&mut self, byte: u8);
+    // The last cycle of a block is under way.
+    fn terminal_count(&mut self) {}
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Channel {
+    address: u16,
+    count: u16,
+}
+
+pub struct Dma {
+    channels: [Channel; 4],
+    devices: [Option<Rc<RefCell<dyn DmaDevice>>>; 4],
+    mode: u8,
+    status: u8,
+    msb: bool,
+    // The channel served last, lowest in rotating priority.
+    last: usize,
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Self {
+            channels: [Channel::default(); 4],
+            devices: [const { None }; 4],
+            mode: 0,
+            status: 0,
+            msb: false,
+            last: 3,
+        }
+    }
+
+    pub fn connect<D: DmaDevice + 'static>(&mut self, channel: usize, device: &Rc<RefCell<D>>) {
+        self.devices[channel] = Some(device.clone());
+    }
+
+    pub fn address(&self, channel: usize) -> u16 {
+        self.channels[channel].address
+    }
+
+    pub fn count(&self, channel: usize) -> u16 {
+        self.channels[channel].count & COUNT_MASK
+    }
+
+    fn enabled(&self, channel: usize) -> bool {
+        self.mode & 1 << channel != 0
+    }
+
+    // Channels by priority: fixed 0 first, or rotating so that the one
+    // just served comes last.
+    fn by_priority(&self) -> impl Iterator<Item = usize> {
+        let first = if self.mode & ROTATING != 0 { (self.last + 1) & 3 } else { 0 };
+        (0..4).map(move |i| (first + i) & 3)
+    }
+
+    // The channel to serve next, if any requests.
+    fn requesting(&self) -> Option<usize> {
+        self.by_priority().find(|&n| {
+            self.enabled(n) && self.devices[n].as_ref().is_some_and(|d| d.borrow().dreq())
+        })
+    }
+
+    fn write_register(&mut self, channel: usize, count: bool, byte: u8) {
+        let channel = &mut self.channels[channel];
+        let reg = if count { &mut channel.count } else { &mut channel.address };
+        *reg = if self.msb {
+            *reg & 0x00ff | (byte as u16) << 8
+        } else {
+            *reg & 0xff00 | byte as u16
+        };
+    }
+}
+
+impl BusMaster for Dma {
+    fn hold(&self) -> bool {
+        self.requesting().is_some()
+    }
+
+    fn bus_cycle(&mut self, ram: &mut Dram) -> u64 {
+        let Some(n) = self.requesting() else { return 0 };
+        let device = self.devices[n].clone().unwrap();
+        let Channel { address, count } = self.channels[n];
+        let tc = count & COUNT_MASK == 0;
+        if tc {
+            device.borrow_mut().terminal_count();
+        }
+        match count & !COUNT_MASK {
+            WRITE => ram.save_byte(address, device.borrow_mut().io_read()),
+            READ => device.borrow_mut().io_write(ram.load_byte(address)),
+            _ => (),
+        }
+        self.last = n;
+        let channel = &mut self.channels[n];
+        channel.address = address.wrapping_add(1);
+        channel.count = count & !COUNT_MASK | count.wrapping_sub(1) & COUNT_MASK;
+        if tc {
+            self.status |= 1 << n;
+            if n == 2 && self.mode & AUTOLOAD != 0 {
+                self.channels[2] = self.channels[3];
+                self.status |= UPDATE;
+            } else if self.mode & TC_STOP != 0 {
+                self.mode &= !(1 << n);
+            }
+        }
+        DMA_CYCLE
+    }
+}
+
+impl PortDevice for Dma {
+    fn read_port(&mut self, port: u8) -> u8 {
+        match port & 0x0f {
+            // Reading clears the TC and update flags.
+            8 => std::mem::take(&mut self.status),
+            p @ 0..=7 => {
+                let channel = self.channels[p as usize / 2];
+                let reg = if p & 1 != 0 { channel.count } else { channel.address };
+                let byte = if self.msb { (reg >> 8) as u8 } else { reg as u8 };
+                self.msb = !self.msb;
+                byte
+            },
+            _ => 0xff,
+        }
+    }
+
+    fn write_port(&mut self, port: u8, byte: u8) {
+        match port & 0x0f {
+            8 => {
+                self.mode = byte;
+                self.msb = false;
+            },
+            p @ 0..=7 => {
+                let (channel, count) = (p as usize / 2, p & 1 != 0);
+                self.write_register(channel, count, byte);
+                // With autoload on, channel 2's parameters also go to
+                // channel 3 for the reload.
+                if channel == 2 && self.mode & AUTOLOAD != 0 {
+                    self.write_register(3, count, byte);
+                }
+                self.msb = !self.msb;
+            },
+            _ => (),
+        }
+    }
+}
@@ -1,42 +1,219 @@
 #![allow(unused)]
 
+use std::cell::{Cell, RefCell};
+use std::ops::Range;
+
 use crate::{cpu::RAM_SIZE, utils::get_u16};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read(u16),
+    // Address and the byte it held before the write.
+    Write(u16, u8),
+}
+
 #[derive(Debug)]
 pub struct Dram {
     memory: [u8; RAM_SIZE],
+    // Every access while at least one tracer is active. Tracers nest, each
+    // one sees the accesses made since it started.
+    trace: RefCell<Vec<Access>>,
+    tracers: usize,
+    // Read-only addresses; writes there are dropped.
+    rom: Range<usize>,
+    // Installed memory. Above it the bus floats: reads give 0FFH and
+    // writes are dropped.
+    size: usize,
+    // Target of read-modify-write accesses to ROM or missing memory.
+    scratch: u8,
+    // Wait states per address, once any are set, and those run up since
+    // the CPU last collected them.
+    waits: Option<Box<[u8]>>,
+    waited: Cell<u64>,
+    // Bytes the block cache has translated, and those written since it
+    // last collected them.
+    code: Box<[bool]>,
+    code_written: Vec<u16>,
 }
 
 impl Dram {
     pub fn new() -> Self {
         Self {
             memory: [0; RAM_SIZE],
+            trace: RefCell::new(Vec::new()),
+            tracers: 0,
+            rom: 0..0,
+            size: RAM_SIZE,
+            scratch: 0,
+            waits: None,
+            waited: Cell::new(0),
+            code: vec![false; RAM_SIZE].into_boxed_slice(),
+            code_written: Vec::new(),
         }
     }
-    
+
     pub fn load_slice(&mut self, data: &[u8]) {
-        self.memory[0x100..(data.len() + 0x100)].copy_from_slice(data);
-        println!("image loaded.");
+        self.load_at(0x100, data);
+        eprintln!("image loaded.");
+    }
+
+    pub fn load_at(&mut self, addr: u16, data: &[u8]) {
+        let addr = addr as usize;
+        self.memory[addr..(data.len() + addr)].copy_from_slice(data);
+        for addr in addr..addr + data.len() {
+            self.touch(addr);
+        }
+    }
+
+    // Sets every byte, ROM included, to what `next` gives in turn.
+    pub fn fill_with(&mut self, next: impl FnMut() -> u8) {
+        self.memory.fill_with(next);
+        for addr in 0..RAM_SIZE {
+            self.touch(addr);
+        }
+    }
+
+    // Makes `range` read-only. `load_at` can still fill it.
+    pub fn protect(&mut self, range: Range<usize>) {
+        self.rom = range;
+    }
+
+    pub fn is_rom(&self, addr: u16) -> bool {
+        self.rom.contains(&(addr as usize))
+    }
+
+    // Populates only the first `size` bytes of the address space.
+    pub fn set_size(&mut self, size: usize) {
+        self.size = size.min(RAM_SIZE);
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // Has every access to `range` take `states` extra T-states, as a slow
+    // board pulling READY low would.
+    pub fn set_wait_states(&mut self, range: Range<usize>, states: u8) {
+        let waits = self.waits.get_or_insert_with(|| vec![0; RAM_SIZE].into_boxed_slice());
+        waits[range.start.min(RAM_SIZE)..range.end.min(RAM_SIZE)].fill(states);
+    }
+
+    pub fn has_wait_states(&self) -> bool {
+        self.waits.is_some()
+    }
+
+    pub fn wait_states(&self, addr: u16) -> u64 {
+        self.waits.as_ref().map_or(0, |waits| waits[addr as usize] as u64)
+    }
+
+    // The wait states accesses have run up since the last call.
+    pub fn take_wait_states(&self) -> u64 {
+        self.waited.take()
+    }
+
+    fn wait(&self, addr: u16) {
+        if let Some(waits) = &self.waits {
+            self.waited.set(self.waited.get() + waits[addr as usize] as u64);
+        }
+    }
+
+    // Asks to hear about writes to `range`.
+    pub fn mark_code(&mut self, range: Range<usize>) {
+        self.code[range].fill(true);
+    }
+
+    pub fn code_written(&self) -> bool {
+        !self.code_written.is_empty()
+    }
+
+    // Marked bytes written since the last call. Each is reported once, then
+    // has to be marked again.
+    pub fn take_code_writes(&mut self) -> Vec<u16> {
+        std::mem::take(&mut self.code_written)
     }
 
+    fn touch(&mut self, addr: usize) {
+        if self.code[addr] {
+            self.code[addr] = false;
+            self.code_written.push(addr as u16);
+        }
+    }
+
+    fn is_writable(&self, addr: u16) -> bool {
+        (addr as usize) < self.size && !self.is_rom(addr)
+    }
+
+    // Read-modify-write access to a byte.
     pub fn get_ptr(&mut self, addr: u16) -> &mut u8 {
+        self.log(Access::Read(addr));
+        // The write cycle happens whether or not anything is there to take it.
+        self.wait(addr);
+        self.wait(addr);
+        if !self.is_writable(addr) {
+            self.scratch = if (addr as usize) < self.size { self.memory[addr as usize] } else { 0xff };
+            return &mut self.scratch;
+        }
+        self.log(Access::Write(addr, self.memory[addr as usize]));
+        self.touch(addr as usize);
         &mut self.memory[addr as usize]
     }
 
     pub fn load_byte(&self, addr: u16) -> u8 {
+        self.log(Access::Read(addr));
+        self.wait(addr);
+        if addr as usize >= self.size {
+            return 0xff;
+        }
+        self.memory[addr as usize]
+    }
+
+    // Reads without tracing, to look at memory from outside the program.
+    pub fn peek(&self, addr: u16) -> u8 {
+        if addr as usize >= self.size {
+            return 0xff;
+        }
         self.memory[addr as usize]
     }
 
     pub fn load_word(&self, addr: u16) -> u16 {
-        get_u16(self.memory[(addr + 1) as usize], self.memory[addr as usize])
+        let lo = self.load_byte(addr);
+        get_u16(self.load_byte(addr.wrapping_add(1)), lo)
     }
 
     pub fn save_byte(&mut self, addr: u16, byte: u8) {
+        self.wait(addr);
+        if !self.is_writable(addr) {
+            return;
+        }
+        self.log(Access::Write(addr, self.memory[addr as usize]));
+        self.touch(addr as usize);
         self.memory[addr as usize] = byte;
     }
 
     pub fn save_word(&mut self, addr: u16, word: u16) {
-        self.memory[addr as usize] = (word & 0xff) as u8;
-        self.memory[addr as usize + 1] = (word >> 8) as u8;
+        self.save_byte(addr, (word & 0xff) as u8);
+        self.save_byte(addr.wrapping_add(1), (word >> 8) as u8);
+    }
+
+    // Starts tracing accesses; pass the returned mark to `end_trace`.
+    pub fn begin_trace(&mut self) -> usize {
+        self.tracers += 1;
+        self.trace.get_mut().len()
     }
-}
\ No newline at end of file
+
+    pub fn end_trace(&mut self, mark: usize) -> Vec<Access> {
+        let trace = self.trace.get_mut();
+        let accesses = trace[mark..].to_vec();
+        self.tracers -= 1;
+        if self.tracers == 0 {
+            trace.clear();
+        }
+        accesses
+    }
+
+    fn log(&self, access: Access) {
+        if self.tracers > 0 {
+            self.trace.borrow_mut().push(access);
+        }
+    }
+}
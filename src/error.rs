@@ -6,6 +6,10 @@ use std::fmt::Display;
 pub enum Error {
     UnknownOpcode(u8),
     PcOutofRange,
+    BadHexRecord(usize),
+    BadScript(usize),
+    BadTapeImage,
+    PastEndOfMemory(u16),
 }
 
 impl Display for Error {
@@ -15,6 +19,10 @@ impl Display for Error {
         match self {
             UnknownOpcode(opcode) => write!(f, "Invalid opcode {}.", opcode),
             PcOutofRange => write!(f, "Program counter out of range."),
+            BadHexRecord(line) => write!(f, "Malformed Intel HEX record on line {}.", line),
+            BadScript(line) => write!(f, "Malformed input script on line {}.", line),
+            BadTapeImage => write!(f, "Malformed Radio-86RK tape image."),
+            PastEndOfMemory(addr) => write!(f, "Image at {:04X}H runs past the end of memory.", addr),
         }
     }
 }
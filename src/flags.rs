@@ -0,0 +1,61 @@
+#![allow(unused)]
+
+// Lazy flags. Most arithmetic results have their sign, zero, parity and
+// auxiliary carry overwritten by the next arithmetic instruction before
+// anything looks at them, so the CPU keeps the result instead and works a
+// flag out only when a conditional, PUSH PSW or the debugger asks. Carry
+// is cheap and read often (ADC, rotates), so it stays in the flag byte.
+
+use crate::cpu::{AUXILIARY_CARRY_BIT, PARITY_BIT, SIGN_BIT, ZERO_BIT};
+use crate::utils::bittest;
+
+// The flag bits that can be pending.
+pub const LAZY_BITS: u8 = 1 << SIGN_BIT | 1 << ZERO_BIT | 1 << AUXILIARY_CARRY_BIT | 1 << PARITY_BIT;
+
+// S, Z and P follow `res`; AC is bit 4 of `aux`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LazyFlags {
+    pub res: u8,
+    pub aux: u8,
+}
+
+impl LazyFlags {
+    // lhs + rhs + carry, or lhs - rhs - carry, and the carry out. Bit 4 of
+    // lhs ^ rhs ^ res is the carry (or borrow) into bit 4; the 8080 sets
+    // AC on a subtraction when there was no borrow.
+    pub fn arith(lhs: u8, rhs: u8, carry: bool, subtract: bool) -> (Self, bool) {
+        let c = u16::from(carry);
+        let (res, carry) = if subtract {
+            let res = u16::from(lhs).wrapping_sub(u16::from(rhs)).wrapping_sub(c);
+            (res as u8, res > 0xff)
+        } else {
+            let res = u16::from(lhs) + u16::from(rhs) + c;
+            (res as u8, res > 0xff)
+        };
+        let aux = lhs ^ rhs ^ res;
+        let aux = if subtract { !aux } else { aux };
+        (Self { res, aux }, carry)
+    }
+
+    // AND, OR and XOR results, which set AC from outside.
+    pub fn logical(res: u8, aux: bool) -> Self {
+        Self { res, aux: u8::from(aux) << AUXILIARY_CARRY_BIT }
+    }
+
+    pub fn get(self, bit: u8) -> bool {
+        match bit {
+            SIGN_BIT => bittest(self.res, 7),
+            ZERO_BIT => self.res == 0,
+            PARITY_BIT => self.res.count_ones().is_multiple_of(2),
+            AUXILIARY_CARRY_BIT => bittest(self.aux, 4),
+            _ => unreachable!(),
+        }
+    }
+
+    // The pending bits, in place.
+    pub fn bits(self) -> u8 {
+        [SIGN_BIT, ZERO_BIT, AUXILIARY_CARRY_BIT, PARITY_BIT]
+            .into_iter()
+            .fold(0, |bits, bit| bits | u8::from(self.get(bit)) << bit)
+    }
+}
@@ -0,0 +1,317 @@
+#![allow(unused)]
+
+// GDB remote serial protocol server.
+//
+// Register layout used by `g`/`G`/`p`/`P` (little-endian, 12 bytes):
+//
+//   0 A   1 F   2 B   3 C   4 D   5 E   6 H   7 L   (8-bit)
+//   8 SP  9 PC                                      (16-bit)
+
+use std::io::{self, BufReader, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use crate::debugger::{Debugger, Stop};
+use crate::error::Error;
+use crate::utils::{get_u16, split_u16};
+
+const SIGINT: &str = "S02";
+const SIGILL: &str = "S04";
+const SIGTRAP: &str = "S05";
+
+enum Event {
+    Packet(String),
+    BadChecksum,
+    Interrupt,
+}
+
+pub struct GdbServer<W: Write> {
+    dbg: Debugger,
+    out: W,
+    events: Receiver<Event>,
+    no_ack: bool,
+}
+
+// Serves one client until it detaches, kills the target or disconnects.
+pub fn serve(dbg: Debugger, input: impl Read + Send + 'static, out: impl Write) -> io::Result<Debugger> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || read_events(input, tx));
+    let mut server = GdbServer { dbg, out, events: rx, no_ack: false };
+    server.run()?;
+    Ok(server.dbg)
+}
+
+impl<W: Write> GdbServer<W> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Ok(event) = self.events.recv() {
+            match event {
+                Event::Packet(packet) => {
+                    if !self.no_ack {
+                        self.out.write_all(b"+")?;
+                    }
+                    match self.handle(&packet) {
+                        Some(reply) => self.send(&reply)?,
+                        None => {
+                            if packet.starts_with('D') {
+                                self.send("OK")?;
+                            }
+                            return Ok(());
+                        },
+                    }
+                },
+                Event::BadChecksum => self.out.write_all(b"-")?,
+                // Nothing is running, so there is nothing to interrupt.
+                Event::Interrupt => (),
+            }
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+
+    // The reply to a packet, or None when the session should end.
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let Some(cmd) = packet.get(..1) else {
+            return Some(String::new());
+        };
+        let args = &packet[1..];
+        let reply = match cmd {
+            "?" => SIGTRAP.to_string(),
+            "g" => encode(&self.read_registers()),
+            "G" => match decode(args) {
+                Some(bytes) if bytes.len() == 12 => {
+                    self.write_registers(&bytes);
+                    "OK".to_string()
+                },
+                _ => "E01".to_string(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < 8 => encode(&self.read_registers()[n..n + 1]),
+                Ok(n @ (8 | 9)) => {
+                    let offset = 8 + (n - 8) * 2;
+                    encode(&self.read_registers()[offset..offset + 2])
+                },
+                _ => "E01".to_string(),
+            },
+            "P" => self.write_register(args).unwrap_or_else(|| "E01".to_string()),
+            "m" => self.read_memory(args).unwrap_or_else(|| "E01".to_string()),
+            "M" => self.write_memory(args).unwrap_or_else(|| "E01".to_string()),
+            "s" => {
+                self.jump(args);
+                stop_reply(self.dbg.step())
+            },
+            "c" => {
+                self.jump(args);
+                self.cont()
+            },
+            "b" => match args {
+                "s" => reverse_reply(self.dbg.step_back()),
+                "c" => reverse_reply(self.dbg.reverse_cont()),
+                _ => String::new(),
+            },
+            "Z" | "z" => self.breakpoint(cmd == "Z", args).unwrap_or_default(),
+            "H" | "T" => "OK".to_string(),
+            "k" | "D" => return None,
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        match packet.split(':').next().unwrap() {
+            "qSupported" => "PacketSize=1000;QStartNoAckMode+;ReverseStep+;ReverseContinue+".to_string(),
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            },
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qOffsets" => "Text=0;Data=0;Bss=0".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn cont(&mut self) -> String {
+        let events = &self.events;
+        let stop = self.dbg.cont_with(|| interrupted(events));
+        match stop {
+            Ok(Stop::Interrupted) => SIGINT.to_string(),
+            stop => stop_reply(stop),
+        }
+    }
+
+    // `c`/`s` may carry an address to resume from.
+    fn jump(&mut self, args: &str) {
+        if let Ok(addr) = u16::from_str_radix(args, 16) {
+            self.dbg.cpu.pc = addr;
+        }
+    }
+
+    fn read_registers(&self) -> [u8; 12] {
+        let cpu = &self.dbg.cpu;
+        let (sp_hi, sp_lo) = split_u16(cpu.sp);
+        let (pc_hi, pc_lo) = split_u16(cpu.pc);
+        [cpu.a, cpu.flags(), cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l, sp_lo, sp_hi, pc_lo, pc_hi]
+    }
+
+    fn write_registers(&mut self, bytes: &[u8]) {
+        let cpu = &mut self.dbg.cpu;
+        cpu.a = bytes[0];
+        cpu.set_flag_byte(bytes[1]);
+        cpu.b = bytes[2];
+        cpu.c = bytes[3];
+        cpu.d = bytes[4];
+        cpu.e = bytes[5];
+        cpu.h = bytes[6];
+        cpu.l = bytes[7];
+        cpu.sp = get_u16(bytes[9], bytes[8]);
+        cpu.pc = get_u16(bytes[11], bytes[10]);
+    }
+
+    // `n=value`
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (n, value) = args.split_once('=')?;
+        let n = usize::from_str_radix(n, 16).ok()?;
+        let value = decode(value)?;
+        let mut regs = self.read_registers();
+        let (offset, len) = match n {
+            0..=7 => (n, 1),
+            8 | 9 => (8 + (n - 8) * 2, 2),
+            _ => return None,
+        };
+        if value.len() != len {
+            return None;
+        }
+        regs[offset..offset + len].copy_from_slice(&value);
+        self.write_registers(&regs);
+        Some("OK".to_string())
+    }
+
+    // `addr,length`
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, len) = parse_range(args)?;
+        let bytes = (0..len)
+            .map(|i| self.dbg.cpu.ram.load_byte((addr + i) as u16))
+            .collect::<Vec<_>>();
+        Some(encode(&bytes))
+    }
+
+    // `addr,length:XX...`
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = parse_range(range)?;
+        let data = decode(data)?;
+        if data.len() != len as usize {
+            return None;
+        }
+        for (i, byte) in data.into_iter().enumerate() {
+            self.dbg.cpu.ram.save_byte((addr + i as u32) as u16, byte);
+        }
+        Some("OK".to_string())
+    }
+
+    // `type,addr,kind`. Software (0) and hardware (1) breakpoints are the
+    // same thing here; watchpoints are not supported.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+        if kind != "0" && kind != "1" {
+            return None;
+        }
+        if insert {
+            self.dbg.add_breakpoint(addr);
+        } else {
+            self.dbg.remove_breakpoint(addr);
+        }
+        Some("OK".to_string())
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.out, "${}#{:02x}", data, sum)?;
+        self.out.flush()
+    }
+}
+
+// Drains the events that arrived while the target ran, looking for Ctrl-C.
+fn interrupted(events: &Receiver<Event>) -> bool {
+    loop {
+        match events.try_recv() {
+            Ok(Event::Interrupt) => return true,
+            // Clients do not send packets while the target runs.
+            Ok(_) => (),
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => return true,
+        }
+    }
+}
+
+fn stop_reply(stop: Result<Stop, Error>) -> String {
+    match stop {
+        Ok(Stop::Exited) => "W00".to_string(),
+        Ok(_) => SIGTRAP.to_string(),
+        Err(_) => SIGILL.to_string(),
+    }
+}
+
+fn reverse_reply(stop: Stop) -> String {
+    match stop {
+        Stop::JournalStart => "T05replaylog:begin;".to_string(),
+        _ => SIGTRAP.to_string(),
+    }
+}
+
+// Splits the byte stream into packets and Ctrl-C interrupts. Acks from
+// the client are dropped.
+fn read_events(input: impl Read, tx: Sender<Event>) {
+    let mut bytes = BufReader::new(input).bytes().map_while(Result::ok);
+    while let Some(byte) = bytes.next() {
+        let event = match byte {
+            b'$' => {
+                let data = bytes.by_ref().take_while(|&b| b != b'#').collect::<Vec<_>>();
+                let checksum = bytes.by_ref().take(2).collect::<Vec<_>>();
+                let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+                let expected = std::str::from_utf8(&checksum).ok()
+                    .and_then(|c| u8::from_str_radix(c, 16).ok());
+                if expected == Some(sum) {
+                    Event::Packet(String::from_utf8_lossy(&data).into_owned())
+                } else {
+                    Event::BadChecksum
+                }
+            },
+            0x03 => Event::Interrupt,
+            _ => continue,
+        };
+        if tx.send(event).is_err() {
+            return;
+        }
+    }
+}
+
+// `addr,length` with both in hex; the range must lie inside memory.
+fn parse_range(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    let len = u32::from_str_radix(len, 16).ok()?;
+    if addr + len > 0x10000 {
+        return None;
+    }
+    Some((addr, len))
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,159 @@
+#![allow(unused)]
+
+// The 8085's extra pins: the TRAP and RST 5.5/6.5/7.5 interrupt inputs
+// with their masks, and the SID/SOD serial lines, all reached from the
+// program through RIM and SIM. Shared behind an `Rc` so devices can drive
+// them.
+//
+// By priority: TRAP (24H) cannot be masked or disabled and needs a rising
+// edge with the level still high; RST 7.5 (3CH) latches a rising edge;
+// RST 6.5 (34H) and 5.5 (2CH) are level triggered. INTR comes last.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Trap,
+    Rst75,
+    Rst65,
+    Rst55,
+    Sid,
+}
+
+pub const TRAP: u16 = 0x24;
+pub const RST75: u16 = 0x3c;
+pub const RST65: u16 = 0x34;
+pub const RST55: u16 = 0x2c;
+
+// Masks as set by SIM and read by RIM.
+const M55: u8 = 0x01;
+const M65: u8 = 0x02;
+const M75: u8 = 0x04;
+
+#[derive(Default)]
+pub struct Pins {
+    trap: bool,
+    trap_latched: bool,
+    rst75: bool,
+    rst75_latched: bool,
+    rst65: bool,
+    rst55: bool,
+    sid: bool,
+    sod: bool,
+    masks: u8,
+    // IE as it was when TRAP was taken, for the next RIM to report.
+    ie_before_trap: Option<bool>,
+    on_sod: Option<Box<dyn FnMut(bool)>>,
+}
+
+impl Pins {
+    pub fn new() -> Self {
+        // The RST inputs come out of reset masked.
+        Self { masks: M75 | M65 | M55, ..Default::default() }
+    }
+
+    // RESET masks the RST inputs, clears the RST 7.5 latch and drops SOD.
+    pub fn reset(&mut self) {
+        self.masks = M75 | M65 | M55;
+        self.rst75_latched = false;
+        self.trap_latched = false;
+        self.ie_before_trap = None;
+        if std::mem::take(&mut self.sod)
+            && let Some(f) = &mut self.on_sod
+        {
+            f(false);
+        }
+    }
+
+    pub fn set(&mut self, input: Input, level: bool) {
+        match input {
+            Input::Trap => {
+                if level && !self.trap {
+                    self.trap_latched = true;
+                }
+                self.trap = level;
+            },
+            Input::Rst75 => {
+                if level && !self.rst75 {
+                    self.rst75_latched = true;
+                }
+                self.rst75 = level;
+            },
+            Input::Rst65 => self.rst65 = level,
+            Input::Rst55 => self.rst55 = level,
+            Input::Sid => self.sid = level,
+        }
+    }
+
+    pub fn sod(&self) -> bool {
+        self.sod
+    }
+
+    pub fn masks(&self) -> u8 {
+        self.masks
+    }
+
+    // Calls `f` with the new level whenever SIM changes SOD.
+    pub fn connect_sod(&mut self, f: impl FnMut(bool) + 'static) {
+        self.on_sod = Some(Box::new(f));
+    }
+
+    // The vector of the interrupt to take, if any. `enabled` is IE, which
+    // gates all but TRAP.
+    pub fn pending(&self, enabled: bool) -> Option<u16> {
+        if self.trap_latched && self.trap {
+            return Some(TRAP);
+        }
+        if !enabled {
+            return None;
+        }
+        if self.rst75_latched && self.masks & M75 == 0 {
+            Some(RST75)
+        } else if self.rst65 && self.masks & M65 == 0 {
+            Some(RST65)
+        } else if self.rst55 && self.masks & M55 == 0 {
+            Some(RST55)
+        } else {
+            None
+        }
+    }
+
+    pub fn accept(&mut self, vector: u16, ie: bool) {
+        match vector {
+            TRAP => {
+                self.trap_latched = false;
+                self.ie_before_trap = Some(ie);
+            },
+            RST75 => self.rst75_latched = false,
+            _ => (),
+        }
+    }
+
+    // SID, pending I7.5/I6.5/I5.5, IE, then the masks.
+    pub fn rim(&mut self, ie: bool) -> u8 {
+        let ie = self.ie_before_trap.take().unwrap_or(ie);
+        (self.sid as u8) << 7
+            | (self.rst75_latched as u8) << 6
+            | (self.rst65 as u8) << 5
+            | (self.rst55 as u8) << 4
+            | (ie as u8) << 3
+            | self.masks
+    }
+
+    // SOD, SOE, -, R7.5, MSE, then the masks.
+    pub fn sim(&mut self, a: u8) {
+        if a & 0x08 != 0 {
+            self.masks = a & 0x07;
+        }
+        if a & 0x10 != 0 {
+            self.rst75_latched = false;
+        }
+        if a & 0x40 != 0 {
+            let sod = a & 0x80 != 0;
+            if sod != self.sod {
+                self.sod = sod;
+                if let Some(f) = &mut self.on_sod {
+                    f(sod);
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,8 @@
-#[derive(Debug)]
+use std::fmt::Display;
+
+use crate::utils::get_u16;
+
+#[derive(Debug, Clone, Copy)]
 pub enum Instruction {
     
     // Carry bit instructions
@@ -52,12 +56,105 @@ pub enum Instruction {
 
     // HLT halt instruction
     HLT,
+
+    // 8085 only: interrupt masks and serial pins
+    RIM, SIM,
+
+    // 8085 only, undocumented
+    DSUB, ARHL, RDEL, LDHI(u8), LDSI(u8), RSTV,
+    SHLX, LHLX, JNK(u8, u8), JK(u8, u8),
 }
 
 impl Instruction {
     pub fn cycle_idx(&self) -> u8 {
         todo!()
     }
+
+    // Bytes of operand following the opcode.
+    pub const fn operand_len(&self) -> u8 {
+        use Instruction::*;
+        match self {
+            MVI(..) | ADI(_) | ACI(_) | SUI(_) | SBI(_) | ANI(_) | XRI(_) | ORI(_) | CPI(_)
+            | IN(_) | OUT(_) | LDHI(_) | LDSI(_) => 1,
+            LXI(..) | STA(..) | LDA(..) | SHLD(..) | LHLD(..)
+            | JMP(..) | JC(..) | JNC(..) | JZ(..) | JNZ(..) | JM(..) | JP(..) | JPE(..) | JPO(..)
+            | CALL(..) | CC(..) | CNC(..) | CZ(..) | CNZ(..) | CM(..) | CP(..) | CPE(..) | CPO(..)
+            | JNK(..) | JK(..) => 2,
+            _ => 0,
+        }
+    }
+
+    // Whether it can leave PC anywhere but the next instruction, or stop
+    // there: the end of a basic block.
+    pub const fn ends_block(&self) -> bool {
+        use Instruction::*;
+        matches!(self,
+            PCHL | JMP(..) | JC(..) | JNC(..) | JZ(..) | JNZ(..) | JM(..) | JP(..) | JPE(..) | JPO(..)
+            | CALL(..) | CC(..) | CNC(..) | CZ(..) | CNZ(..) | CM(..) | CP(..) | CPE(..) | CPO(..)
+            | RET | RC | RNC | RZ | RNZ | RM | RP | RPE | RPO
+            | RST(_) | HLT | RSTV | JNK(..) | JK(..))
+    }
+
+    // The same instruction with its operand bytes filled in; `hi` is
+    // ignored by one-byte operands.
+    pub fn with_operands(self, lo: u8, hi: u8) -> Self {
+        use Instruction::*;
+        match self {
+            MVI(r, _) => MVI(r, lo),
+            ADI(_) => ADI(lo),
+            ACI(_) => ACI(lo),
+            SUI(_) => SUI(lo),
+            SBI(_) => SBI(lo),
+            ANI(_) => ANI(lo),
+            XRI(_) => XRI(lo),
+            ORI(_) => ORI(lo),
+            CPI(_) => CPI(lo),
+            IN(_) => IN(lo),
+            OUT(_) => OUT(lo),
+            LDHI(_) => LDHI(lo),
+            LDSI(_) => LDSI(lo),
+            LXI(rp, _, _) => LXI(rp, lo, hi),
+            STA(..) => STA(lo, hi),
+            LDA(..) => LDA(lo, hi),
+            SHLD(..) => SHLD(lo, hi),
+            LHLD(..) => LHLD(lo, hi),
+            JMP(..) => JMP(lo, hi),
+            JC(..) => JC(lo, hi),
+            JNC(..) => JNC(lo, hi),
+            JZ(..) => JZ(lo, hi),
+            JNZ(..) => JNZ(lo, hi),
+            JM(..) => JM(lo, hi),
+            JP(..) => JP(lo, hi),
+            JPE(..) => JPE(lo, hi),
+            JPO(..) => JPO(lo, hi),
+            CALL(..) => CALL(lo, hi),
+            CC(..) => CC(lo, hi),
+            CNC(..) => CNC(lo, hi),
+            CZ(..) => CZ(lo, hi),
+            CNZ(..) => CNZ(lo, hi),
+            CM(..) => CM(lo, hi),
+            CP(..) => CP(lo, hi),
+            CPE(..) => CPE(lo, hi),
+            CPO(..) => CPO(lo, hi),
+            JNK(..) => JNK(lo, hi),
+            JK(..) => JK(lo, hi),
+            other => other,
+        }
+    }
+
+    // The 16-bit address or immediate operand, if the instruction has one.
+    pub fn address(&self) -> Option<u16> {
+        use Instruction::*;
+        match *self {
+            LXI(_, lo, hi) | STA(lo, hi) | LDA(lo, hi) | SHLD(lo, hi) | LHLD(lo, hi)
+            | JMP(lo, hi) | JC(lo, hi) | JNC(lo, hi) | JZ(lo, hi) | JNZ(lo, hi)
+            | JM(lo, hi) | JP(lo, hi) | JPE(lo, hi) | JPO(lo, hi)
+            | CALL(lo, hi) | CC(lo, hi) | CNC(lo, hi) | CZ(lo, hi) | CNZ(lo, hi)
+            | CM(lo, hi) | CP(lo, hi) | CPE(lo, hi) | CPO(lo, hi)
+            | JNK(lo, hi) | JK(lo, hi) => Some(get_u16(hi, lo)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -68,4 +165,147 @@ pub enum Src {
 #[derive(Debug, Clone, Copy)]
 pub enum RegPair {
     BC, DE, HL, PSW, SP,
-}
\ No newline at end of file
+}
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Instruction::*;
+        match *self {
+            CMC => write!(f, "CMC"),
+            STC => write!(f, "STC"),
+            INR(r) => write!(f, "INR {r}"),
+            DCR(r) => write!(f, "DCR {r}"),
+            CMA => write!(f, "CMA"),
+            DAA => write!(f, "DAA"),
+            NOP => write!(f, "NOP"),
+            MOV(d, s) => write!(f, "MOV {d},{s}"),
+            SATX(rp) => write!(f, "STAX {rp}"),
+            LDAX(rp) => write!(f, "LDAX {rp}"),
+            ADD(r) => write!(f, "ADD {r}"),
+            ADC(r) => write!(f, "ADC {r}"),
+            SUB(r) => write!(f, "SUB {r}"),
+            SBB(r) => write!(f, "SBB {r}"),
+            ANA(r) => write!(f, "ANA {r}"),
+            XRA(r) => write!(f, "XRA {r}"),
+            ORA(r) => write!(f, "ORA {r}"),
+            CMP(r) => write!(f, "CMP {r}"),
+            RLC => write!(f, "RLC"),
+            RRC => write!(f, "RRC"),
+            RAL => write!(f, "RAL"),
+            RAR => write!(f, "RAR"),
+            PUSH(rp) => write!(f, "PUSH {rp}"),
+            POP(rp) => write!(f, "POP {rp}"),
+            DAD(rp) => write!(f, "DAD {rp}"),
+            INX(rp) => write!(f, "INX {rp}"),
+            DCX(rp) => write!(f, "DCX {rp}"),
+            XCHG => write!(f, "XCHG"),
+            XTHL => write!(f, "XTHL"),
+            SPHL => write!(f, "SPHL"),
+            LXI(rp, lo, hi) => write!(f, "LXI {rp},{}", hex16(get_u16(hi, lo))),
+            MVI(r, d) => write!(f, "MVI {r},{}", hex8(d)),
+            ADI(d) => write!(f, "ADI {}", hex8(d)),
+            ACI(d) => write!(f, "ACI {}", hex8(d)),
+            SUI(d) => write!(f, "SUI {}", hex8(d)),
+            SBI(d) => write!(f, "SBI {}", hex8(d)),
+            ANI(d) => write!(f, "ANI {}", hex8(d)),
+            XRI(d) => write!(f, "XRI {}", hex8(d)),
+            ORI(d) => write!(f, "ORI {}", hex8(d)),
+            CPI(d) => write!(f, "CPI {}", hex8(d)),
+            STA(lo, hi) => write!(f, "STA {}", hex16(get_u16(hi, lo))),
+            LDA(lo, hi) => write!(f, "LDA {}", hex16(get_u16(hi, lo))),
+            SHLD(lo, hi) => write!(f, "SHLD {}", hex16(get_u16(hi, lo))),
+            LHLD(lo, hi) => write!(f, "LHLD {}", hex16(get_u16(hi, lo))),
+            PCHL => write!(f, "PCHL"),
+            JMP(lo, hi) => write!(f, "JMP {}", hex16(get_u16(hi, lo))),
+            JC(lo, hi) => write!(f, "JC {}", hex16(get_u16(hi, lo))),
+            JNC(lo, hi) => write!(f, "JNC {}", hex16(get_u16(hi, lo))),
+            JZ(lo, hi) => write!(f, "JZ {}", hex16(get_u16(hi, lo))),
+            JNZ(lo, hi) => write!(f, "JNZ {}", hex16(get_u16(hi, lo))),
+            JM(lo, hi) => write!(f, "JM {}", hex16(get_u16(hi, lo))),
+            JP(lo, hi) => write!(f, "JP {}", hex16(get_u16(hi, lo))),
+            JPE(lo, hi) => write!(f, "JPE {}", hex16(get_u16(hi, lo))),
+            JPO(lo, hi) => write!(f, "JPO {}", hex16(get_u16(hi, lo))),
+            CALL(lo, hi) => write!(f, "CALL {}", hex16(get_u16(hi, lo))),
+            CC(lo, hi) => write!(f, "CC {}", hex16(get_u16(hi, lo))),
+            CNC(lo, hi) => write!(f, "CNC {}", hex16(get_u16(hi, lo))),
+            CZ(lo, hi) => write!(f, "CZ {}", hex16(get_u16(hi, lo))),
+            CNZ(lo, hi) => write!(f, "CNZ {}", hex16(get_u16(hi, lo))),
+            CM(lo, hi) => write!(f, "CM {}", hex16(get_u16(hi, lo))),
+            CP(lo, hi) => write!(f, "CP {}", hex16(get_u16(hi, lo))),
+            CPE(lo, hi) => write!(f, "CPE {}", hex16(get_u16(hi, lo))),
+            CPO(lo, hi) => write!(f, "CPO {}", hex16(get_u16(hi, lo))),
+            RET => write!(f, "RET"),
+            RC => write!(f, "RC"),
+            RNC => write!(f, "RNC"),
+            RZ => write!(f, "RZ"),
+            RNZ => write!(f, "RNZ"),
+            RM => write!(f, "RM"),
+            RP => write!(f, "RP"),
+            RPE => write!(f, "RPE"),
+            RPO => write!(f, "RPO"),
+            RST(n) => write!(f, "RST {n}"),
+            EI => write!(f, "EI"),
+            DI => write!(f, "DI"),
+            IN(port) => write!(f, "IN {}", hex8(port)),
+            OUT(port) => write!(f, "OUT {}", hex8(port)),
+            HLT => write!(f, "HLT"),
+            RIM => write!(f, "RIM"),
+            SIM => write!(f, "SIM"),
+            DSUB => write!(f, "DSUB"),
+            ARHL => write!(f, "ARHL"),
+            RDEL => write!(f, "RDEL"),
+            LDHI(d) => write!(f, "LDHI {}", hex8(d)),
+            LDSI(d) => write!(f, "LDSI {}", hex8(d)),
+            RSTV => write!(f, "RSTV"),
+            SHLX => write!(f, "SHLX"),
+            LHLX => write!(f, "LHLX"),
+            JNK(lo, hi) => write!(f, "JNK {}", hex16(get_u16(hi, lo))),
+            JK(lo, hi) => write!(f, "JK {}", hex16(get_u16(hi, lo))),
+        }
+    }
+}
+
+impl Display for Src {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Src::B => "B",
+            Src::C => "C",
+            Src::D => "D",
+            Src::E => "E",
+            Src::H => "H",
+            Src::L => "L",
+            Src::A => "A",
+            Src::Mem => "M",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for RegPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RegPair::BC => "B",
+            RegPair::DE => "D",
+            RegPair::HL => "H",
+            RegPair::PSW => "PSW",
+            RegPair::SP => "SP",
+        };
+        write!(f, "{name}")
+    }
+}
+
+// Intel style hex literals: 0FFH, 01A3H.
+pub fn hex8(val: u8) -> String {
+    hex_literal(format!("{:02X}", val))
+}
+
+pub fn hex16(val: u16) -> String {
+    hex_literal(format!("{:04X}", val))
+}
+
+fn hex_literal(digits: String) -> String {
+    if digits.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        format!("0{digits}H")
+    } else {
+        format!("{digits}H")
+    }
+}
@@ -0,0 +1,42 @@
+#![allow(unused)]
+
+// Whatever drives the CPU's INT input. On acknowledge it puts the
+// instruction to execute on the data bus: RST n, or a whole CALL.
+pub trait InterruptSource {
+    fn requested(&self) -> bool;
+    fn acknowledge(&mut self) -> Vec<u8>;
+}
+
+// A request flip-flop jamming RST n onto the bus, the simplest way to let
+// one device interrupt. Set on a rising edge of its input, cleared on
+// acknowledge.
+#[derive(Debug)]
+pub struct RstLatch {
+    vector: u8,
+    level: bool,
+    latched: bool,
+}
+
+impl RstLatch {
+    pub fn new(vector: u8) -> Self {
+        Self { vector: vector & 7, level: false, latched: false }
+    }
+
+    pub fn set(&mut self, level: bool) {
+        if level && !self.level {
+            self.latched = true;
+        }
+        self.level = level;
+    }
+}
+
+impl InterruptSource for RstLatch {
+    fn requested(&self) -> bool {
+        self.latched
+    }
+
+    fn acknowledge(&mut self) -> Vec<u8> {
+        self.latched = false;
+        vec![0xc7 | self.vector << 3]
+    }
+}
@@ -0,0 +1,300 @@
+#![allow(unused)]
+
+// Taito Space Invaders (1978): 8K of ROM at 0000H, 1K of work RAM at
+// 2000H, 7K of 1bpp video RAM at 2400H, a hardware shift register and two
+// interrupts per frame (RST 1 mid-screen, RST 2 at vblank).
+//
+// Ports:
+//   IN  0  unused by the game        OUT 2  shift amount
+//   IN  1  coin, starts, player 1    OUT 3  sound latch 1
+//   IN  2  DIP switches, player 2    OUT 4  shift data
+//   IN  3  shift register result     OUT 5  sound latch 2
+//                                    OUT 6  watchdog
+
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::cpu::{CLOCK_RATE, Cpu};
+use crate::device::PortDevice;
+use crate::error::Error;
+
+pub const ROM_SIZE: usize = 0x2000;
+pub const VRAM: u16 = 0x2400;
+// The monitor is mounted on its side, so the 256x224 raster in video RAM
+// shows up as 224 wide by 256 high.
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 256;
+
+const FRAME_CYCLES: u64 = CLOCK_RATE as u64 / 60;
+const RST1: u8 = 0xcf;
+const RST2: u8 = 0xd7;
+// MAME's split ROM set, in address order.
+const ROM_FILES: [&str; 4] = ["invaders.h", "invaders.g", "invaders.f", "invaders.e"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Coin,
+    Start1,
+    Start2,
+    Fire1,
+    Left1,
+    Right1,
+    Fire2,
+    Left2,
+    Right2,
+    Tilt,
+}
+
+impl Button {
+    pub fn from_name(name: &str) -> Option<Self> {
+        use Button::*;
+        let button = match name.to_ascii_lowercase().as_str() {
+            "coin" => Coin,
+            "start1" => Start1,
+            "start2" => Start2,
+            "fire1" | "fire" => Fire1,
+            "left1" | "left" => Left1,
+            "right1" | "right" => Right1,
+            "fire2" => Fire2,
+            "left2" => Left2,
+            "right2" => Right2,
+            "tilt" => Tilt,
+            _ => return None,
+        };
+        Some(button)
+    }
+
+    // Input port and bit mask.
+    fn wiring(self) -> (usize, u8) {
+        use Button::*;
+        match self {
+            Coin => (1, 0x01),
+            Start2 => (1, 0x02),
+            Start1 => (1, 0x04),
+            Fire1 => (1, 0x10),
+            Left1 => (1, 0x20),
+            Right1 => (1, 0x40),
+            Tilt => (2, 0x04),
+            Fire2 => (2, 0x10),
+            Left2 => (2, 0x20),
+            Right2 => (2, 0x40),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Io {
+    inputs: [u8; 3],
+    shift: u16,
+    offset: u8,
+    pub sound: [u8; 2],
+    pub watchdog: u64,
+}
+
+impl Io {
+    fn new() -> Self {
+        Self {
+            // Port 1 bit 3 is tied high; DIP switches all off (3 ships,
+            // bonus at 1500, coin info shown).
+            inputs: [0x0e, 0x08, 0x00],
+            shift: 0,
+            offset: 0,
+            sound: [0; 2],
+            watchdog: 0,
+        }
+    }
+}
+
+impl PortDevice for Io {
+    fn read_port(&mut self, port: u8) -> u8 {
+        match port {
+            0..=2 => self.inputs[port as usize],
+            3 => (self.shift >> (8 - self.offset)) as u8,
+            _ => 0,
+        }
+    }
+
+    fn write_port(&mut self, port: u8, byte: u8) {
+        match port {
+            2 => self.offset = byte & 7,
+            3 => self.sound[0] = byte,
+            4 => self.shift = (byte as u16) << 8 | self.shift >> 8,
+            5 => self.sound[1] = byte,
+            6 => self.watchdog += 1,
+            _ => (),
+        }
+    }
+}
+
+pub struct Invaders {
+    pub cpu: Cpu,
+    pub io: Rc<RefCell<Io>>,
+    pub frame: u64,
+    // Cycle count at which the next interrupt is due.
+    next_interrupt: u64,
+    mid_screen: bool,
+}
+
+impl Invaders {
+    pub fn new(rom: &[u8]) -> Self {
+        let mut cpu = Cpu::new();
+        cpu.ram.load_at(0, &rom[..rom.len().min(ROM_SIZE)]);
+        cpu.ram.protect(0..ROM_SIZE);
+        let io = Rc::new(RefCell::new(Io::new()));
+        cpu.attach(0..=6, &io);
+        Self {
+            cpu,
+            io,
+            frame: 0,
+            next_interrupt: FRAME_CYCLES / 2,
+            mid_screen: true,
+        }
+    }
+
+    // Either a single 8K image or a directory holding invaders.h/g/f/e.
+    pub fn load_rom(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            return fs::read(path);
+        }
+        let mut rom = Vec::new();
+        for name in ROM_FILES {
+            rom.extend(fs::read(path.join(name))?);
+        }
+        Ok(rom)
+    }
+
+    pub fn press(&mut self, button: Button) {
+        let (port, mask) = button.wiring();
+        self.io.borrow_mut().inputs[port] |= mask;
+    }
+
+    pub fn release(&mut self, button: Button) {
+        let (port, mask) = button.wiring();
+        self.io.borrow_mut().inputs[port] &= !mask;
+    }
+
+    // Runs to the end of the current frame, raising both interrupts.
+    pub fn run_frame(&mut self) -> Result<(), Error> {
+        loop {
+            while self.cpu.cycles < self.next_interrupt {
+                if self.cpu.halted {
+                    self.cpu.cycles = self.next_interrupt;
+                } else {
+                    self.cpu.next()?;
+                }
+            }
+            let vblank = !self.mid_screen;
+            self.cpu.interrupt(&[if vblank { RST2 } else { RST1 }])?;
+            self.mid_screen = vblank;
+            self.next_interrupt += FRAME_CYCLES / 2;
+            if vblank {
+                self.frame += 1;
+                return Ok(());
+            }
+        }
+    }
+
+    // Runs `frames` frames, applying each script action at the start of
+    // the frame it names.
+    pub fn run(&mut self, frames: u64, script: &Script) -> Result<(), Box<dyn std::error::Error>> {
+        let (start, end) = (self.frame, self.frame + frames);
+        let mut actions = script.actions.iter().skip_while(|(f, _)| *f < start).peekable();
+        while self.frame < end {
+            let frame = self.frame;
+            while let Some((_, action)) = actions.next_if(|(f, _)| *f <= frame) {
+                match action {
+                    Action::Press(button) => self.press(*button),
+                    Action::Release(button) => self.release(*button),
+                    Action::Dump(path) => self.dump(path)?,
+                }
+            }
+            self.run_frame()?;
+        }
+        Ok(())
+    }
+
+    // Pixel at (x, y) on the upright screen, (0, 0) being top left.
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        // Video RAM holds 224 lines of 32 bytes, scanned bottom to top
+        // on the rotated monitor, least significant bit first.
+        let bit = HEIGHT - 1 - y;
+        let byte = self.cpu.ram.load_byte(VRAM + (x * 32 + bit / 8) as u16);
+        byte & 1 << (bit % 8) != 0
+    }
+
+    // Binary PBM, which most image tools read.
+    pub fn write_pbm(&self, w: &mut dyn Write) -> io::Result<()> {
+        write!(w, "P4\n{} {}\n", WIDTH, HEIGHT)?;
+        for y in 0..HEIGHT {
+            let row = (0..WIDTH / 8)
+                .map(|i| (0..8).fold(0u8, |acc, b| acc << 1 | self.pixel(i * 8 + b, y) as u8))
+                .collect::<Vec<_>>();
+            w.write_all(&row)?;
+        }
+        Ok(())
+    }
+
+    pub fn dump(&self, path: &str) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_pbm(&mut w)?;
+        w.flush()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Press(Button),
+    Release(Button),
+    Dump(String),
+}
+
+// Timed inputs, one per line: `FRAME press|release BUTTON` or
+// `FRAME dump FILE.pbm`. `#` starts a comment.
+#[derive(Debug, Default)]
+pub struct Script {
+    actions: Vec<(u64, Action)>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut actions = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap();
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            if tokens.is_empty() {
+                continue;
+            }
+            let bad = || Error::BadScript(i + 1);
+            let [frame, verb, arg] = tokens[..] else {
+                return Err(bad());
+            };
+            let frame = frame.parse().map_err(|_| bad())?;
+            let action = match verb {
+                "press" => Action::Press(Button::from_name(arg).ok_or_else(bad)?),
+                "release" => Action::Release(Button::from_name(arg).ok_or_else(bad)?),
+                "dump" => Action::Dump(arg.to_string()),
+                _ => return Err(bad()),
+            };
+            actions.push((frame, action));
+        }
+        // Stable, so actions on the same frame keep their order.
+        actions.sort_by_key(|(frame, _)| *frame);
+        Ok(Self { actions })
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
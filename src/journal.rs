@@ -0,0 +1,86 @@
+#![allow(unused)]
+
+use std::collections::VecDeque;
+
+use crate::z80;
+
+// Register file (and cycle count) snapshot, taken before an instruction runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub flag: u8,
+    pub halted: bool,
+    pub inte: bool,
+    pub ei_delay: bool,
+    pub cycles: u64,
+    pub z80: z80::Regs,     // Z80 only
+}
+
+// Everything needed to undo one instruction.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub regs: Registers,
+    // (address, previous byte), in the order the writes happened.
+    pub writes: Vec<(u16, u8)>,
+}
+
+impl Entry {
+    pub fn wrote(&self, addr: u16) -> bool {
+        self.writes.iter().any(|&(a, _)| a == addr)
+    }
+}
+
+// Ring buffer of the most recent instructions; the oldest entry is dropped
+// once capacity is reached.
+#[derive(Debug)]
+pub struct Journal {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+
+    pub fn last(&self) -> Option<&Entry> {
+        self.entries.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,56 @@
+#![allow(unused)]
+
+use std::collections::BTreeMap;
+
+use crate::utils::parse_hex;
+
+// Source line <-> address mapping taken from an assembler listing, where
+// listing line N shows source line N with its address in front.
+#[derive(Debug, Default)]
+pub struct LineMap {
+    lines: BTreeMap<usize, u16>,
+    addrs: BTreeMap<u16, usize>,
+}
+
+impl LineMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // CP/M ASM style `.PRN`: ` 0100 C3B201    START:  JMP INIT`. EQU lines
+    // (`0005 =`) hold values, not addresses, and are skipped.
+    pub fn parse_listing(text: &str) -> Self {
+        let mut map = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let tokens = line.split_whitespace().take(2).collect::<Vec<_>>();
+            let Some(addr) = tokens.first().filter(|t| t.len() == 4).and_then(|t| parse_hex(t)) else {
+                continue;
+            };
+            let code = match tokens.get(1) {
+                Some(&"=") => continue,
+                Some(t) => t.len().is_multiple_of(2) && t.chars().all(|c| c.is_ascii_hexdigit()),
+                None => false,
+            };
+            map.lines.insert(i + 1, addr);
+            // Prefer the line that emitted code over labels and ORGs.
+            if code || !map.addrs.contains_key(&addr) {
+                map.addrs.insert(addr, i + 1);
+            }
+        }
+        map
+    }
+
+    // The address of `line`, or of the first mapped line after it. Returns
+    // the line actually used along with the address.
+    pub fn addr_of(&self, line: usize) -> Option<(usize, u16)> {
+        self.lines.range(line..).next().map(|(&l, &a)| (l, a))
+    }
+
+    pub fn line_of(&self, addr: u16) -> Option<usize> {
+        self.addrs.get(&addr).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+}
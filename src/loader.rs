@@ -0,0 +1,102 @@
+#![allow(unused)]
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::Error;
+
+// A contiguous run of bytes to place at an address.
+pub type Segment = (u16, Vec<u8>);
+
+// `.HEX` files are read as Intel HEX, `.RK`/`.RKR`/`.GAM` as Radio-86RK
+// tape images, anything else as a CP/M `.COM` image loaded at 0x0100.
+pub fn read_image(path: impl AsRef<Path>) -> Result<Vec<Segment>, Box<dyn std::error::Error>> {
+    read_image_at(path, 0x0100)
+}
+
+// As `read_image`, with raw binaries placed at `org`.
+pub fn read_image_at(path: impl AsRef<Path>, org: u16) -> Result<Vec<Segment>, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    if ext == "hex" {
+        Ok(parse_ihex(&String::from_utf8_lossy(&data))?)
+    } else if ["rk", "rkr", "gam"].contains(&ext.as_str()) {
+        Ok(vec![parse_rk(&data)?])
+    } else {
+        let segments = vec![(org, data)];
+        check_fits(&segments)?;
+        Ok(segments)
+    }
+}
+
+// Segments must end by FFFFH; memory does not wrap around for an image.
+fn check_fits(segments: &[Segment]) -> Result<(), Error> {
+    match segments.iter().find(|(addr, data)| *addr as usize + data.len() > 0x10000) {
+        Some((addr, _)) => Err(Error::PastEndOfMemory(*addr)),
+        None => Ok(()),
+    }
+}
+
+// Lowest and highest address covered by the segments.
+pub fn extent(segments: &[Segment]) -> Option<(u16, u16)> {
+    let start = segments.iter().filter(|(_, d)| !d.is_empty()).map(|(a, _)| *a).min()?;
+    let end = segments.iter()
+        .filter(|(_, d)| !d.is_empty())
+        .map(|(a, d)| (*a as usize + d.len() - 1).min(0xffff) as u16)
+        .max()?;
+    Some((start, end))
+}
+
+// Radio-86RK tape image: an optional E6H sync byte, start and end
+// addresses big endian, the bytes in between, then a trailer with the
+// checksum, which is not checked.
+pub fn parse_rk(data: &[u8]) -> Result<Segment, Error> {
+    let data = data.strip_prefix(&[0xe6]).unwrap_or(data);
+    let [s1, s0, e1, e0, body @ ..] = data else {
+        return Err(Error::BadTapeImage);
+    };
+    let (start, end) = (u16::from_be_bytes([*s1, *s0]), u16::from_be_bytes([*e1, *e0]));
+    let len = end.checked_sub(start).ok_or(Error::BadTapeImage)? as usize + 1;
+    let body = body.get(..len).ok_or(Error::BadTapeImage)?;
+    Ok((start, body.to_vec()))
+}
+
+// Data (00) and end of file (01) records; the other record types only
+// matter above 64K and are skipped.
+pub fn parse_ihex(text: &str) -> Result<Vec<Segment>, Error> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bad = || Error::BadHexRecord(i + 1);
+        let hex = line.strip_prefix(':').ok_or_else(bad)?;
+        if !hex.is_ascii() || hex.len() < 10 || !hex.len().is_multiple_of(2) {
+            return Err(bad());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|j| u8::from_str_radix(&hex[j..j + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| bad())?;
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 || bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b)) != 0 {
+            return Err(bad());
+        }
+        let addr = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let data = &bytes[4..4 + len];
+        match bytes[3] {
+            0x00 => match segments.last_mut() {
+                // Extend the previous segment when the records are contiguous.
+                Some((start, prev)) if *start as usize + prev.len() == addr as usize => prev.extend_from_slice(data),
+                _ => segments.push((addr, data.to_vec())),
+            },
+            0x01 => break,
+            _ => (),
+        }
+    }
+    check_fits(&segments)?;
+    Ok(segments)
+}
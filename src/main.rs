@@ -1,10 +1,23 @@
 #![allow(unused)]
 
+use std::cell::RefCell;
 use std::io;
 use std::io::prelude::*;
 use std::fs::File;
+use std::net::TcpListener;
+use std::ops::Range;
+use std::rc::Rc;
 
-use cpu::Cpu;
+use cpu::{Cpu, PowerOn, Variant};
+use altair::Altair;
+use debugger::Debugger;
+use invaders::{Invaders, Script};
+use ppi::{PinScript, Ppi};
+use coverage::Coverage;
+use profiler::Profiler;
+use serial::Line;
+use symbols::Symbols;
+use usart::Usart;
 
 mod cpu;
 mod dram;
@@ -13,26 +26,397 @@ mod instruction;
 mod clock_cycles;
 mod error;
 mod utils;
+mod journal;
+mod debugger;
+mod profiler;
+mod disasm;
+mod coverage;
+mod symbols;
+mod gdb;
+mod json;
+mod loader;
+mod linemap;
+mod dap;
+mod invaders;
+mod altair;
+mod dcdd;
+mod serial;
+mod usart;
+mod interrupt;
+mod pit;
+mod pic;
+mod ppi;
+mod dma;
+mod i8085;
+mod rk86;
+mod bus;
+mod blocks;
+mod bench;
+mod z80;
+mod flags;
 mod test_instr;
+mod test_debugger;
+mod test_profiler;
+mod test_coverage;
+mod test_symbols;
+mod test_gdb;
+mod test_dap;
+mod test_invaders;
+mod test_altair;
+mod test_dcdd;
+mod test_usart;
+mod test_pit;
+mod test_pic;
+mod test_ppi;
+mod test_dma;
+mod test_serial;
+mod test_i8085;
+mod test_vm80a;
+mod test_z80;
+mod test_flags;
+mod test_blocks;
+mod test_bus;
+mod test_wait;
+mod test_reset;
+
+const GDB_PORT: u16 = 1234;
+const INVADERS_FRAMES: u64 = 600;
+const ALTAIR_MEMORY: usize = 64;  // KB
+
+fn usage() {
+    eprintln!("Usage: i8080 [image-file]");
+    eprintln!("       i8080 debug [image-file]");
+    eprintln!("       i8080 profile [image-file]");
+    eprintln!("       i8080 coverage [image-file]");
+    eprintln!("       i8080 gdb [image-file]          (GDB remote protocol on TCP)");
+    eprintln!("       i8080 gdb-stdio [image-file]    (GDB remote protocol on stdin/stdout)");
+    eprintln!("       i8080 dap                       (Debug Adapter Protocol on stdin/stdout)");
+    eprintln!("       i8080 bench                     (decoder and workload benchmarks)");
+    eprintln!("       i8080 invaders [rom-file|rom-dir]");
+    eprintln!("       i8080 altair [image-file]       (binary loaded at 0000H, or .HEX)");
+    eprintln!("       i8080 rk86 [image-file]         (Radio-86RK .RK tape image or binary at 0000H, monitor");
+    eprintln!("                                        output calls only; KR580VM80A unless --cpu is given)");
+    eprintln!("Images: .COM loaded at 0100H, or Intel .HEX");
+    eprintln!("Options: --cpu [8080|8085|580|z80]  (default 8080; 580 is the KR580VM80A)");
+    eprintln!("         --sym [symbol-file]   (.SYM, .PRN or `label = addr` text, repeatable)");
+    eprintln!("         --port [port]         (gdb, default {GDB_PORT})");
+    eprintln!("         --frames [n]          (invaders, default {INVADERS_FRAMES})");
+    eprintln!("         --script [file]       (invaders, timed inputs and screen dumps)");
+    eprintln!("         --memory [KB]         (altair, default {ALTAIR_MEMORY})");
+    eprintln!("         --switches [hex]      (altair sense switches, default 00)");
+    eprintln!("         --disk [file]         (altair 88-DCDD .dsk image, repeatable for drives 0, 1, ...)");
+    eprintln!("         --start [hex]         (altair start address, default 0000)");
+    eprintln!("         --usart [port][:dev]  (8251 at port/port+1 on stdio, a new PTY (dev = pty), a device");
+    eprintln!("                 [port]:in:out  node, or input and output files)");
+    eprintln!("         --console [dev]       (altair 2SIO line: pty, a device node or in:out files, default stdio)");
+    eprintln!("         --ppi [port][:script] (8255 at port..port+3, its pins driven and checked by a timed script)");
+    eprintln!("         --wait [start-end]:[n] (n wait states on every access to start..end, hex, repeatable)");
+    eprintln!("         --power-on [pattern]  (registers and RAM at power on: zeros, ones or random[:seed], default zeros)");
+    eprintln!("         --engine [name]       (run mode: interp, or blocks for the basic-block translation cache)");
+}
+
+// Nothing for the host terminal, `pty` for a new pseudo-terminal, a
+// device node, or `input:output` files.
+fn open_line(fields: &[&str]) -> Result<Line, String> {
+    let line = match fields {
+        [] => Ok(Line::stdio()),
+        ["pty"] => Line::pty().map(|(line, path)| {
+            eprintln!("serial line on {}", path.display());
+            line
+        }),
+        [device] => Line::open(device),
+        [input, output] => Line::files(input, output),
+        _ => return Err("expected pty, a device, or input:output".to_string()),
+    };
+    line.map_err(|e| e.to_string())
+}
+
+// `port`, `port:pty`, `port:device` or `port:input:output`.
+fn open_usart(spec: &str) -> Result<(u8, Usart), String> {
+    let fields = spec.split(':').collect::<Vec<_>>();
+    let port = utils::parse_hex(fields[0])
+        .and_then(|p| u8::try_from(p).ok())
+        .ok_or_else(|| "bad port".to_string())?;
+    open_line(&fields[1..]).map(|line| (port, Usart::new(line)))
+}
+
+// `port` or `port:script`.
+fn open_ppi(spec: &str) -> Result<(u8, Ppi), String> {
+    let (port, script) = spec.split_once(':').map_or((spec, None), |(p, s)| (p, Some(s)));
+    let port = utils::parse_hex(port)
+        .and_then(|p| u8::try_from(p).ok())
+        .ok_or_else(|| "bad port".to_string())?;
+    let mut ppi = Ppi::new();
+    if let Some(path) = script {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ppi.run_script(PinScript::parse(&text).map_err(|e| e.to_string())?);
+    }
+    Ok((port, ppi))
+}
+
+// `start-end:n`, the range inclusive and in hex.
+fn parse_wait(spec: &str) -> Result<(Range<usize>, u8), String> {
+    let (range, states) = spec.split_once(':').ok_or_else(|| "expected start-end:n".to_string())?;
+    let (start, end) = range.split_once('-').ok_or_else(|| "expected start-end:n".to_string())?;
+    let start = utils::parse_hex(start).ok_or_else(|| "bad address".to_string())?;
+    let end = utils::parse_hex(end).filter(|&end| end >= start).ok_or_else(|| "bad address".to_string())?;
+    let states = states.parse().map_err(|_| "bad wait state count".to_string())?;
+    Ok((start as usize..end as usize + 1, states))
+}
 
 fn main() {
-    let mut args = std::env::args().collect::<Vec<_>>();
-    if args.len() != 2 {
-        eprintln!("Usage: i8080 [image-file]");
-        return;
+    let mut args = Vec::new();
+    let mut symbols = Symbols::new();
+    let mut port = GDB_PORT;
+    let mut frames = INVADERS_FRAMES;
+    let mut script = Script::new();
+    let mut memory = ALTAIR_MEMORY;
+    let mut switches = 0u8;
+    let mut disks = Vec::new();
+    let mut start = 0u16;
+    let mut usart = None;
+    let mut ppi = None;
+    let mut console = None;
+    let mut variant = None;
+    let mut waits = Vec::new();
+    let mut power_on = PowerOn::Zeros;
+    let mut blocks = false;
+    let mut argv = std::env::args();
+    while let Some(arg) = argv.next() {
+        if !["--sym", "--port", "--frames", "--script", "--memory", "--switches", "--disk", "--start", "--usart", "--ppi", "--console", "--cpu", "--wait", "--power-on", "--engine"].contains(&arg.as_str()) {
+            args.push(arg);
+            continue;
+        }
+        let Some(value) = argv.next() else {
+            usage();
+            return;
+        };
+        let res = match arg.as_str() {
+            "--cpu" => Variant::from_name(&value)
+                .map(|v| variant = Some(v))
+                .ok_or_else(|| "expected 8080, 8085, 580 or z80".to_string()),
+            "--port" => value.parse().map(|p| port = p).map_err(|e| e.to_string()),
+            "--frames" => value.parse().map(|n| frames = n).map_err(|e| e.to_string()),
+            "--memory" => value.parse()
+                .ok()
+                .filter(|kb| (1..=64).contains(kb))
+                .map(|kb| memory = kb)
+                .ok_or_else(|| "memory size must be 1 to 64 KB".to_string()),
+            "--switches" => u8::from_str_radix(&value, 16).map(|s| switches = s).map_err(|e| e.to_string()),
+            "--disk" => {
+                disks.push(value.clone());
+                Ok(())
+            },
+            "--start" => utils::parse_hex(&value).map(|a| start = a).ok_or_else(|| "bad address".to_string()),
+            "--usart" => open_usart(&value).map(|u| usart = Some(u)),
+            "--ppi" => open_ppi(&value).map(|p| ppi = Some(p)),
+            "--wait" => parse_wait(&value).map(|w| waits.push(w)),
+            "--power-on" => PowerOn::from_name(&value)
+                .map(|p| power_on = p)
+                .ok_or_else(|| "expected zeros, ones or random[:seed]".to_string()),
+            "--engine" => match value.as_str() {
+                "interp" | "blocks" => {
+                    blocks = value == "blocks";
+                    Ok(())
+                },
+                _ => Err("expected interp or blocks".to_string()),
+            },
+            "--console" => open_line(&value.split(':').collect::<Vec<_>>()).map(|l| console = Some(l)),
+            "--script" => std::fs::read_to_string(&value)
+                .map_err(|e| e.to_string())
+                .and_then(|text| Script::parse(&text).map_err(|e| e.to_string()))
+                .map(|s| script = s),
+            _ => symbols.load(&value).map(|_| ()).map_err(|e| e.to_string()),
+        };
+        if let Err(e) = res {
+            eprintln!("Error: {value}: {e}");
+            return;
+        }
+    }
+    let (mode, path) = match args.as_slice() {
+        // The program comes with the launch request.
+        [_, mode] if mode == "dap" => {
+            if let Err(e) = dap::serve(io::stdin(), io::stdout()) {
+                eprintln!("Error: {e}");
+            }
+            return;
+        },
+        [_, mode] if mode == "bench" => {
+            if let Err(e) = bench::run(&mut io::stdout()) {
+                eprintln!("Error: {e}");
+            }
+            return;
+        },
+        [_, path] => ("run", path),
+        [_, mode, path] => (mode.as_str(), path),
+        _ => {
+            usage();
+            return;
+        },
     };
 
-    let mut f;
-    match File::open(&args[1]) {
-        Ok(file) => f = file,
-        Err(e) => {
+    if mode == "invaders" {
+        let res = Invaders::load_rom(path)
+            .map_err(Box::<dyn std::error::Error>::from)
+            .and_then(|rom| {
+                let mut machine = Invaders::new(&rom);
+                machine.run(frames, &script)?;
+                let screen = format!("{path}.pbm");
+                machine.dump(&screen)?;
+                println!("{} frames run, screen written to {screen}", machine.frame);
+                Ok(())
+            });
+        if let Err(e) = res {
+            eprintln!("Error: {e}");
+        }
+        return;
+    }
+
+    if mode == "altair" {
+        let res = loader::read_image_at(path, 0x0000).and_then(|segments| {
+            let mut altair = Altair::new(memory * 1024, console.unwrap_or_else(Line::stdio));
+            altair.cpu.variant = variant.unwrap_or_default();
+            altair.cpu.power_on(power_on);
+            altair.load(&segments);
+            altair.panel.borrow_mut().switches = switches;
+            for (drive, disk) in disks.iter().enumerate().take(dcdd::DRIVES) {
+                altair.disk.borrow_mut().insert(drive, disk).map_err(|e| format!("{disk}: {e}"))?;
+            }
+            altair.cpu.pc = start;
+            for (range, states) in &waits {
+                altair.cpu.ram.set_wait_states(range.clone(), *states);
+            }
+            altair.run()?;
+            Ok(altair.disk.borrow_mut().flush()?)
+        });
+        if let Err(e) = res {
+            eprintln!("Error: {e}");
+        }
+        return;
+    }
+
+    if mode == "rk86" {
+        let res = loader::read_image_at(path, 0x0000).and_then(|segments| {
+            let mut cpu = Cpu::with_variant(variant.unwrap_or(Variant::Kr580Vm80a));
+            cpu.power_on(power_on);
+            for (addr, data) in &segments {
+                cpu.ram.load_at(*addr, data);
+            }
+            for (range, states) in &waits {
+                cpu.ram.set_wait_states(range.clone(), *states);
+            }
+            let start = segments.first().map_or(0, |(addr, _)| *addr);
+            rk86::run_with(&mut cpu, start, &mut io::stdout(), |cpu| cpu.next())?;
+            Ok(())
+        });
+        if let Err(e) = res {
             eprintln!("Error: {e}");
+        }
+        return;
+    }
+
+    let segments = match loader::read_image(path) {
+        Ok(segments) => segments,
+        Err(e) => {
+            eprintln!("Error: {path}: {e}");
             return;
         },
     };
-    let mut image = Vec::new();
-    f.read_to_end(&mut image).unwrap();
-
-    let mut cpu = Cpu::new().load(&image);
-    cpu.test().unwrap();
+    let mut cpu = Cpu::with_variant(variant.unwrap_or_default());
+    cpu.power_on(power_on);
+    for (addr, data) in &segments {
+        cpu.ram.load_at(*addr, data);
+    }
+    for (range, states) in &waits {
+        cpu.ram.set_wait_states(range.clone(), *states);
+    }
+    eprintln!("image loaded.");
+    if let Some((port, usart)) = usart {
+        cpu.attach([port, port.wrapping_add(1)], &Rc::new(RefCell::new(usart)));
+    }
+    let ppi = ppi.map(|(port, ppi)| {
+        let ppi = Rc::new(RefCell::new(ppi));
+        cpu.attach((0..4).map(|i| port.wrapping_add(i)), &ppi);
+        cpu.clock(&ppi);
+        ppi
+    });
+    match mode {
+        "run" if blocks => {
+            cpu.translate_blocks();
+            cpu.run_cpm_with(|cpu| cpu.next_block()).unwrap();
+        },
+        "run" => cpu.test().unwrap(),
+        "debug" => {
+            cpu.prepare_cpm();
+            let mut debugger = Debugger::new(cpu);
+            debugger.cpm = true;
+            debugger.symbols = symbols;
+            if let Err(e) = debugger.run_cli(io::stdin().lock()) {
+                eprintln!("Error: {e}");
+            }
+        },
+        "profile" => {
+            let mut profiler = Profiler::new();
+            if let Err(e) = cpu.run_cpm_with(|cpu| profiler.step(cpu)) {
+                eprintln!("Error: {e}");
+            }
+            profiler.finish();
+            let report = format!("{path}.prof");
+            let folded = format!("{path}.folded");
+            let res = File::create(&report)
+                .and_then(|mut f| profiler.write_report(&mut f, &symbols))
+                .and_then(|_| File::create(&folded))
+                .and_then(|mut f| profiler.write_folded(&mut f, &symbols));
+            match res {
+                Ok(()) => println!("profile written to {report} and {folded}"),
+                Err(e) => eprintln!("Error: {e}"),
+            }
+        },
+        "coverage" => {
+            let mut coverage = Coverage::new();
+            if let Err(e) = cpu.run_cpm_with(|cpu| coverage.step(cpu)) {
+                eprintln!("Error: {e}");
+            }
+            let (start, end) = loader::extent(&segments).unwrap_or((0x0100, 0x0100));
+            let mut ranges = vec![(start, end)];
+            ranges.extend((start..=end).step_by(0x100).map(|page| (page, end.min(page | 0xff))));
+            let listing = format!("{path}.lst");
+            let summary = format!("{path}.cov");
+            let res = File::create(&listing)
+                .and_then(|mut f| coverage.write_listing(&mut f, &cpu.ram, start, end, &symbols))
+                .and_then(|_| File::create(&summary))
+                .and_then(|mut f| coverage.write_summary(&mut f, &ranges));
+            match res {
+                Ok(()) => println!("coverage written to {listing} and {summary}"),
+                Err(e) => eprintln!("Error: {e}"),
+            }
+        },
+        "gdb" | "gdb-stdio" => {
+            cpu.prepare_cpm();
+            let mut debugger = Debugger::new(cpu);
+            debugger.cpm = true;
+            debugger.symbols = symbols;
+            let res = if mode == "gdb" {
+                TcpListener::bind(("127.0.0.1", port))
+                    .and_then(|listener| {
+                        eprintln!("waiting for gdb on 127.0.0.1:{port}");
+                        listener.accept()
+                    })
+                    .and_then(|(stream, _)| gdb::serve(debugger, stream.try_clone()?, stream))
+            } else {
+                // stdout carries the protocol, so program output goes to stderr.
+                debugger.console = Box::new(io::stderr());
+                gdb::serve(debugger, io::stdin(), io::stdout())
+            };
+            if let Err(e) = res {
+                eprintln!("Error: {e}");
+            }
+        },
+        _ => usage(),
+    }
+    if let Some(ppi) = ppi {
+        for failure in ppi.borrow().failures() {
+            eprintln!("pins: {failure}");
+        }
+    }
 }
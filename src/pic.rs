@@ -0,0 +1,262 @@
+#![allow(unused)]
+
+// Intel 8259A programmable interrupt controller in 8080 mode. On
+// acknowledge it puts a CALL on the bus, to ICW2:ICW1 with the level
+// folded in at an interval of 4 or 8 bytes.
+//
+// A0=0: ICW1, OCW2, OCW3 (out); IRR, ISR or poll (in)
+// A0=1: ICW2-ICW4 during initialization, else IMR
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::device::PortDevice;
+use crate::interrupt::InterruptSource;
+
+const CALL: u8 = 0xcd;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    Icw2,
+    Icw3,
+    Icw4,
+    Ocw,
+}
+
+#[derive(Debug)]
+pub struct Pic {
+    expect: Expect,
+    icw1: u8,
+    icw2: u8,
+    icw4: u8,
+    pub irr: u8,
+    pub isr: u8,
+    pub imr: u8,
+    // Input levels, for edge detection.
+    lines: u8,
+    // The level with the lowest priority; the next one up has the highest.
+    lowest: u8,
+    rotate_on_aeoi: bool,
+    special_mask: bool,
+    read_isr: bool,
+    poll: bool,
+}
+
+impl Pic {
+    pub fn new() -> Self {
+        Self {
+            expect: Expect::Ocw,
+            icw1: 0,
+            icw2: 0,
+            icw4: 0,
+            irr: 0,
+            isr: 0,
+            imr: 0,
+            lines: 0,
+            lowest: 7,
+            rotate_on_aeoi: false,
+            special_mask: false,
+            read_isr: false,
+            poll: false,
+        }
+    }
+
+    fn level_triggered(&self) -> bool {
+        self.icw1 & 0x08 != 0
+    }
+
+    fn auto_eoi(&self) -> bool {
+        self.icw4 & 0x02 != 0
+    }
+
+    // Drives IR`line`. Edge triggered mode latches a request on the rising
+    // edge; level triggered mode requests for as long as the line is high.
+    pub fn set_irq(&mut self, line: u8, level: bool) {
+        let bit = 1 << (line & 7);
+        if level {
+            if self.lines & bit == 0 || self.level_triggered() {
+                self.irr |= bit;
+            }
+            self.lines |= bit;
+        } else {
+            self.lines &= !bit;
+            if self.level_triggered() {
+                self.irr &= !bit;
+            }
+        }
+    }
+
+    // A handle for a device to drive IR`line` with, e.g. `Pit::connect`.
+    pub fn irq(pic: &Rc<RefCell<Pic>>, line: u8) -> impl FnMut(bool) + 'static {
+        let pic = pic.clone();
+        move |level| pic.borrow_mut().set_irq(line, level)
+    }
+
+    // Levels from highest to lowest priority.
+    fn by_priority(&self) -> impl Iterator<Item = u8> {
+        let first = (self.lowest + 1) & 7;
+        (0..8).map(move |i| (first + i) & 7)
+    }
+
+    // The request that would be serviced next, if any. Normally anything
+    // in service blocks its own level and those below; special mask mode
+    // leaves only the IMR and the level itself in the way.
+    fn pending(&self) -> Option<u8> {
+        let requests = self.irr & !self.imr;
+        for level in self.by_priority() {
+            let bit = 1 << level;
+            if self.isr & bit != 0 && !self.special_mask {
+                return None;
+            }
+            if requests & bit != 0 && self.isr & bit == 0 {
+                return Some(level);
+            }
+        }
+        None
+    }
+
+    fn highest_in_service(&self) -> Option<u8> {
+        self.by_priority().find(|&level| self.isr & 1 << level != 0)
+    }
+
+    // Puts `level` in service, as an INTA sequence does.
+    fn accept(&mut self, level: u8) {
+        let bit = 1 << level;
+        if !self.level_triggered() {
+            self.irr &= !bit;
+        }
+        if self.auto_eoi() {
+            if self.rotate_on_aeoi {
+                self.lowest = level;
+            }
+        } else {
+            self.isr |= bit;
+        }
+    }
+
+    fn vector(&self, level: u8) -> u16 {
+        let low = if self.icw1 & 0x04 != 0 {
+            self.icw1 & 0xe0 | level << 2
+        } else {
+            self.icw1 & 0xc0 | level << 3
+        };
+        (self.icw2 as u16) << 8 | low as u16
+    }
+
+    fn command(&mut self, byte: u8) {
+        if byte & 0x10 != 0 {
+            // ICW1 restarts initialization.
+            self.icw1 = byte;
+            self.icw4 = 0;
+            self.imr = 0;
+            self.isr = 0;
+            self.irr = 0;
+            self.lowest = 7;
+            self.special_mask = false;
+            self.read_isr = false;
+            self.expect = Expect::Icw2;
+            return;
+        }
+        if byte & 0x08 != 0 {
+            // OCW3
+            if byte & 0x02 != 0 {
+                self.read_isr = byte & 0x01 != 0;
+            }
+            if byte & 0x40 != 0 {
+                self.special_mask = byte & 0x20 != 0;
+            }
+            self.poll = byte & 0x04 != 0;
+            return;
+        }
+        // OCW2
+        let level = byte & 7;
+        match byte >> 5 {
+            0b001 => self.eoi(None, false),
+            0b011 => self.eoi(Some(level), false),
+            0b101 => self.eoi(None, true),
+            0b111 => self.eoi(Some(level), true),
+            0b100 => self.rotate_on_aeoi = true,
+            0b000 => self.rotate_on_aeoi = false,
+            0b110 => self.lowest = level,
+            _ => (),
+        }
+    }
+
+    fn eoi(&mut self, level: Option<u8>, rotate: bool) {
+        let Some(level) = level.or_else(|| self.highest_in_service()) else {
+            return;
+        };
+        self.isr &= !(1 << level);
+        if rotate {
+            self.lowest = level;
+        }
+    }
+
+    fn init(&mut self, byte: u8) {
+        let single = self.icw1 & 0x02 != 0;
+        let icw4 = self.icw1 & 0x01 != 0;
+        self.expect = match self.expect {
+            Expect::Icw2 => {
+                self.icw2 = byte;
+                if !single {
+                    Expect::Icw3
+                } else if icw4 {
+                    Expect::Icw4
+                } else {
+                    Expect::Ocw
+                }
+            },
+            // Cascading is not modelled.
+            Expect::Icw3 if icw4 => Expect::Icw4,
+            Expect::Icw3 => Expect::Ocw,
+            _ => {
+                self.icw4 = byte;
+                Expect::Ocw
+            },
+        };
+    }
+}
+
+impl PortDevice for Pic {
+    fn read_port(&mut self, port: u8) -> u8 {
+        if port & 1 != 0 {
+            return self.imr;
+        }
+        if std::mem::take(&mut self.poll) {
+            return match self.pending() {
+                Some(level) => {
+                    self.accept(level);
+                    0x80 | level
+                },
+                None => 0,
+            };
+        }
+        if self.read_isr { self.isr } else { self.irr }
+    }
+
+    fn write_port(&mut self, port: u8, byte: u8) {
+        if port & 1 == 0 {
+            self.command(byte);
+        } else if self.expect == Expect::Ocw {
+            self.imr = byte;
+        } else {
+            self.init(byte);
+        }
+    }
+}
+
+impl InterruptSource for Pic {
+    fn requested(&self) -> bool {
+        self.expect == Expect::Ocw && self.pending().is_some()
+    }
+
+    fn acknowledge(&mut self) -> Vec<u8> {
+        let pending = self.pending();
+        if let Some(level) = pending {
+            self.accept(level);
+        }
+        // If the request went away, real parts answer with IR7's vector.
+        let [low, high] = self.vector(pending.unwrap_or(7)).to_le_bytes();
+        vec![CALL, low, high]
+    }
+}
@@ -0,0 +1,369 @@
+#![allow(unused)]
+
+// Intel 8253/8254 programmable interval timer. A1-A0 pick counter 0-2 or
+// the control word register (3). The counters are clocked at the CPU
+// clock divided by `divider`, so timing follows `Cpu::cycles` and not the
+// host.
+//
+// Modes:
+//   0  interrupt on terminal count   3  square wave
+//   1  hardware one-shot             4  software triggered strobe
+//   2  rate generator                5  hardware triggered strobe
+
+use crate::device::{Clocked, PortDevice};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    I8253,
+    // Adds the read-back command and status.
+    I8254,
+}
+
+// Read/write formats of a counter.
+const LATCH: u8 = 0;
+const LSB: u8 = 1;
+const MSB: u8 = 2;
+const WORD: u8 = 3;
+
+#[derive(Default)]
+struct Counter {
+    mode: u8,
+    rw: u8,
+    bcd: bool,
+    // The count register as written, and whether the MSB comes next.
+    written: u16,
+    write_msb: bool,
+    read_msb: bool,
+    latch: Option<u16>,
+    status: Option<u8>,
+    // A count written and not yet loaded into the counting element.
+    pending: bool,
+    null_count: bool,
+    count: u32,
+    counting: bool,
+    gate: bool,
+    triggered: bool,
+    // Modes 4 and 5 strobe only once per count.
+    strobed: bool,
+    out: bool,
+    on_out: Option<Box<dyn FnMut(bool)>>,
+}
+
+impl Counter {
+    fn new() -> Self {
+        Self { gate: true, out: true, ..Default::default() }
+    }
+
+    // 0 counts as the largest value.
+    fn initial(&self) -> u32 {
+        if self.bcd {
+            let n = (0..4).fold(0, |n, i| n * 10 + (self.written >> (12 - 4 * i) & 0xf) as u32 % 10);
+            if n == 0 { 10_000 } else { n }
+        } else if self.written == 0 {
+            0x10000
+        } else {
+            self.written as u32
+        }
+    }
+
+    // The counting element as read back.
+    fn value(&self) -> u16 {
+        if self.bcd {
+            let n = self.count % 10_000;
+            (0..4).fold(0, |v, i| v | ((n / 10u32.pow(i) % 10) as u16) << (4 * i))
+        } else {
+            self.count as u16
+        }
+    }
+
+    fn set_out(&mut self, level: bool) {
+        if self.out != level {
+            self.out = level;
+            if let Some(f) = &mut self.on_out {
+                f(level);
+            }
+        }
+    }
+
+    fn control(&mut self, word: u8) {
+        self.rw = word >> 4 & 3;
+        self.mode = match word >> 1 & 7 {
+            6 => 2,
+            7 => 3,
+            mode => mode,
+        };
+        self.bcd = word & 1 != 0;
+        self.write_msb = false;
+        self.read_msb = false;
+        self.latch = None;
+        self.status = None;
+        self.pending = false;
+        self.counting = false;
+        self.null_count = true;
+        self.set_out(self.mode != 0);
+    }
+
+    fn write(&mut self, byte: u8) {
+        match self.rw {
+            LSB => self.written = byte as u16,
+            MSB => self.written = (byte as u16) << 8,
+            _ if !self.write_msb => {
+                self.written = self.written & 0xff00 | byte as u16;
+                self.write_msb = true;
+                // Mode 0 stops counting as soon as the LSB arrives.
+                if self.mode == 0 {
+                    self.counting = false;
+                    self.set_out(false);
+                }
+                return;
+            },
+            _ => {
+                self.written = self.written & 0x00ff | (byte as u16) << 8;
+                self.write_msb = false;
+            },
+        }
+        self.pending = true;
+        self.null_count = true;
+        match self.mode {
+            0 => self.set_out(false),
+            4 => self.strobed = false,
+            _ => (),
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        if let Some(status) = self.status.take() {
+            return status;
+        }
+        let value = self.latch.unwrap_or_else(|| self.value());
+        let byte = match self.rw {
+            MSB => (value >> 8) as u8,
+            WORD if self.read_msb => (value >> 8) as u8,
+            _ => value as u8,
+        };
+        if self.rw == WORD {
+            self.read_msb = !self.read_msb;
+        }
+        if self.rw != WORD || !self.read_msb {
+            self.latch = None;
+        }
+        byte
+    }
+
+    fn latch_count(&mut self) {
+        if self.latch.is_none() {
+            self.latch = Some(self.value());
+        }
+    }
+
+    fn latch_status(&mut self) {
+        if self.status.is_none() {
+            let mode = self.mode << 1 | self.bcd as u8;
+            self.status = Some((self.out as u8) << 7 | (self.null_count as u8) << 6 | self.rw << 4 | mode);
+        }
+    }
+
+    fn set_gate(&mut self, level: bool) {
+        if level && !self.gate {
+            self.triggered = true;
+        }
+        self.gate = level;
+        if !level && matches!(self.mode, 2 | 3) {
+            self.set_out(true);
+        }
+    }
+
+    fn load(&mut self) {
+        self.count = self.initial();
+        self.pending = false;
+        self.null_count = false;
+        self.counting = true;
+    }
+
+    // Square wave halves: with an odd count the high half is one clock
+    // longer. Counts go down by two.
+    fn load_half(&mut self) {
+        self.load();
+        if self.count & 1 != 0 {
+            self.count = if self.out { self.count + 1 } else { self.count - 1 };
+        }
+    }
+
+    fn clock(&mut self) {
+        match self.mode {
+            0 | 4 => {
+                if self.mode == 4 && !self.out {
+                    self.set_out(true);
+                }
+                if self.pending {
+                    self.load();
+                    return;
+                }
+                if !self.counting || !self.gate {
+                    return;
+                }
+                self.count -= 1;
+                if self.count == 0 {
+                    self.count = if self.bcd { 10_000 } else { 0x10000 };
+                    if self.mode == 0 {
+                        self.set_out(true);
+                    } else if !self.strobed {
+                        self.strobed = true;
+                        self.set_out(false);
+                    }
+                }
+            },
+            1 | 5 => {
+                if self.mode == 5 && !self.out {
+                    self.set_out(true);
+                }
+                if std::mem::take(&mut self.triggered) {
+                    self.load();
+                    self.strobed = false;
+                    if self.mode == 1 {
+                        self.set_out(false);
+                    }
+                    return;
+                }
+                if !self.counting {
+                    return;
+                }
+                self.count -= 1;
+                if self.count == 0 {
+                    self.count = if self.bcd { 10_000 } else { 0x10000 };
+                    if self.mode == 1 {
+                        self.set_out(true);
+                    } else if !self.strobed {
+                        self.strobed = true;
+                        self.set_out(false);
+                    }
+                }
+            },
+            2 => {
+                if !self.gate {
+                    return;
+                }
+                if (self.pending && !self.counting) || std::mem::take(&mut self.triggered) {
+                    self.load();
+                    return;
+                }
+                if !self.counting {
+                    return;
+                }
+                // A count written meanwhile is picked up at the reload.
+                if self.count == 1 {
+                    self.load();
+                    self.set_out(true);
+                } else {
+                    self.count -= 1;
+                    if self.count == 1 {
+                        self.set_out(false);
+                    }
+                }
+            },
+            _ => {
+                if !self.gate {
+                    return;
+                }
+                if (self.pending && !self.counting) || std::mem::take(&mut self.triggered) {
+                    self.set_out(true);
+                    self.load_half();
+                    return;
+                }
+                if !self.counting {
+                    return;
+                }
+                self.count = self.count.saturating_sub(2);
+                if self.count == 0 {
+                    self.set_out(!self.out);
+                    self.load_half();
+                }
+            },
+        }
+    }
+}
+
+pub struct Pit {
+    model: Model,
+    counters: [Counter; 3],
+    divider: u64,
+    // Counter clocks given so far, once the first tick sets the origin.
+    clocks: Option<u64>,
+}
+
+impl Pit {
+    pub fn new(model: Model, divider: u64) -> Self {
+        Self {
+            model,
+            counters: [Counter::new(), Counter::new(), Counter::new()],
+            divider: divider.max(1),
+            clocks: None,
+        }
+    }
+
+    pub fn out(&self, counter: usize) -> bool {
+        self.counters[counter].out
+    }
+
+    pub fn set_gate(&mut self, counter: usize, level: bool) {
+        self.counters[counter].set_gate(level);
+    }
+
+    // Calls `f` with the new level whenever OUT of `counter` changes.
+    pub fn connect(&mut self, counter: usize, f: impl FnMut(bool) + 'static) {
+        self.counters[counter].on_out = Some(Box::new(f));
+    }
+
+    // Advances every counter by one input clock.
+    pub fn clock(&mut self) {
+        for counter in &mut self.counters {
+            counter.clock();
+        }
+    }
+
+    fn read_back(&mut self, word: u8) {
+        for (i, counter) in self.counters.iter_mut().enumerate() {
+            if word & 2 << i == 0 {
+                continue;
+            }
+            if word & 0x20 == 0 {
+                counter.latch_count();
+            }
+            if word & 0x10 == 0 {
+                counter.latch_status();
+            }
+        }
+    }
+}
+
+impl PortDevice for Pit {
+    fn read_port(&mut self, port: u8) -> u8 {
+        match port & 3 {
+            3 => 0xff,
+            n => self.counters[n as usize].read(),
+        }
+    }
+
+    fn write_port(&mut self, port: u8, byte: u8) {
+        match port & 3 {
+            3 => match byte >> 6 {
+                3 if self.model == Model::I8254 => self.read_back(byte),
+                3 => (),
+                n if byte >> 4 & 3 == LATCH => self.counters[n as usize].latch_count(),
+                n => self.counters[n as usize].control(byte),
+            },
+            n => self.counters[n as usize].write(byte),
+        }
+    }
+}
+
+impl Clocked for Pit {
+    fn tick(&mut self, now: u64) {
+        let target = now / self.divider;
+        let clocks = *self.clocks.get_or_insert(target);
+        for _ in clocks..target {
+            self.clock();
+        }
+        self.clocks = Some(target);
+    }
+}
@@ -0,0 +1,442 @@
+#![allow(unused)]
+
+// Intel 8255 programmable peripheral interface. A1-A0 pick port A, B, C
+// or the control word register (3). The lines facing the outside world
+// are reached through `Pins`, by Rust callbacks or by a `PinScript`.
+//
+// Mode 0 is plain I/O. In mode 1 ports A and B are strobed, and port C
+// lines become handshake signals; port A alone does mode 2, both ways:
+//
+//   A mode 1 in:  PC4 STB  PC5 IBF  PC3 INTR    B mode 1 in:  PC2 STB  PC1 IBF  PC0 INTR
+//   A mode 1 out: PC6 ACK  PC7 OBF  PC3 INTR    B mode 1 out: PC2 ACK  PC1 OBF  PC0 INTR
+//   A mode 2:     PC4 STB  PC5 IBF  PC6 ACK  PC7 OBF  PC3 INTR
+//
+// STB, ACK and OBF are active low. The interrupt enable flip-flops are
+// set and reset through the STB/ACK bits of the bit set/reset command,
+// and read back in their place.
+
+use crate::device::{Clocked, Pins, PortDevice};
+use crate::error::Error;
+
+pub const A: u8 = 0;
+pub const B: u8 = 1;
+pub const C: u8 = 2;
+
+// All ports mode 0 input, as after reset.
+const RESET_MODE: u8 = 0x9b;
+
+type OnChange = Box<dyn FnMut(u8)>;
+type OnRead = Box<dyn FnMut() -> u8>;
+
+pub struct Ppi {
+    control: u8,
+    // Output latches, and the input latches strobed in modes 1 and 2.
+    latch: [u8; 3],
+    input: [u8; 2],
+    external: [u8; 3],
+    ibf: [bool; 2],
+    obf: [bool; 2],
+    inte_a_in: bool,
+    inte_a_out: bool,
+    inte_b: bool,
+    // Line levels as last reported to `on_change`.
+    seen: [u8; 3],
+    on_change: [Option<OnChange>; 3],
+    on_read: [Option<OnRead>; 3],
+    script: Option<PinScript>,
+}
+
+impl Ppi {
+    pub fn new() -> Self {
+        Self {
+            control: RESET_MODE,
+            latch: [0; 3],
+            input: [0; 2],
+            external: [0xff; 3],
+            ibf: [false; 2],
+            obf: [false; 2],
+            inte_a_in: false,
+            inte_a_out: false,
+            inte_b: false,
+            seen: [0xff; 3],
+            on_change: [None, None, None],
+            on_read: [None, None, None],
+            script: None,
+        }
+    }
+
+    // Calls `f` with the line levels of `port` whenever they change. The
+    // callback runs with the PPI borrowed, so it must not touch it.
+    pub fn connect(&mut self, port: u8, f: impl FnMut(u8) + 'static) {
+        self.on_change[port as usize] = Some(Box::new(f));
+    }
+
+    // Has `f` supply the levels driven onto `port` each time the CPU reads
+    // it, e.g. the columns of a keypad scanned through another port.
+    pub fn connect_input(&mut self, port: u8, f: impl FnMut() -> u8 + 'static) {
+        self.on_read[port as usize] = Some(Box::new(f));
+    }
+
+    // Replays `script` as `Cpu::cycles` goes by; see `failures`.
+    pub fn run_script(&mut self, script: PinScript) {
+        self.script = Some(script);
+    }
+
+    pub fn failures(&self) -> &[String] {
+        self.script.as_ref().map_or(&[], |s| &s.failures)
+    }
+
+    fn mode_a(&self) -> u8 {
+        match self.control >> 5 & 3 {
+            0 => 0,
+            1 => 1,
+            _ => 2,
+        }
+    }
+
+    fn mode_b(&self) -> u8 {
+        self.control >> 2 & 1
+    }
+
+    fn a_input(&self) -> bool {
+        self.control & 0x10 != 0
+    }
+
+    fn b_input(&self) -> bool {
+        self.control & 0x02 != 0
+    }
+
+    // Port C lines taken over by handshaking, as (outputs, inputs).
+    fn handshake(&self) -> (u8, u8) {
+        let (mut outputs, mut inputs) = match self.mode_a() {
+            0 => (0, 0),
+            1 if self.a_input() => (0x28, 0x10),
+            1 => (0x88, 0x40),
+            _ => (0xa8, 0x50),
+        };
+        if self.mode_b() == 1 {
+            outputs |= 0x03;
+            inputs |= 0x04;
+        }
+        (outputs, inputs)
+    }
+
+    // Interrupt requests are gated by INTE and the strobe or acknowledge
+    // being back high.
+    fn intr_a(&self) -> bool {
+        let c = self.external[C as usize];
+        let input = self.ibf[0] && c & 0x10 != 0 && self.inte_a_in;
+        let output = !self.obf[0] && c & 0x40 != 0 && self.inte_a_out;
+        match self.mode_a() {
+            0 => false,
+            1 if self.a_input() => input,
+            1 => output,
+            _ => input || output,
+        }
+    }
+
+    fn intr_b(&self) -> bool {
+        let ready = self.external[C as usize] & 0x04 != 0 && self.inte_b;
+        match self.mode_b() {
+            0 => false,
+            _ if self.b_input() => ready && self.ibf[1],
+            _ => ready && !self.obf[1],
+        }
+    }
+
+    fn status(&self) -> u8 {
+        let buffer_b = if self.b_input() { self.ibf[1] } else { !self.obf[1] };
+        (!self.obf[0] as u8) << 7
+            | (self.ibf[0] as u8) << 5
+            | (self.intr_a() as u8) << 3
+            | (buffer_b as u8) << 1
+            | self.intr_b() as u8
+    }
+
+    // Lines the chip drives on `port`.
+    fn driving(&self, port: u8) -> u8 {
+        match port {
+            A => match self.mode_a() {
+                // Port A drives the bus only while ACK is low.
+                2 if self.external[C as usize] & 0x40 == 0 => 0xff,
+                2 => 0,
+                _ if self.a_input() => 0,
+                _ => 0xff,
+            },
+            B if self.b_input() => 0,
+            B => 0xff,
+            _ => {
+                let (outputs, inputs) = self.handshake();
+                let mut direction = 0;
+                if self.control & 0x08 == 0 {
+                    direction |= 0xf0;
+                }
+                if self.control & 0x01 == 0 {
+                    direction |= 0x0f;
+                }
+                outputs | direction & !(outputs | inputs)
+            },
+        }
+    }
+
+    fn output(&self, port: u8) -> u8 {
+        match port {
+            C => {
+                let (outputs, _) = self.handshake();
+                self.latch[2] & !outputs | self.status() & outputs
+            },
+            _ => self.latch[port as usize],
+        }
+    }
+
+    fn notify(&mut self) {
+        for port in [A, B, C] {
+            let levels = self.sense(port);
+            if levels != self.seen[port as usize] {
+                self.seen[port as usize] = levels;
+                if let Some(f) = &mut self.on_change[port as usize] {
+                    f(levels);
+                }
+            }
+        }
+    }
+
+    fn sample(&mut self, port: u8) {
+        if let Some(f) = &mut self.on_read[port as usize] {
+            let levels = f();
+            self.drive(port, levels);
+        }
+    }
+
+    // Handshake inputs act on their falling edge.
+    fn strobe(&mut self, old: u8, new: u8) {
+        let fell = |bit: u8| old & bit != 0 && new & bit == 0;
+        let mode_a = self.mode_a();
+        if (mode_a == 2 || mode_a == 1 && self.a_input()) && fell(0x10) {
+            self.input[0] = self.external[A as usize];
+            self.ibf[0] = true;
+        }
+        if (mode_a == 2 || mode_a == 1 && !self.a_input()) && fell(0x40) {
+            self.obf[0] = false;
+        }
+        if self.mode_b() == 1 && fell(0x04) {
+            if self.b_input() {
+                self.input[1] = self.external[B as usize];
+                self.ibf[1] = true;
+            } else {
+                self.obf[1] = false;
+            }
+        }
+    }
+
+    fn read_c(&self) -> u8 {
+        let (outputs, inputs) = self.handshake();
+        let inte = (self.inte_a_out as u8) << 6 | (self.inte_a_in as u8) << 4 | (self.inte_b as u8) << 2;
+        let free = !(outputs | inputs);
+        let driving = self.driving(C) & free;
+        self.status() & outputs
+            | inte & inputs
+            | self.latch[2] & driving
+            | self.external[C as usize] & free & !driving
+    }
+
+    fn set_mode(&mut self, word: u8) {
+        self.control = word;
+        self.latch = [0; 3];
+        self.ibf = [false; 2];
+        self.obf = [false; 2];
+        self.inte_a_in = false;
+        self.inte_a_out = false;
+        self.inte_b = false;
+    }
+
+    // Bit set/reset of port C; on a handshake input it flips the matching
+    // interrupt enable instead.
+    fn set_bit(&mut self, bit: u8, level: bool) {
+        let (_, inputs) = self.handshake();
+        match bit {
+            4 if inputs & 0x10 != 0 => self.inte_a_in = level,
+            6 if inputs & 0x40 != 0 => self.inte_a_out = level,
+            2 if inputs & 0x04 != 0 => self.inte_b = level,
+            _ => self.latch[2] = self.latch[2] & !(1 << bit) | (level as u8) << bit,
+        }
+    }
+}
+
+impl Pins for Ppi {
+    fn drive(&mut self, port: u8, levels: u8) {
+        let old = std::mem::replace(&mut self.external[port as usize], levels);
+        if port == C {
+            self.strobe(old, levels);
+        }
+        self.notify();
+    }
+
+    fn driven(&self, port: u8) -> u8 {
+        self.external[port as usize]
+    }
+
+    fn sense(&self, port: u8) -> u8 {
+        let driving = self.driving(port);
+        self.output(port) & driving | self.external[port as usize] & !driving
+    }
+}
+
+impl PortDevice for Ppi {
+    fn read_port(&mut self, port: u8) -> u8 {
+        let port = port & 3;
+        if port == 3 {
+            return 0xff;
+        }
+        self.sample(port);
+        let byte = match port {
+            A if self.mode_a() == 2 || self.mode_a() == 1 && self.a_input() => {
+                self.ibf[0] = false;
+                self.input[0]
+            },
+            B if self.mode_b() == 1 && self.b_input() => {
+                self.ibf[1] = false;
+                self.input[1]
+            },
+            A if self.a_input() => self.external[0],
+            B if self.b_input() => self.external[1],
+            A | B => self.latch[port as usize],
+            _ => self.read_c(),
+        };
+        self.notify();
+        byte
+    }
+
+    fn write_port(&mut self, port: u8, byte: u8) {
+        match port & 3 {
+            A => {
+                self.latch[0] = byte;
+                if self.mode_a() == 2 || self.mode_a() == 1 && !self.a_input() {
+                    self.obf[0] = true;
+                }
+            },
+            B => {
+                self.latch[1] = byte;
+                if self.mode_b() == 1 && !self.b_input() {
+                    self.obf[1] = true;
+                }
+            },
+            C => self.latch[2] = byte,
+            _ if byte & 0x80 != 0 => self.set_mode(byte),
+            _ => self.set_bit(byte >> 1 & 7, byte & 1 != 0),
+        }
+        self.notify();
+    }
+}
+
+impl Clocked for Ppi {
+    fn tick(&mut self, now: u64) {
+        if let Some(mut script) = self.script.take() {
+            script.apply(self, now);
+            self.script = Some(script);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    // Port, and a single line of it if given.
+    Drive(u8, Option<u8>, u8),
+    Expect(u8, Option<u8>, u8),
+}
+
+// Timed pin activity, one step a line:
+//
+//   # cycle  verb    pin   hex
+//   1000     drive   A     55
+//   1200     drive   C4    0      (one line: PC4 low)
+//   5000     expect  B     aa
+//
+// Steps run once `Cpu::cycles` reaches their cycle. Expectations that do
+// not hold are collected in `failures`.
+#[derive(Debug, Default)]
+pub struct PinScript {
+    steps: Vec<(u64, Step)>,
+    next: usize,
+    pub failures: Vec<String>,
+}
+
+impl PinScript {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut steps = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap();
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            if tokens.is_empty() {
+                continue;
+            }
+            let bad = || Error::BadScript(i + 1);
+            let [cycle, verb, pin, value] = tokens[..] else {
+                return Err(bad());
+            };
+            let cycle = cycle.parse().map_err(|_| bad())?;
+            let (port, bit) = parse_pin(pin).ok_or_else(bad)?;
+            let value = u8::from_str_radix(value, 16).map_err(|_| bad())?;
+            if bit.is_some() && value > 1 {
+                return Err(bad());
+            }
+            let step = match verb {
+                "drive" => Step::Drive(port, bit, value),
+                "expect" => Step::Expect(port, bit, value),
+                _ => return Err(bad()),
+            };
+            steps.push((cycle, step));
+        }
+        steps.sort_by_key(|(cycle, _)| *cycle);
+        Ok(Self { steps, ..Default::default() })
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next == self.steps.len()
+    }
+
+    pub fn apply(&mut self, pins: &mut dyn Pins, now: u64) {
+        while let Some(&(cycle, step)) = self.steps.get(self.next) {
+            if cycle > now {
+                break;
+            }
+            self.next += 1;
+            match step {
+                Step::Drive(port, None, levels) => pins.drive(port, levels),
+                Step::Drive(port, Some(bit), level) => pins.drive_line(port, bit, level != 0),
+                Step::Expect(port, bit, expected) => {
+                    let levels = pins.sense(port);
+                    let actual = bit.map_or(levels, |bit| levels >> bit & 1);
+                    if actual != expected {
+                        let name = pin_name(port, bit);
+                        self.failures.push(format!("cycle {cycle}: {name} is {actual:02x}, expected {expected:02x}"));
+                    }
+                },
+            }
+        }
+    }
+}
+
+// "A", "B", "C", or a line such as "C4".
+fn parse_pin(pin: &str) -> Option<(u8, Option<u8>)> {
+    let mut chars = pin.chars();
+    let port = match chars.next()?.to_ascii_uppercase() {
+        'A' => A,
+        'B' => B,
+        'C' => C,
+        _ => return None,
+    };
+    match chars.as_str() {
+        "" => Some((port, None)),
+        bit => bit.parse().ok().filter(|&b| b < 8).map(|b| (port, Some(b))),
+    }
+}
+
+fn pin_name(port: u8, bit: Option<u8>) -> String {
+    let port = (b'A' + port) as char;
+    bit.map_or(format!("port {port}"), |bit| format!("P{port}{bit}"))
+}
@@ -0,0 +1,202 @@
+#![allow(unused)]
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::cpu::{CLOCK_RATE, Cpu, RAM_SIZE};
+use crate::disasm::{flow_at, took_interrupt};
+use crate::error::Error;
+use crate::symbols::Symbols;
+
+const HOT_SPOTS: usize = 32;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RoutineStats {
+    pub calls: u64,
+    pub inclusive: u64,
+    pub exclusive: u64,
+}
+
+struct Frame {
+    routine: u16,
+    sp: u16,          // stack pointer right after the call pushed its return address
+    entered: u64,     // total T-states when the routine was entered
+    stack: usize,     // index into `stacks`
+}
+
+pub struct Profiler {
+    instructions: Vec<u64>,
+    cycles: Vec<u64>,
+    routines: HashMap<u16, RoutineStats>,
+    frames: Vec<Frame>,
+    // Number of frames per routine currently on the shadow stack, so
+    // recursive calls are only counted once towards inclusive time.
+    active: HashMap<u16, u32>,
+    stacks: Vec<(Vec<u16>, u64)>,
+    stack_ids: HashMap<Vec<u16>, usize>,
+    total_instructions: u64,
+    total_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            instructions: vec![0; RAM_SIZE],
+            cycles: vec![0; RAM_SIZE],
+            routines: HashMap::new(),
+            frames: Vec::new(),
+            active: HashMap::new(),
+            stacks: Vec::new(),
+            stack_ids: HashMap::new(),
+            total_instructions: 0,
+            total_cycles: 0,
+        }
+    }
+
+    // Executes one instruction and records where the time went.
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<(), Error> {
+        let pc = cpu.pc;
+        let sp = cpu.sp;
+        let flow = flow_at(&cpu.ram, pc, cpu.variant);
+        let before = cpu.cycles;
+        let idle = cpu.halted;
+        if self.frames.is_empty() {
+            self.enter(pc, sp);
+        }
+
+        cpu.next()?;
+
+        // An interrupt costs the routine it came in, but no instruction, and
+        // neither does idling in HLT.
+        let interrupted = took_interrupt(cpu, pc, sp, flow.len);
+        let spent = cpu.cycles - before;
+        if !interrupted && !idle {
+            self.instructions[pc as usize] += 1;
+            self.cycles[pc as usize] += spent;
+            self.total_instructions += 1;
+        }
+        self.total_cycles += spent;
+        let top = self.frames.last().unwrap();
+        self.routines.entry(top.routine).or_default().exclusive += spent;
+        self.stacks[top.stack].1 += spent;
+
+        if (interrupted || flow.call) && cpu.sp == sp.wrapping_sub(2) {
+            self.enter(cpu.pc, cpu.sp);
+        } else if flow.ret && cpu.sp == sp.wrapping_add(2) {
+            self.leave(cpu.sp);
+        }
+        Ok(())
+    }
+
+    // Closes the frames still open so their inclusive time is counted.
+    pub fn finish(&mut self) {
+        while let Some(frame) = self.frames.pop() {
+            self.close(frame);
+        }
+    }
+
+    pub fn instructions_at(&self, addr: u16) -> u64 {
+        self.instructions[addr as usize]
+    }
+
+    pub fn cycles_at(&self, addr: u16) -> u64 {
+        self.cycles[addr as usize]
+    }
+
+    pub fn routine(&self, addr: u16) -> Option<RoutineStats> {
+        self.routines.get(&addr).copied()
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn write_report(&self, w: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        writeln!(w, "{} instructions, {} T-states ({:.3} s at {:.1} MHz)",
+            self.total_instructions, self.total_cycles,
+            self.total_cycles as f64 / CLOCK_RATE as f64, CLOCK_RATE as f64 / 1e6)?;
+
+        writeln!(w)?;
+        writeln!(w, "Hot spots")?;
+        writeln!(w, "{:<20} {:>12} {:>14} {:>7}", "addr", "count", "T-states", "%")?;
+        let mut hot = (0..RAM_SIZE).filter(|&a| self.instructions[a] != 0).collect::<Vec<_>>();
+        hot.sort_by_key(|&a| (std::cmp::Reverse(self.cycles[a]), a));
+        for &addr in hot.iter().take(HOT_SPOTS) {
+            writeln!(w, "{:<20} {:>12} {:>14} {:>6.2}%",
+                symbols.describe(addr as u16), self.instructions[addr], self.cycles[addr], self.percent(self.cycles[addr]))?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "Routines")?;
+        writeln!(w, "{:<20} {:>10} {:>14} {:>7} {:>14} {:>7}", "routine", "calls", "inclusive", "%", "exclusive", "%")?;
+        let mut routines = self.routines.iter().collect::<Vec<_>>();
+        routines.sort_by_key(|&(&addr, stats)| (std::cmp::Reverse(stats.inclusive), addr));
+        for (addr, stats) in routines {
+            writeln!(w, "{:<20} {:>10} {:>14} {:>6.2}% {:>14} {:>6.2}%",
+                symbols.describe(*addr), stats.calls,
+                stats.inclusive, self.percent(stats.inclusive),
+                stats.exclusive, self.percent(stats.exclusive))?;
+        }
+        Ok(())
+    }
+
+    // One line per call stack, `outer;inner T-states`, as read by flamegraph.pl
+    // and compatible tools.
+    pub fn write_folded(&self, w: &mut impl Write, symbols: &Symbols) -> io::Result<()> {
+        for (stack, cycles) in &self.stacks {
+            if *cycles == 0 {
+                continue;
+            }
+            let names = stack.iter().map(|&a| symbols.describe(a)).collect::<Vec<_>>();
+            writeln!(w, "{} {}", names.join(";"), cycles)?;
+        }
+        Ok(())
+    }
+
+    fn percent(&self, cycles: u64) -> f64 {
+        if self.total_cycles == 0 {
+            0.0
+        } else {
+            cycles as f64 * 100.0 / self.total_cycles as f64
+        }
+    }
+
+    fn enter(&mut self, routine: u16, sp: u16) {
+        let mut path = self.frames.last()
+            .map(|f| self.stacks[f.stack].0.clone())
+            .unwrap_or_default();
+        path.push(routine);
+        let stack = match self.stack_ids.get(&path) {
+            Some(&id) => id,
+            None => {
+                self.stacks.push((path.clone(), 0));
+                self.stack_ids.insert(path, self.stacks.len() - 1);
+                self.stacks.len() - 1
+            },
+        };
+        self.frames.push(Frame { routine, sp, entered: self.total_cycles, stack });
+        *self.active.entry(routine).or_default() += 1;
+        self.routines.entry(routine).or_default().calls += 1;
+    }
+
+    // A return left the stack pointer at `sp`: drop every frame it unwound.
+    // The outermost frame is never dropped here.
+    fn leave(&mut self, sp: u16) {
+        while self.frames.len() > 1 {
+            let frame = self.frames.last().unwrap();
+            if (sp.wrapping_sub(frame.sp) as i16) <= 0 {
+                break;
+            }
+            let frame = self.frames.pop().unwrap();
+            self.close(frame);
+        }
+    }
+
+    fn close(&mut self, frame: Frame) {
+        let active = self.active.get_mut(&frame.routine).unwrap();
+        *active -= 1;
+        if *active == 0 {
+            self.routines.get_mut(&frame.routine).unwrap().inclusive += self.total_cycles - frame.entered;
+        }
+    }
+}
@@ -0,0 +1,64 @@
+#![allow(unused)]
+
+// Enough of the Radio-86RK ROM monitor to run test programs written for
+// it, the way `Cpu::cpm_hook` does for CP/M ones. Orion software calls the
+// same entry points.
+//
+//   F800H  warm start, taken as the end of the program
+//   F809H  print the character in C
+//   F815H  print A as two hex digits
+//   F818H  print the string at HL, up to a 0 byte
+
+use std::io::{self, Write};
+
+use crate::cpu::Cpu;
+use crate::error::Error;
+
+pub const WARM_START: u16 = 0xf800;
+const PUTC: u16 = 0xf809;
+const HEX: u16 = 0xf815;
+const PUTS: u16 = 0xf818;
+
+// Puts RETs at the entry points and starts the program at `start`.
+pub fn prepare(cpu: &mut Cpu, start: u16) {
+    for entry in [PUTC, HEX, PUTS] {
+        cpu.ram.save_byte(entry, 0xc9);
+    }
+    cpu.pc = start;
+}
+
+// Handles a monitor call at `cpu.pc`. True once the program is done.
+pub fn hook(cpu: &mut Cpu, console: &mut dyn Write) -> bool {
+    match cpu.pc {
+        PUTC => {
+            console.write_all(&[cpu.c]).ok();
+        },
+        HEX => {
+            write!(console, "{:02X}", cpu.a).ok();
+        },
+        PUTS => {
+            let mut addr = cpu.get_hl_addr();
+            loop {
+                let c = cpu.ram.load_byte(addr);
+                if c == 0 {
+                    break;
+                }
+                console.write_all(&[c]).ok();
+                addr = addr.wrapping_add(1);
+            }
+        },
+        _ => return cpu.pc == WARM_START,
+    }
+    console.flush().ok();
+    false
+}
+
+pub fn run_with(cpu: &mut Cpu, start: u16, console: &mut dyn Write, mut step: impl FnMut(&mut Cpu) -> Result<(), Error>) -> Result<(), Error> {
+    prepare(cpu, start);
+    loop {
+        if cpu.halted || hook(cpu, console) {
+            break Ok(());
+        }
+        step(cpu)?;
+    }
+}
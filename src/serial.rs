@@ -0,0 +1,252 @@
+#![allow(unused)]
+
+// The host end of an emulated serial port: bytes typed in arrive on a
+// channel fed by a reader thread, bytes sent go straight to a writer, or
+// to a writer thread when the other end may stop reading.
+//
+// There is no baud rate. Flow control is the devices' status flags: a
+// receiver is ready while a byte is waiting and a transmitter while the
+// output backlog is short, and the host end pushes back by not reading.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+// Empty polls after the input has ended before the line counts as idle.
+const IDLE_POLLS: u32 = 10_000;
+// Bytes read ahead from the host before its writes block.
+const INPUT_BUFFER: usize = 64;
+// Queued output bytes at which the transmitter stops being ready.
+pub const OUTPUT_LIMIT: usize = 64;
+
+pub struct Line {
+    rx: VecDeque<u8>,
+    input: Option<Receiver<u8>>,
+    out: Box<dyn Write>,
+    // Output not yet taken by a queued writer.
+    backlog: Option<Arc<AtomicUsize>>,
+    // Polls since the input ran dry for good.
+    idle: u32,
+    // Kept open so the line survives the other end closing.
+    _peer: Option<File>,
+}
+
+// Output handed to a writer thread, one byte at a time.
+struct Queue {
+    tx: Sender<u8>,
+    backlog: Arc<AtomicUsize>,
+}
+
+impl Write for Queue {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.backlog.fetch_add(1, Ordering::SeqCst);
+            if self.tx.send(byte).is_err() {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Line {
+    // Nothing comes in; output goes to `out`. Use `feed` to type.
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self {
+            rx: VecDeque::new(),
+            input: None,
+            out,
+            backlog: None,
+            idle: 0,
+            _peer: None,
+        }
+    }
+
+    // Output goes through a thread of its own, so a writer that blocks
+    // holds up `tx_ready` instead of the emulator.
+    pub fn queued(mut out: impl Write + Send + 'static) -> Self {
+        let (tx, rx) = mpsc::channel::<u8>();
+        let backlog = Arc::new(AtomicUsize::new(0));
+        let taken = backlog.clone();
+        thread::spawn(move || {
+            for byte in rx {
+                let res = out.write_all(&[byte]).and_then(|_| out.flush());
+                taken.fetch_sub(1, Ordering::SeqCst);
+                if res.is_err() {
+                    return;
+                }
+            }
+        });
+        let mut line = Self::new(Box::new(Queue { tx, backlog: backlog.clone() }));
+        line.backlog = Some(backlog);
+        line
+    }
+
+    // The host terminal. It is line buffered, so LF is turned into the CR
+    // the Return key sends on a real terminal.
+    pub fn stdio() -> Self {
+        let mut line = Self::new(Box::new(io::stdout()));
+        line.connect(io::stdin(), true);
+        line
+    }
+
+    // A device node such as a pseudo-terminal or a FIFO, both ways.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut line = Self::new(Box::new(file.try_clone()?));
+        line.connect(file, false);
+        Ok(line)
+    }
+
+    // A new pseudo-terminal, returned with the path of its slave side for
+    // `screen`, `minicom` or a test to open. The slave is set raw and held
+    // open, so clients can come and go.
+    #[cfg(target_os = "linux")]
+    pub fn pty() -> io::Result<(Self, PathBuf)> {
+        use std::ffi::CStr;
+        use std::os::fd::FromRawFd;
+        use std::os::raw::{c_char, c_int};
+        use std::os::unix::fs::OpenOptionsExt;
+
+        unsafe extern "C" {
+            fn posix_openpt(flags: c_int) -> c_int;
+            fn grantpt(fd: c_int) -> c_int;
+            fn unlockpt(fd: c_int) -> c_int;
+            fn ptsname_r(fd: c_int, buf: *mut c_char, len: usize) -> c_int;
+            // The termios layout stays opaque behind a large enough buffer.
+            fn tcgetattr(fd: c_int, termios: *mut u64) -> c_int;
+            fn cfmakeraw(termios: *mut u64);
+            fn tcsetattr(fd: c_int, action: c_int, termios: *const u64) -> c_int;
+        }
+        const O_RDWR: c_int = 0o2;
+        const O_NOCTTY: c_int = 0o400;
+        const TCSANOW: c_int = 0;
+
+        let check = |res: c_int| if res < 0 { Err(io::Error::last_os_error()) } else { Ok(res) };
+        let mut name = [0 as c_char; 128];
+        // SAFETY: plain libc calls; the fd is owned by `master` as soon as
+        // it exists, and `name` outlives the call filling it.
+        let (master, path) = unsafe {
+            let fd = check(posix_openpt(O_RDWR | O_NOCTTY))?;
+            let master = File::from_raw_fd(fd);
+            check(grantpt(fd))?;
+            check(unlockpt(fd))?;
+            let res = ptsname_r(fd, name.as_mut_ptr(), name.len());
+            if res != 0 {
+                return Err(io::Error::from_raw_os_error(res));
+            }
+            (master, PathBuf::from(CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned()))
+        };
+        let slave = OpenOptions::new().read(true).write(true).custom_flags(O_NOCTTY).open(&path)?;
+        let mut termios = [0u64; 32];
+        // SAFETY: `termios` is larger than any struct termios.
+        unsafe {
+            use std::os::fd::AsRawFd;
+            check(tcgetattr(slave.as_raw_fd(), termios.as_mut_ptr()))?;
+            cfmakeraw(termios.as_mut_ptr());
+            check(tcsetattr(slave.as_raw_fd(), TCSANOW, termios.as_ptr()))?;
+        }
+        let mut line = Self::queued(master.try_clone()?);
+        line.connect(master, false);
+        line._peer = Some(slave);
+        Ok((line, path))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn pty() -> io::Result<(Self, PathBuf)> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "pseudo-terminals are only supported on Linux"))
+    }
+
+    // Input read from one file, output written to another.
+    pub fn files(input: impl AsRef<Path>, output: impl AsRef<Path>) -> io::Result<Self> {
+        let mut line = Self::new(Box::new(File::create(output)?));
+        line.connect(File::open(input)?, false);
+        Ok(line)
+    }
+
+    // Takes input from `reader` on a thread of its own.
+    pub fn connect(&mut self, mut reader: impl Read + Send + 'static, lf_to_cr: bool) {
+        let (tx, rx) = mpsc::sync_channel(INPUT_BUFFER);
+        thread::spawn(move || {
+            let mut buf = [0; 256];
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                for &byte in &buf[..n] {
+                    let byte = if lf_to_cr && byte == b'\n' { b'\r' } else { byte };
+                    if tx.send(byte).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        self.input = Some(rx);
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.rx.extend(bytes);
+    }
+
+    // Whether a byte is waiting. Counts towards `is_idle` when not.
+    pub fn ready(&mut self) -> bool {
+        self.poll();
+        if !self.rx.is_empty() {
+            self.idle = 0;
+            return true;
+        }
+        if self.input.is_none() {
+            self.idle = self.idle.saturating_add(1);
+        }
+        false
+    }
+
+    pub fn read(&mut self) -> Option<u8> {
+        self.poll();
+        self.rx.pop_front()
+    }
+
+    // Whether the other end keeps up with output.
+    pub fn tx_ready(&self) -> bool {
+        self.backlog.as_ref().is_none_or(|b| b.load(Ordering::SeqCst) < OUTPUT_LIMIT)
+    }
+
+    pub fn write(&mut self, byte: u8) {
+        let _ = self.out.write_all(&[byte]);
+        let _ = self.out.flush();
+    }
+
+    // Drops what has arrived so far.
+    pub fn clear(&mut self) {
+        self.rx.clear();
+        if let Some(input) = &self.input {
+            while input.try_recv().is_ok() {}
+        }
+    }
+
+    // True once all input is consumed and the program keeps polling for
+    // more that can never come.
+    pub fn is_idle(&self) -> bool {
+        self.idle >= IDLE_POLLS
+    }
+
+    // Takes in one more byte when out of them, so a program that stops
+    // reading stops the host end too.
+    fn poll(&mut self) {
+        if !self.rx.is_empty() {
+            return;
+        }
+        let Some(input) = &self.input else { return };
+        match input.try_recv() {
+            Ok(byte) => self.rx.push_back(byte),
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => self.input = None,
+        }
+    }
+}
@@ -0,0 +1,121 @@
+#![allow(unused)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::utils::parse_hex;
+
+// Address <-> label tables loaded from assembler output.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    by_name: HashMap<String, u16>,
+    by_addr: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Picks the parser from the extension: `.SYM` for M80/L80 symbol files,
+    // `.PRN`/`.LST` for assembler listings, anything else as `label = addr`.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let path = path.as_ref();
+        let text = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+        let ext = path.extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let before = self.len();
+        match ext.as_str() {
+            "sym" => self.parse_sym(&text),
+            "prn" | "lst" => self.parse_prn(&text),
+            _ => self.parse_text(&text),
+        }
+        Ok(self.len() - before)
+    }
+
+    pub fn add(&mut self, name: &str, addr: u16) {
+        let name = name.to_ascii_uppercase();
+        // Keep the first label seen for an address.
+        self.by_addr.entry(addr).or_insert_with(|| name.clone());
+        self.by_name.insert(name, addr);
+    }
+
+    // Case-insensitive, as in the assemblers.
+    pub fn lookup(&self, name: &str) -> Option<u16> {
+        self.by_name.get(&name.to_ascii_uppercase()).copied()
+    }
+
+    pub fn name_of(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(|s| s.as_str())
+    }
+
+    // `LABEL`, `LABEL+3` for addresses shortly after a label, or plain hex.
+    pub fn describe(&self, addr: u16) -> String {
+        match self.by_addr.range(..=addr).next_back() {
+            Some((&base, name)) if base == addr => name.clone(),
+            Some((&base, name)) if addr - base < 0x40 => format!("{}+{}", name, addr - base),
+            _ => format!("{:04x}", addr),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    // M80/L80 .SYM: `ADDR NAME` pairs, several to a line, separated by
+    // blanks or tabs. Relocatable addresses may carry a trailing `'`.
+    pub fn parse_sym(&mut self, text: &str) {
+        for line in text.lines() {
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            for pair in tokens.chunks(2) {
+                if let [addr, name] = pair
+                    && let Some(addr) = parse_hex(addr.trim_end_matches(['\'', '"', '*'])) {
+                    self.add(name, addr);
+                }
+            }
+        }
+    }
+
+    // `label = addr`, `label equ addr` or `label addr`, with `;` comments.
+    pub fn parse_text(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap();
+            let tokens = line.split(|c: char| c.is_whitespace() || c == '=')
+                .filter(|t| !t.is_empty())
+                .collect::<Vec<_>>();
+            let (name, value) = match tokens.as_slice() {
+                [name, equ, value] if equ.eq_ignore_ascii_case("equ") => (name, value),
+                [name, value] => (name, value),
+                _ => continue,
+            };
+            if let Some(addr) = parse_hex(value) {
+                self.add(name.trim_end_matches(':'), addr);
+            }
+        }
+    }
+
+    // CP/M ASM .PRN listings: a line starting with an address and holding
+    // `LABEL:` defines LABEL there, and `0005 = BDOS EQU 5` defines BDOS.
+    pub fn parse_prn(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap();
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            let Some(addr) = tokens.first().filter(|t| t.len() == 4).and_then(|t| parse_hex(t)) else {
+                continue;
+            };
+            let equ = tokens.iter().position(|t| t.eq_ignore_ascii_case("equ") || t.eq_ignore_ascii_case("set"));
+            if let Some(i) = equ.filter(|&i| i >= 2) {
+                self.add(tokens[i - 1].trim_end_matches(':'), addr);
+            } else if let Some(label) = tokens.iter().find(|t| t.len() > 1 && t.ends_with(':')) {
+                self.add(label.trim_end_matches(':'), addr);
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::altair::*;
+use crate::serial::Line;
+
+#[derive(Clone, Default)]
+pub struct Terminal(pub Rc<RefCell<Vec<u8>>>);
+
+impl Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn machine(memory: usize, program: &[u8]) -> (Altair, Terminal) {
+    let terminal = Terminal::default();
+    let mut altair = Altair::new(memory, Line::new(Box::new(terminal.clone())));
+    altair.load(&[(0x0000, program.to_vec())]);
+    (altair, terminal)
+}
+
+#[test]
+fn test_echo() {
+    let (mut altair, terminal) = machine(0x10000, &[
+        0xdb, 0x10,       // IN 10H
+        0x0f,             // RRC
+        0xd2, 0x00, 0x00, // JNC 0000H
+        0xdb, 0x11,       // IN 11H
+        0xd3, 0x11,       // OUT 11H
+        0xc3, 0x00, 0x00, // JMP 0000H
+    ]);
+    altair.sio.borrow_mut().line.feed(b"HELLO\r\xc1");
+    altair.run().unwrap();
+    assert!(altair.sio.borrow().line.is_idle());
+    assert_eq!(terminal.0.borrow().as_slice(), b"HELLO\rA");
+}
+
+#[test]
+fn test_memory_size() {
+    let (mut altair, _) = machine(0x1000, &[
+        0x3e, 0x55,       // MVI A,55H
+        0x32, 0x00, 0x10, // STA 1000H
+        0x3a, 0x00, 0x10, // LDA 1000H
+        0x32, 0x00, 0x08, // STA 0800H
+        0x3e, 0x55,       // MVI A,55H
+        0x32, 0xff, 0x0f, // STA 0FFFH
+    ]);
+    for _ in 0..6 {
+        altair.cpu.next().unwrap();
+    }
+    assert_eq!(altair.cpu.ram.load_byte(0x0800), 0xff);
+    assert_eq!(altair.cpu.ram.load_byte(0x0fff), 0x55);
+    assert_eq!(altair.cpu.ram.load_byte(0x1000), 0xff);
+}
+
+#[test]
+fn test_sense_switches() {
+    let (mut altair, _) = machine(0x10000, &[
+        0xdb, 0xff,       // IN 0FFH
+        0xd3, 0xff,       // OUT 0FFH
+        0xdb, 0x22,       // IN 22H
+    ]);
+    altair.panel.borrow_mut().switches = 0xa5;
+    altair.cpu.next().unwrap();
+    assert_eq!(altair.cpu.a, 0xa5);
+    altair.cpu.next().unwrap();
+    assert_eq!(altair.panel.borrow().leds, 0xa5);
+    // Nothing answers on 22H.
+    altair.cpu.next().unwrap();
+    assert_eq!(altair.cpu.a, 0xff);
+    assert!(!altair.cpu.halted);
+}
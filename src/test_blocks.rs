@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::interrupt::RstLatch;
+
+// Runs a CP/M program to its warm boot, through the block cache or not.
+fn run(image: &[u8], blocks: bool) -> (Cpu, Vec<u8>) {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0x0100, image);
+    cpu.prepare_cpm();
+    if blocks {
+        cpu.translate_blocks();
+    }
+    let mut console = Vec::new();
+    while !cpu.cpm_hook(&mut console) {
+        cpu.next_block().unwrap();
+    }
+    (cpu, console)
+}
+
+#[test]
+fn test_blocks_match_interpreter() {
+    let image = include_bytes!("../test_roms/TST8080.COM");
+    let (interp, expected) = run(image, false);
+    let (cpu, console) = run(image, true);
+    assert_eq!(console, expected);
+    assert!(String::from_utf8_lossy(&console).contains("CPU IS OPERATIONAL"));
+    assert_eq!(cpu.registers(), interp.registers());
+    assert!(cpu.blocks.unwrap().translated > 0);
+}
+
+#[test]
+fn test_blocks_are_reused() {
+    let image = [
+        0x06, 0x64,       // 0100 MVI B,100
+        0x3c,             // 0102 INR A
+        0x05,             // 0103 DCR B
+        0xc2, 0x02, 0x01, // 0104 JNZ 0102H
+        0xc3, 0x00, 0x00, // 0107 JMP 0
+    ];
+    let (interp, _) = run(&image, false);
+    let (cpu, _) = run(&image, true);
+    assert_eq!(cpu.a, 100);
+    assert_eq!(cpu.cycles, interp.cycles);
+    // From 0100H, from 0102H, and the JMP.
+    let blocks = cpu.blocks.unwrap();
+    assert_eq!((blocks.translated, blocks.len()), (3, 3));
+}
+
+#[test]
+fn test_self_modifying_code() {
+    let image = [
+        0x06, 0x05,       // 0100 MVI B,5
+        0x3e, 0x00,       // 0102 MVI A,0
+        0xc6, 0x01,       // 0104 ADI 1
+        0x21, 0x05, 0x01, // 0106 LXI H,0105H
+        0x34,             // 0109 INR M, the ADI operand
+        0x05,             // 010A DCR B
+        0xc2, 0x04, 0x01, // 010B JNZ 0104H
+        0xc3, 0x00, 0x00, // 010E JMP 0
+    ];
+    let (interp, _) = run(&image, false);
+    let (cpu, _) = run(&image, true);
+    assert_eq!(cpu.a, 1 + 2 + 3 + 4 + 5);
+    assert_eq!(cpu.registers(), interp.registers());
+    assert!(cpu.blocks.unwrap().invalidated > 0);
+}
+
+#[test]
+fn test_interrupt_inside_block() {
+    let program = [
+        0xfb,             // EI
+        0x3c,             // INR A
+        0x3c,             // INR A
+        0x3c,             // INR A
+        0xc3, 0x01, 0x00, // JMP 1
+    ];
+    let start = |blocks| {
+        let latch = Rc::new(RefCell::new(RstLatch::new(2)));
+        latch.borrow_mut().set(true);
+        let mut cpu = Cpu::new();
+        cpu.sp = 0x1000;
+        cpu.ram.load_at(0, &program);
+        cpu.connect_interrupts(&latch);
+        if blocks {
+            cpu.translate_blocks();
+        }
+        cpu
+    };
+    let mut interp = start(false);
+    for _ in 0..3 {
+        interp.next().unwrap();
+    }
+    // EI holds the interrupt off for one instruction, then the block is
+    // left for the interpreter to take it.
+    let mut cpu = start(true);
+    cpu.next_block().unwrap();
+    assert_eq!((cpu.pc, cpu.a), (0x0002, 1));
+    cpu.next_block().unwrap();
+    assert_eq!((cpu.pc, cpu.a), (0x0010, 1));
+    assert_eq!(cpu.registers(), interp.registers());
+}
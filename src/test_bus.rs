@@ -0,0 +1,149 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bus::*;
+use crate::cpu::*;
+use crate::device::Device;
+use crate::i8085::Input;
+use crate::interrupt::RstLatch;
+
+fn cycle(status: u8, address: u16, data: u8, states: u64) -> MachineCycle {
+    MachineCycle { status, address, data, states }
+}
+
+fn cpu(program: &[u8]) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0x1000;
+    cpu.ram.load_at(0, program);
+    cpu
+}
+
+#[test]
+fn test_memory_cycles() {
+    let mut cpu = cpu(&[
+        0x32, 0x34, 0x12, // STA 1234H
+        0x34,             // INR M
+    ]);
+    cpu.a = 0x5a;
+    (cpu.h, cpu.l) = (0x12, 0x34);
+    assert_eq!(cpu.step_instruction_cycles().unwrap(), [
+        cycle(FETCH, 0x0000, 0x32, 4),
+        cycle(MEMORY_READ, 0x0001, 0x34, 3),
+        cycle(MEMORY_READ, 0x0002, 0x12, 3),
+        cycle(MEMORY_WRITE, 0x1234, 0x5a, 3),
+    ]);
+    assert_eq!(cpu.step_instruction_cycles().unwrap(), [
+        cycle(FETCH, 0x0003, 0x34, 4),
+        cycle(MEMORY_READ, 0x1234, 0x5a, 3),
+        cycle(MEMORY_WRITE, 0x1234, 0x5b, 3),
+    ]);
+    assert_eq!(cpu.cycles, 13 + 10);
+}
+
+#[test]
+fn test_cycles_advance_one_at_a_time() {
+    let mut cpu = cpu(&[0xcd, 0x10, 0x00]); // CALL 0010H
+    cpu.ram.save_byte(0x10, 0xc9);          // RET
+    let first = cpu.step_cycle().unwrap();
+    assert_eq!(first, cycle(FETCH, 0x0000, 0xcd, 5));
+    assert_eq!(cpu.cycles, 5);
+    assert_eq!(cpu.pc, 0x0010);
+    let rest: Vec<_> = (0..4).map(|_| cpu.step_cycle().unwrap()).collect();
+    assert_eq!(rest, [
+        cycle(MEMORY_READ, 0x0001, 0x10, 3),
+        cycle(MEMORY_READ, 0x0002, 0x00, 3),
+        cycle(STACK_WRITE, 0x0fff, 0x00, 3),
+        cycle(STACK_WRITE, 0x0ffe, 0x03, 3),
+    ]);
+    assert_eq!(cpu.cycles, 17);
+    assert_eq!(cpu.step_instruction_cycles().unwrap(), [
+        cycle(FETCH, 0x0010, 0xc9, 4),
+        cycle(STACK_READ, 0x0ffe, 0x03, 3),
+        cycle(STACK_READ, 0x0fff, 0x00, 3),
+    ]);
+}
+
+struct Echo(u8);
+
+impl Device for Echo {
+    fn read(&mut self) -> u8 {
+        self.0
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.0 = byte;
+    }
+}
+
+#[test]
+fn test_io_cycles() {
+    let mut cpu = cpu(&[
+        0xd3, 0x07, // OUT 07H
+        0xdb, 0x07, // IN 07H
+    ]);
+    cpu.devices[7] = Some(Box::new(Echo(0)));
+    cpu.a = 0x42;
+    assert_eq!(cpu.step_instruction_cycles().unwrap(), [
+        cycle(FETCH, 0x0000, 0xd3, 4),
+        cycle(MEMORY_READ, 0x0001, 0x07, 3),
+        cycle(OUTPUT_WRITE, 0x0707, 0x42, 3),
+    ]);
+    cpu.a = 0;
+    let cycles = cpu.step_instruction_cycles().unwrap();
+    assert_eq!(cycles[2], cycle(INPUT_READ, 0x0707, 0x42, 3));
+    assert_eq!(cpu.a, 0x42);
+}
+
+#[test]
+fn test_interrupt_acknowledge() {
+    let latch = Rc::new(RefCell::new(RstLatch::new(2)));
+    let mut cpu = cpu(&[0xfb, 0x00, 0x00]); // EI, NOP
+    cpu.connect_interrupts(&latch);
+    cpu.step_instruction_cycles().unwrap();
+    cpu.step_instruction_cycles().unwrap();
+    latch.borrow_mut().set(true);
+    assert_eq!(cpu.step_instruction_cycles().unwrap(), [
+        cycle(INTERRUPT_ACK, 0x0002, 0xd7, 5),
+        cycle(STACK_WRITE, 0x0fff, 0x00, 3),
+        cycle(STACK_WRITE, 0x0ffe, 0x02, 3),
+    ]);
+    assert_eq!(cpu.pc, 0x0010);
+}
+
+#[test]
+fn test_halt_acknowledge() {
+    let mut cpu = Cpu::new();
+    cpu.ram.save_byte(0, 0x76);
+    assert_eq!(cpu.step_instruction_cycles().unwrap(), [
+        cycle(FETCH, 0x0000, 0x76, 4),
+        cycle(HALT_ACK, 0x0001, 0x00, 3),
+    ]);
+}
+
+#[test]
+fn test_stack_cycles() {
+    // Moving SP over code doesn't make the operand reads stack cycles.
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0x0100, &[
+        0x31, 0x00, 0xf0, // LXI SP,0F000H
+        0xe5,             // PUSH H
+    ]);
+    cpu.pc = 0x0100;
+    assert_eq!(cpu.step_instruction_cycles().unwrap(), [
+        cycle(FETCH, 0x0100, 0x31, 4),
+        cycle(MEMORY_READ, 0x0101, 0x00, 3),
+        cycle(MEMORY_READ, 0x0102, 0xf0, 3),
+    ]);
+    assert_eq!(cpu.step_instruction_cycles().unwrap()[1..], [
+        cycle(STACK_WRITE, 0xefff, 0x00, 3),
+        cycle(STACK_WRITE, 0xeffe, 0x00, 3),
+    ]);
+
+    // An 8085 TRAP pushes with no instruction behind it.
+    let mut cpu = Cpu::with_variant(Variant::I8085);
+    cpu.sp = 0x1000;
+    cpu.pins.borrow_mut().set(Input::Trap, true);
+    let cycles = cpu.step_instruction_cycles().unwrap();
+    assert_eq!(cycles.iter().filter(|c| c.status == STACK_WRITE).count(), 2);
+    assert_eq!(cpu.pc, 0x0024);
+}
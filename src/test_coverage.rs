@@ -0,0 +1,182 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::coverage::*;
+use crate::cpu::*;
+use crate::disasm::*;
+use crate::interrupt::RstLatch;
+use crate::symbols::Symbols;
+
+// 0000: MVI B,02h
+// 0002: LDA 0020h
+// 0005: STA 0021h
+// 0008: DCR B
+// 0009: JNZ 0005h
+// 000c: JZ 0000h      (taken every time it runs)
+// 000f: NOP           (never reached)
+fn looping() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[
+        0x06, 0x02, 0x3a, 0x20, 0x00, 0x32, 0x21, 0x00, 0x05, 0xc2, 0x05, 0x00, 0xca, 0x00, 0x00, 0x00,
+    ]);
+    cpu
+}
+
+#[test]
+fn test_code_and_data_marks() {
+    let mut cpu = looping();
+    let mut coverage = Coverage::new();
+    for _ in 0..9 {
+        coverage.step(&mut cpu).unwrap();
+    }
+    assert!(coverage.executed(0x0000));
+    assert!(coverage.executed(0x0001));
+    assert!(!coverage.executed(0x000f));
+    assert!(coverage.read(0x0020));
+    assert!(!coverage.written(0x0020));
+    assert!(coverage.written(0x0021));
+    assert!(!coverage.read(0x0021));
+    // Operand bytes are not data reads.
+    assert!(!coverage.read(0x0003));
+}
+
+#[test]
+fn test_branch_directions() {
+    let mut cpu = looping();
+    let mut coverage = Coverage::new();
+    for _ in 0..9 {
+        coverage.step(&mut cpu).unwrap();
+    }
+    assert_eq!(coverage.branch(0x0009), Some((true, true)));
+    assert_eq!(coverage.branch(0x000c), Some((true, false)));
+    assert_eq!(coverage.branch(0x0005), None);
+
+    let summary = coverage.summary(0x0000, 0x000f);
+    assert_eq!(summary.branches, 2);
+    assert_eq!(summary.both_ways, 1);
+    assert_eq!(summary.executed, 15);
+    assert_eq!(summary.untouched, 1);
+}
+
+#[test]
+fn test_variant_branches() {
+    let mut cpu = Cpu::with_variant(Variant::I8085);
+    cpu.ram.load_at(0, &[0xdd, 0x06, 0x00]); // JNK 0006H
+    let mut coverage = Coverage::new();
+    coverage.step(&mut cpu).unwrap();
+    assert_eq!(coverage.branch(0x0000), Some((true, false)));
+
+    let mut cpu = Cpu::with_variant(Variant::Z80);
+    cpu.ram.load_at(0, &[
+        0x06, 0x02, // LD B,2
+        0x10, 0xfe, // DJNZ $
+    ]);
+    let mut coverage = Coverage::new();
+    for _ in 0..3 {
+        coverage.step(&mut cpu).unwrap();
+    }
+    assert_eq!(coverage.branch(0x0002), Some((true, true)));
+    assert!(!coverage.read(0x0003));
+}
+
+#[test]
+fn test_interrupt_runs_nothing_at_pc() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[
+        0x31, 0x00, 0x10, // LXI SP,1000H
+        0xfb,             // EI
+        0x00,             // NOP
+        0xca, 0x00, 0x00, // JZ 0000H
+    ]);
+    let latch = Rc::new(RefCell::new(RstLatch::new(2)));
+    latch.borrow_mut().set(true);
+    cpu.connect_interrupts(&latch);
+    let mut coverage = Coverage::new();
+    for _ in 0..4 {
+        coverage.step(&mut cpu).unwrap();
+    }
+    assert_eq!(cpu.pc, 0x0010);
+    assert!(!coverage.executed(0x0005));
+    assert_eq!(coverage.branch(0x0005), None);
+    assert!(coverage.written(0x0ffe) && coverage.written(0x0fff));
+}
+
+#[test]
+fn test_listing() {
+    let mut cpu = looping();
+    let mut coverage = Coverage::new();
+    for _ in 0..9 {
+        coverage.step(&mut cpu).unwrap();
+    }
+    let mut out = Vec::new();
+    coverage.write_listing(&mut out, &cpu.ram, 0x0000, 0x0021, &Symbols::new()).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("0009  c2 05 00  JNZ 0005H        ; taken both ways"));
+    assert!(out.contains("000c  ca 00 00  JZ 0000H         ; always taken"));
+    assert!(out.contains("; 000f-001f not executed (17 bytes)"));
+    assert!(out.contains("0021  00        DB 00H           ; written"));
+}
+
+#[test]
+fn test_disassemble() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0xcd, 0xa3, 0x01, 0x36, 0xff, 0x08, 0xf5]);
+    assert_eq!(disassemble(&cpu.ram, 0x0000, &Symbols::new(), Variant::I8080), ("CALL 01A3H".to_string(), 3));
+    assert_eq!(disassemble(&cpu.ram, 0x0003, &Symbols::new(), Variant::I8080), ("MVI M,0FFH".to_string(), 2));
+    assert_eq!(disassemble(&cpu.ram, 0x0005, &Symbols::new(), Variant::I8080), ("NOP".to_string(), 1));
+    assert_eq!(disassemble(&cpu.ram, 0x0006, &Symbols::new(), Variant::I8080), ("PUSH PSW".to_string(), 1));
+}
+
+#[test]
+fn test_z80_instruction_lengths() {
+    let cases: &[(&[u8], u16)] = &[
+        (&[0xdd, 0x21, 0x34, 0x12], 4),       // LD IX,1234H
+        (&[0xfd, 0x36, 0x05, 0x99], 4),       // LD (IY+5),99H
+        (&[0xdd, 0x7e, 0x05], 3),             // LD A,(IX+5)
+        (&[0xdd, 0x7c], 2),                   // LD A,IXH
+        (&[0xdd, 0x22, 0x00, 0x20], 4),       // LD (2000H),IX
+        (&[0xdd, 0xe9], 2),                   // JP (IX)
+        (&[0xdd, 0xcb, 0x05, 0xc6], 4),       // SET 0,(IX+5)
+        (&[0xdd, 0xfd, 0x23], 3),             // INC IY, after a spent DD
+        (&[0xcb, 0x11], 2),                   // RL C
+        (&[0xed, 0x43, 0x00, 0x20], 4),       // LD (2000H),BC
+        (&[0xed, 0x7b, 0x00, 0x20], 4),       // LD SP,(2000H)
+        (&[0xed, 0xb0], 2),                   // LDIR
+        (&[0x10, 0xfe], 2),                   // DJNZ $
+        (&[0x08], 1),                         // EX AF,AF'
+        (&[0xd3, 0x10], 2),                   // OUT (10H),A
+    ];
+    for &(bytes, len) in cases {
+        let mut cpu = Cpu::with_variant(Variant::Z80);
+        cpu.ram.load_at(0x100, bytes);
+        assert_eq!(flow_at(&cpu.ram, 0x100, Variant::Z80).len, len, "{bytes:02x?}");
+    }
+
+    let mut cpu = Cpu::with_variant(Variant::Z80);
+    cpu.ram.load_at(0x100, &[0xdd, 0xcd, 0x00, 0x20]);
+    let flow = flow_at(&cpu.ram, 0x100, Variant::Z80);
+    assert!(flow.call);
+    assert_eq!(flow.len, 4);
+}
+
+#[test]
+fn test_z80_operands_are_not_executed() {
+    let mut cpu = Cpu::with_variant(Variant::Z80);
+    cpu.ram.load_at(0, &[
+        0xdd, 0x21, 0x00, 0x20, // LD IX,2000H
+        0xdd, 0x36, 0x01, 0x00, // LD (IX+1),00H
+        0xdd, 0xcb, 0x01, 0xc6, // SET 0,(IX+1)
+        0x00,                   // NOP
+    ]);
+    let mut coverage = Coverage::new();
+    for _ in 0..4 {
+        coverage.step(&mut cpu).unwrap();
+    }
+    // Operands are part of the instruction, not data it read.
+    for addr in 0..13 {
+        assert!(coverage.executed(addr), "{addr:04x}");
+        assert!(!coverage.read(addr), "{addr:04x}");
+    }
+    assert!(coverage.read(0x2001));
+    assert!(coverage.written(0x2001));
+}
@@ -0,0 +1,283 @@
+use std::fs;
+use std::io::Cursor;
+
+use crate::dap;
+use crate::error::Error;
+use crate::json::*;
+use crate::linemap::*;
+use crate::loader::*;
+
+const LISTING: &str = concat!(
+    " 0005 =         BDOS    EQU     5\n",
+    " 0100                   ORG     100H\n",
+    " 0100 0E02      START:  MVI     C,2\n",
+    " 0102 1E41              MVI     E,'A'\n",
+    " 0104 CD0500            CALL    BDOS\n",
+    " 0107 C30000    DONE:   JMP     0\n",
+);
+
+const PROGRAM: [u8; 10] = [0x0e, 0x02, 0x1e, 0x41, 0xcd, 0x05, 0x00, 0xc3, 0x00, 0x00];
+
+fn frame(msg: &str) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", msg.len(), msg)
+}
+
+// Runs a session and returns every message the server sent.
+fn session(requests: &[String]) -> Vec<Value> {
+    let input = requests.iter().map(|r| frame(r)).collect::<String>();
+    let mut out = Vec::new();
+    dap::serve(Cursor::new(input.into_bytes()), &mut out).unwrap();
+    let mut out = String::from_utf8(out).unwrap();
+    let mut messages = Vec::new();
+    while let Some((header, rest)) = out.split_once("\r\n\r\n") {
+        let len = header.trim_start_matches("Content-Length: ").parse::<usize>().unwrap();
+        messages.push(Value::parse(&rest[..len]).unwrap());
+        out = rest[len..].to_string();
+    }
+    messages
+}
+
+fn events<'a>(messages: &'a [Value], name: &str) -> Vec<&'a Value> {
+    messages.iter().filter(|m| m.get("event").as_str() == Some(name)).collect()
+}
+
+fn response(messages: &[Value], seq: i64) -> &Value {
+    messages.iter().find(|m| m.get("request_seq").as_i64() == Some(seq)).unwrap()
+}
+
+#[test]
+fn test_json() {
+    let text = r#"{"a":[1,-2.5,true,null],"b":"x\"\né","c":{}}"#;
+    let value = Value::parse(text).unwrap();
+    assert_eq!(value.get("a").as_array()[0].as_i64(), Some(1));
+    assert_eq!(value.get("b").as_str(), Some("x\"\né"));
+    assert_eq!(value.get("missing"), &Value::Null);
+    assert_eq!(Value::parse(&value.to_string()), Some(value));
+    assert_eq!(Value::parse("[1,"), None);
+}
+
+#[test]
+fn test_parse_ihex() {
+    let text = ":0401000001020304F1\n:02010400AABB94\n:0200100055AAEF\n:00000001FF\n";
+    let segments = parse_ihex(text).unwrap();
+    assert_eq!(segments, vec![
+        (0x0100, vec![1, 2, 3, 4, 0xaa, 0xbb]),
+        (0x0010, vec![0x55, 0xaa]),
+    ]);
+    assert_eq!(extent(&segments), Some((0x0010, 0x0105)));
+    assert!(parse_ihex(":0401000001020304F2\n").is_err());
+}
+
+#[test]
+fn test_parse_ihex_non_ascii() {
+    assert!(matches!(parse_ihex(":040100000102030é4F1\n"), Err(Error::BadHexRecord(1))));
+}
+
+#[test]
+fn test_extent_of_all_memory() {
+    assert_eq!(extent(&[(0x0000, vec![0; 0x10000])]), Some((0x0000, 0xffff)));
+}
+
+#[test]
+fn test_image_past_end_of_memory() {
+    // Two bytes at FFFFH would wrap around to 0000H.
+    assert!(matches!(parse_ihex(":02FFFF00AABB9B\n"), Err(Error::PastEndOfMemory(0xffff))));
+
+    let program = std::env::temp_dir().join(format!("i8080_big_{}.com", std::process::id()));
+    fs::write(&program, [0u8; 0x100]).unwrap();
+    let res = read_image_at(&program, 0xff80);
+    fs::remove_file(&program).unwrap();
+    assert_eq!(res.unwrap_err().to_string(), "Image at FF80H runs past the end of memory.");
+}
+
+#[test]
+fn test_linemap() {
+    let lines = LineMap::parse_listing(LISTING);
+    assert_eq!(lines.addr_of(3), Some((3, 0x0100)));
+    // Comment or blank lines move on to the next line with code.
+    assert_eq!(lines.addr_of(1), Some((2, 0x0100)));
+    assert_eq!(lines.line_of(0x0100), Some(3));
+    assert_eq!(lines.line_of(0x0104), Some(5));
+    assert_eq!(lines.line_of(0x0005), None);
+}
+
+#[test]
+fn test_session() {
+    let dir = std::env::temp_dir();
+    let program = dir.join(format!("i8080_dap_{}.com", std::process::id()));
+    let listing = program.with_extension("prn");
+    fs::write(&program, PROGRAM).unwrap();
+    fs::write(&listing, LISTING).unwrap();
+
+    let requests = [
+        r#"{"seq":1,"type":"request","command":"initialize","arguments":{}}"#.to_string(),
+        Value::object([
+            ("seq", 2.into()),
+            ("type", "request".into()),
+            ("command", "launch".into()),
+            ("arguments", Value::object([
+                ("program", program.to_string_lossy().as_ref().into()),
+                ("listing", listing.to_string_lossy().as_ref().into()),
+                ("stopOnEntry", true.into()),
+            ])),
+        ]).to_string(),
+        format!(
+            r#"{{"seq":3,"type":"request","command":"setBreakpoints","arguments":{{"source":{{"path":"{}"}},"breakpoints":[{{"line":5}},{{"line":40}}]}}}}"#,
+            program.with_extension("asm").file_name().unwrap().to_string_lossy(),
+        ),
+        r#"{"seq":4,"type":"request","command":"configurationDone"}"#.to_string(),
+        r#"{"seq":5,"type":"request","command":"continue","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":6,"type":"request","command":"stackTrace","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":7,"type":"request","command":"next","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":8,"type":"request","command":"variables","arguments":{"variablesReference":1}}"#.to_string(),
+        r#"{"seq":9,"type":"request","command":"readMemory","arguments":{"memoryReference":"0x0100","count":4}}"#.to_string(),
+        r#"{"seq":10,"type":"request","command":"disassemble","arguments":{"memoryReference":"0x0104","instructionCount":2}}"#.to_string(),
+        r#"{"seq":11,"type":"request","command":"continue","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":12,"type":"request","command":"disconnect"}"#.to_string(),
+    ];
+    let messages = session(&requests);
+    fs::remove_file(&program).unwrap();
+    fs::remove_file(&listing).unwrap();
+
+    assert_eq!(response(&messages, 1).get("body").get("supportsStepBack").as_bool(), Some(true));
+    assert_eq!(response(&messages, 2).get("success").as_bool(), Some(true));
+    assert_eq!(events(&messages, "initialized").len(), 1);
+
+    let bps = response(&messages, 3).get("body").get("breakpoints").as_array();
+    assert_eq!(bps[0].get("verified").as_bool(), Some(true));
+    assert_eq!(bps[0].get("instructionReference").as_str(), Some("0x0104"));
+    assert_eq!(bps[1].get("verified").as_bool(), Some(false));
+
+    let reasons = events(&messages, "stopped").iter()
+        .map(|e| e.get("body").get("reason").as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(reasons, ["entry", "breakpoint", "step"]);
+
+    let frame = &response(&messages, 6).get("body").get("stackFrames").as_array()[0];
+    assert_eq!(frame.get("line").as_i64(), Some(5));
+    assert_eq!(frame.get("name").as_str(), Some("START+4"));
+
+    let vars = response(&messages, 8).get("body").get("variables").as_array();
+    let pc = vars.iter().find(|v| v.get("name").as_str() == Some("PC")).unwrap();
+    assert_eq!(pc.get("value").as_str(), Some("0x0107"));
+
+    let memory = response(&messages, 9).get("body");
+    assert_eq!(memory.get("data").as_str(), Some("DgIeQQ=="));
+
+    let ins = response(&messages, 10).get("body").get("instructions").as_array();
+    assert_eq!(ins[0].get("instruction").as_str(), Some("CALL BDOS"));
+    assert_eq!(ins[1].get("symbol").as_str(), Some("DONE"));
+
+    let output = events(&messages, "output");
+    assert_eq!(output[0].get("body").get("output").as_str(), Some("A"));
+    assert_eq!(events(&messages, "exited").len(), 1);
+    assert_eq!(events(&messages, "terminated").len(), 1);
+    assert_eq!(response(&messages, 12).get("success").as_bool(), Some(true));
+}
+
+// Launches `program`, with the listing next to it if `stop_on_entry`.
+fn launch(seq: i64, program: &std::path::Path, stop_on_entry: bool) -> String {
+    let mut args = vec![("program".to_string(), program.to_string_lossy().as_ref().into())];
+    if stop_on_entry {
+        args.push(("listing".to_string(), program.with_extension("prn").to_string_lossy().as_ref().into()));
+        args.push(("stopOnEntry".to_string(), true.into()));
+    }
+    Value::object([
+        ("seq", seq.into()),
+        ("type", "request".into()),
+        ("command", "launch".into()),
+        ("arguments", Value::Object(args)),
+    ]).to_string()
+}
+
+#[test]
+fn test_disconnect_while_running() {
+    let program = std::env::temp_dir().join(format!("i8080_dap_loop_{}.com", std::process::id()));
+    fs::write(&program, [0xc3, 0x00, 0x01]).unwrap(); // JMP $
+    let requests = [
+        launch(1, &program, false),
+        r#"{"seq":2,"type":"request","command":"configurationDone"}"#.to_string(),
+        r#"{"seq":3,"type":"request","command":"threads"}"#.to_string(),
+        r#"{"seq":4,"type":"request","command":"disconnect"}"#.to_string(),
+    ];
+    let messages = session(&requests);
+    fs::remove_file(&program).unwrap();
+
+    assert_eq!(response(&messages, 4).get("success").as_bool(), Some(true));
+    assert!(events(&messages, "stopped").is_empty());
+}
+
+#[test]
+fn test_disconnect_while_stepping_over() {
+    let program = std::env::temp_dir().join(format!("i8080_dap_next_{}.com", std::process::id()));
+    let listing = program.with_extension("prn");
+    fs::write(&program, [0xcd, 0x04, 0x01, 0x00, 0xc3, 0x04, 0x01]).unwrap(); // CALL L; NOP; L: JMP L
+    fs::write(&listing, concat!(
+        " 0100                   ORG     100H\n",
+        " 0100 CD0401            CALL    L\n",
+        " 0103 00                NOP\n",
+        " 0104 C30401    L:      JMP     L\n",
+    )).unwrap();
+    let requests = [
+        launch(1, &program, true),
+        r#"{"seq":2,"type":"request","command":"configurationDone"}"#.to_string(),
+        r#"{"seq":3,"type":"request","command":"next","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":4,"type":"request","command":"disconnect"}"#.to_string(),
+    ];
+    let messages = session(&requests);
+    fs::remove_file(&program).unwrap();
+    fs::remove_file(&listing).unwrap();
+
+    assert_eq!(response(&messages, 4).get("success").as_bool(), Some(true));
+    let reasons = events(&messages, "stopped").iter()
+        .map(|e| e.get("body").get("reason").as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(reasons, ["entry"]);
+}
+
+#[test]
+fn test_breakpoints_per_source() {
+    let program = std::env::temp_dir().join(format!("i8080_dap_bps_{}.com", std::process::id()));
+    let listing = program.with_extension("prn");
+    fs::write(&program, PROGRAM).unwrap();
+    fs::write(&listing, LISTING).unwrap();
+    let source = program.with_extension("asm").to_string_lossy().into_owned();
+
+    let set = |seq: i64, path: &str, lines: &[i64]| {
+        let lines = lines.iter().map(|&l| Value::object([("line", l.into())])).collect::<Vec<_>>();
+        Value::object([
+            ("seq", seq.into()),
+            ("type", "request".into()),
+            ("command", "setBreakpoints".into()),
+            ("arguments", Value::object([
+                ("source", Value::object([("path", path.into())])),
+                ("breakpoints", lines.into()),
+            ])),
+        ]).to_string()
+    };
+    let requests = [
+        launch(1, &program, true),
+        set(2, &source, &[5]),
+        r#"{"seq":3,"type":"request","command":"setInstructionBreakpoints","arguments":{"breakpoints":[{"instructionReference":"0x0104"},{"instructionReference":"0x0107"}]}}"#.to_string(),
+        // Neither another file nor clearing this one drops 0104H, which an
+        // instruction breakpoint still asks for.
+        set(4, "other.asm", &[]),
+        set(5, &source, &[]),
+        r#"{"seq":6,"type":"request","command":"configurationDone"}"#.to_string(),
+        r#"{"seq":7,"type":"request","command":"continue","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":8,"type":"request","command":"disassemble","arguments":{"memoryReference":"0x0104","instructionOffset":-9223372036854775807,"instructionCount":1}}"#.to_string(),
+        r#"{"seq":9,"type":"request","command":"continue","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":10,"type":"request","command":"disconnect"}"#.to_string(),
+    ];
+    let messages = session(&requests);
+    fs::remove_file(&program).unwrap();
+    fs::remove_file(&listing).unwrap();
+
+    let reasons = events(&messages, "stopped").iter()
+        .map(|e| e.get("body").get("reason").as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(reasons, ["entry", "breakpoint", "breakpoint"]);
+    let ins = response(&messages, 8).get("body").get("instructions").as_array();
+    assert_eq!(ins[0].get("address").as_str(), Some("0x0000"));
+    assert_eq!(events(&messages, "exited").len(), 0);
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::io;
+
+use crate::altair::*;
+use crate::dcdd::*;
+use crate::device::{Clocked, PortDevice};
+use crate::serial::Line;
+
+fn pattern() -> Vec<u8> {
+    (0..DISK_SIZE).map(|i| (i / SECTOR_SIZE + i % SECTOR_SIZE) as u8).collect()
+}
+
+fn sector(image: &[u8], track: usize, sector: usize) -> &[u8] {
+    let start = (track * SECTORS + sector) * SECTOR_SIZE;
+    &image[start..start + SECTOR_SIZE]
+}
+
+#[test]
+fn test_rotation() {
+    let mut dcdd = Dcdd::new();
+    dcdd.insert_image(0, pattern());
+    assert_eq!(dcdd.read_port(0x08), 0xff);
+    dcdd.write_port(0x08, 0x00);
+    // Head not loaded, on track 0.
+    assert_eq!(dcdd.read_port(0x08), 0b1010_0101);
+    assert_eq!(dcdd.read_port(0x09), 0xff);
+    dcdd.write_port(0x09, 0x04 | 0x01);
+    assert_eq!(dcdd.read_port(0x08), 0b0110_0001);
+
+    dcdd.tick(SECTOR_CYCLES * 3 + 1);
+    assert_eq!(dcdd.read_port(0x09), 0xc0 | 3 << 1);
+    dcdd.tick(SECTOR_CYCLES * 3 + SECTOR_CYCLES / 2);
+    assert_eq!(dcdd.read_port(0x09), 0xc0 | 3 << 1 | 1);
+    // A full turn later the same sector comes round again.
+    dcdd.tick(SECTOR_CYCLES * (SECTORS as u64 + 3));
+    assert_eq!(dcdd.read_port(0x09), 0xc0 | 3 << 1);
+
+    // Deselect.
+    dcdd.write_port(0x08, 0x80);
+    assert_eq!(dcdd.read_port(0x08), 0xff);
+}
+
+#[test]
+fn test_read_sector() {
+    let mut altair = Altair::new(0x10000, Line::new(Box::new(io::sink())));
+    altair.disk.borrow_mut().insert_image(0, pattern());
+    altair.load(&[(0x0000, vec![
+        0x3e, 0x00, 0xd3, 0x08, // select drive 0
+        0x3e, 0x04, 0xd3, 0x09, // load head
+        0x3e, 0x01, 0xd3, 0x09, // step in
+        0x21, 0x00, 0x10,       // LXI H,1000H
+        0x06, 0x89,             // MVI B,137
+        0xdb, 0x09,             // wait: IN 09H
+        0x1f,                   // RAR
+        0xda, 0x11, 0x00,       // JC wait
+        0xe6, 0x1f,             // ANI 1FH
+        0xfe, 0x02,             // CPI 2
+        0xc2, 0x11, 0x00,       // JNZ wait
+        0xdb, 0x08,             // read: IN 08H
+        0xb7,                   // ORA A
+        0xfa, 0x1e, 0x00,       // JM read
+        0xdb, 0x0a,             // IN 0AH
+        0x77,                   // MOV M,A
+        0x23,                   // INX H
+        0x05,                   // DCR B
+        0xc2, 0x1e, 0x00,       // JNZ read
+        0xc3, 0x2c, 0x00,       // JMP $
+    ])]);
+    while altair.cpu.pc != 0x002c {
+        altair.cpu.next().unwrap();
+        assert!(altair.cpu.cycles < 1_000_000);
+    }
+    let data = (0x1000..0x1000 + SECTOR_SIZE as u16).map(|a| altair.cpu.ram.load_byte(a)).collect::<Vec<_>>();
+    assert_eq!(data, sector(&pattern(), 1, 2));
+}
+
+#[test]
+fn test_write_back() {
+    let path = std::env::temp_dir().join(format!("i8080_dcdd_{}.dsk", std::process::id()));
+    fs::write(&path, sector(&pattern(), 0, 0)).unwrap();
+
+    let mut dcdd = Dcdd::new();
+    dcdd.insert(1, &path).unwrap();
+    dcdd.write_port(0x08, 0x01);
+    dcdd.write_port(0x09, 0x04);
+    dcdd.tick(SECTOR_CYCLES * 5);
+    assert_eq!(dcdd.read_port(0x09) & 1, 0);
+    dcdd.write_port(0x09, 0x80);
+    for i in 0..SECTOR_SIZE {
+        assert_eq!(dcdd.read_port(0x08) & 0x01, 0);
+        dcdd.write_port(0x0a, i as u8 ^ 0x55);
+    }
+    // ENWD goes high once the sector is full.
+    assert_eq!(dcdd.read_port(0x08) & 0x01, 1);
+    dcdd.flush().unwrap();
+
+    let image = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(image.len(), DISK_SIZE);
+    assert_eq!(sector(&image, 0, 0), sector(&pattern(), 0, 0));
+    let written = (0..SECTOR_SIZE).map(|i| i as u8 ^ 0x55).collect::<Vec<_>>();
+    assert_eq!(sector(&image, 0, 5), written.as_slice());
+    assert!(sector(&image, 0, 6).iter().all(|&b| b == 0xe5));
+}
@@ -0,0 +1,139 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::debugger::*;
+use crate::interrupt::RstLatch;
+use crate::utils::parse_hex;
+
+#[test]
+fn test_step_back_restores_registers_and_memory() {
+    // MVI A,42h; STA 2000h; INR A
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x3e, 0x42, 0x32, 0x00, 0x20, 0x3c]);
+    cpu.record(16);
+    cpu.ram.save_byte(0x2000, 0x11);
+    cpu.next().unwrap();
+    cpu.next().unwrap();
+    cpu.next().unwrap();
+    assert_eq!(cpu.a, 0x43);
+    assert_eq!(cpu.ram.load_byte(0x2000), 0x42);
+
+    cpu.step_back().unwrap();
+    assert_eq!(cpu.a, 0x42);
+    assert_eq!(cpu.pc, 0x0005);
+    cpu.step_back().unwrap();
+    assert_eq!(cpu.ram.load_byte(0x2000), 0x11);
+    assert_eq!(cpu.pc, 0x0002);
+    cpu.step_back().unwrap();
+    assert_eq!(cpu.a, 0x00);
+    assert_eq!(cpu.pc, 0x0000);
+    assert!(cpu.step_back().is_none());
+}
+
+#[test]
+fn test_journal_is_a_ring_buffer() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x3c; 8]);
+    cpu.record(3);
+    for _ in 0..8 {
+        cpu.next().unwrap();
+    }
+    assert_eq!(cpu.a, 8);
+    while cpu.step_back().is_some() {}
+    assert_eq!(cpu.a, 5);
+    assert_eq!(cpu.pc, 0x0005);
+}
+
+#[test]
+fn test_step_back_undoes_stack_writes() {
+    // LXI SP,3000h; CALL 0010h
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x31, 0x00, 0x30, 0xcd, 0x10, 0x00]);
+    cpu.record(16);
+    cpu.next().unwrap();
+    cpu.next().unwrap();
+    assert_eq!(cpu.ram.load_word(0x2ffe), 0x0006);
+    cpu.step_back().unwrap();
+    assert_eq!(cpu.sp, 0x3000);
+    assert_eq!(cpu.ram.load_word(0x2ffe), 0x0000);
+}
+
+#[test]
+fn test_back_to_write() {
+    // LXI H,2000h; MVI M,01h; INX H; MVI M,02h; NOP; NOP
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x21, 0x00, 0x20, 0x36, 0x01, 0x23, 0x36, 0x02, 0x00, 0x00]);
+    let mut dbg = Debugger::new(cpu);
+    for _ in 0..6 {
+        dbg.step().unwrap();
+    }
+    assert_eq!(dbg.back_to_write(0x2000), Stop::Write(0x2000));
+    assert_eq!(dbg.cpu.pc, 0x0003);
+    assert_eq!(dbg.cpu.ram.load_byte(0x2000), 0x00);
+    assert_eq!(dbg.cpu.ram.load_byte(0x2001), 0x00);
+}
+
+#[test]
+fn test_reverse_continue_to_breakpoint() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x3c; 16]);
+    let mut dbg = Debugger::new(cpu);
+    dbg.add_breakpoint(0x0004);
+    dbg.add_breakpoint(0x000a);
+    assert_eq!(dbg.cont().unwrap(), Stop::Breakpoint(0x0004));
+    assert_eq!(dbg.cont().unwrap(), Stop::Breakpoint(0x000a));
+    assert_eq!(dbg.reverse_cont(), Stop::Breakpoint(0x0004));
+    assert_eq!(dbg.cpu.a, 4);
+    assert_eq!(dbg.reverse_cont(), Stop::JournalStart);
+    assert_eq!(dbg.cpu.pc, 0x0000);
+}
+
+#[test]
+fn test_step_over_and_out_poll() {
+    // 0000 CALL 0010h; NOP / 0010 JMP 0010h
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0xcd, 0x10, 0x00, 0x00]);
+    cpu.ram.load_at(0x10, &[0xc3, 0x10, 0x00]);
+    cpu.sp = 0x1000;
+    let mut dbg = Debugger::new(cpu);
+    let mut polls = 0;
+    assert_eq!(dbg.step_over_with(|| { polls += 1; polls == 3 }).unwrap(), Stop::Interrupted);
+    assert_eq!(dbg.cpu.pc, 0x0010);
+
+    polls = 0;
+    assert_eq!(dbg.step_out_with(|| { polls += 1; polls == 3 }).unwrap(), Stop::Interrupted);
+    assert_eq!(dbg.cpu.pc, 0x0010);
+    assert_eq!(dbg.cpu.sp, 0x0ffe);
+}
+
+#[test]
+fn test_parse_hex() {
+    assert_eq!(parse_hex("1a3"), Some(0x01a3));
+    assert_eq!(parse_hex("0x1A3"), Some(0x01a3));
+    assert_eq!(parse_hex("01A3H"), Some(0x01a3));
+    assert_eq!(parse_hex("zz"), None);
+}
+
+#[test]
+fn test_step_back_over_ei_keeps_the_delay() {
+    // EI; NOP; NOP with RST 2 requested throughout
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0xfb, 0x00, 0x00]);
+    let latch = Rc::new(RefCell::new(RstLatch::new(2)));
+    latch.borrow_mut().set(true);
+    cpu.connect_interrupts(&latch);
+    cpu.sp = 0x1000;
+    cpu.record(16);
+    cpu.next().unwrap();
+    cpu.next().unwrap();
+    assert_eq!(cpu.pc, 0x0002);
+
+    // Back before the NOP: EI's delay must still hold it off.
+    cpu.step_back().unwrap();
+    assert_eq!(cpu.pc, 0x0001);
+    cpu.next().unwrap();
+    assert_eq!(cpu.pc, 0x0002);
+    cpu.next().unwrap();
+    assert_eq!(cpu.pc, 0x0010);
+}
@@ -0,0 +1,144 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::device::PortDevice;
+use crate::dma::*;
+use crate::dram::Dram;
+
+// A peripheral requesting for as long as it has data, or room for more.
+#[derive(Default)]
+struct Fifo {
+    source: VecDeque<u8>,
+    sink: Vec<u8>,
+    room: usize,
+    tc: bool,
+}
+
+impl DmaDevice for Fifo {
+    fn dreq(&self) -> bool {
+        !self.source.is_empty() || self.sink.len() < self.room
+    }
+
+    fn io_read(&mut self) -> u8 {
+        self.source.pop_front().unwrap_or(0xff)
+    }
+
+    fn io_write(&mut self, byte: u8) {
+        self.sink.push(byte);
+    }
+
+    fn terminal_count(&mut self) {
+        self.tc = true;
+    }
+}
+
+#[test]
+fn test_cycle_stealing() {
+    let fifo = Rc::new(RefCell::new(Fifo { source: (0..32).collect(), ..Default::default() }));
+    let dma = Rc::new(RefCell::new(Dma::new()));
+    dma.borrow_mut().connect(1, &fifo);
+    let mut cpu = Cpu::new();
+    cpu.attach(0x00..0x09, &dma);
+    cpu.connect_hold(&dma);
+    let program = [
+        0x3e, 0x00, 0xd3, 0x02, 0x3e, 0x20, 0xd3, 0x02, // channel 1 at 2000H
+        0x3e, 0x0f, 0xd3, 0x03, 0x3e, 0x40, 0xd3, 0x03, // 16 bytes to memory
+        0x3e, 0x42, 0xd3, 0x08,                         // TC stop, enable 1
+        0xc3, 0x14, 0x00,                               // JMP $
+    ];
+    for (i, b) in program.iter().enumerate() {
+        cpu.ram.save_byte(i as u16, *b);
+    }
+    while cpu.pc != 0x14 {
+        cpu.next().unwrap();
+    }
+    let cycles = cpu.cycles;
+    cpu.next().unwrap();
+    assert_eq!(cpu.cycles - cycles, 16 * DMA_CYCLE + 10);
+    for i in 0..16 {
+        assert_eq!(cpu.ram.load_byte(0x2000 + i), i as u8);
+    }
+    assert_eq!(cpu.ram.load_byte(0x2010), 0);
+
+    // The channel stopped at terminal count though the device has more.
+    assert!(fifo.borrow().tc);
+    assert_eq!(fifo.borrow().source.len(), 16);
+    let mut dma = dma.borrow_mut();
+    assert_eq!(dma.address(1), 0x2010);
+    assert_eq!(dma.count(1), 0x3fff);
+    assert_eq!(dma.read_port(8), 0x02);
+    assert_eq!(dma.read_port(8), 0x00);
+    assert!(!dma.hold());
+}
+
+#[test]
+fn test_hold_between_machine_cycles() {
+    let fifo = Rc::new(RefCell::new(Fifo::default()));
+    let dma = Rc::new(RefCell::new(Dma::new()));
+    dma.borrow_mut().connect(1, &fifo);
+    for (port, byte) in [(2, 0x00), (2, 0x20), (3, 0x0f), (3, 0x40), (8, 0x42)] {
+        dma.borrow_mut().write_port(port, byte);
+    }
+    let mut cpu = Cpu::new();
+    cpu.connect_hold(&dma);
+    cpu.ram.load_at(0, &[0x3a, 0x00, 0x20]); // LDA 2000H
+
+    // The device asks for the bus once the opcode fetch is over.
+    assert_eq!(cpu.step_cycle().unwrap().states, 4);
+    fifo.borrow_mut().source.extend([0x11, 0x22, 0x33]);
+    let cycle = cpu.step_cycle().unwrap();
+    assert_eq!((cycle.address, cycle.states), (0x0001, 3));
+    assert_eq!(cpu.cycles, 4 + 3 * DMA_CYCLE + 3);
+    assert_eq!(cpu.ram.load_byte(0x2002), 0x33);
+    cpu.step_cycle().unwrap();
+    cpu.step_cycle().unwrap();
+    assert_eq!(cpu.cycles, 13 + 3 * DMA_CYCLE);
+}
+
+#[test]
+fn test_autoload() {
+    let mut ram = Dram::new();
+    ram.load_at(0x0100, &[0xaa, 0xbb, 0xcc]);
+    let fifo = Rc::new(RefCell::new(Fifo { room: 5, ..Default::default() }));
+    let mut dma = Dma::new();
+    dma.connect(2, &fifo);
+    dma.write_port(8, 0x80); // autoload first, so channel 3 gets a copy
+    for (port, byte) in [(4, 0x00), (4, 0x01), (5, 0x01), (5, 0x80)] {
+        dma.write_port(port, byte);
+    }
+    dma.write_port(8, 0x84);
+    while dma.hold() {
+        assert_eq!(dma.bus_cycle(&mut ram), DMA_CYCLE);
+    }
+    assert_eq!(fifo.borrow().sink, [0xaa, 0xbb, 0xaa, 0xbb, 0xaa]);
+    assert_eq!(dma.read_port(8), 0x14);
+    assert_eq!(dma.address(2), 0x0101);
+    // Registers read back LSB first.
+    assert_eq!(dma.read_port(4), 0x01);
+    assert_eq!(dma.read_port(4), 0x01);
+}
+
+#[test]
+fn test_priority() {
+    let mut ram = Dram::new();
+    let a = Rc::new(RefCell::new(Fifo { room: 3, ..Default::default() }));
+    let b = Rc::new(RefCell::new(Fifo { room: 3, ..Default::default() }));
+    let mut dma = Dma::new();
+    dma.connect(0, &a);
+    dma.connect(1, &b);
+    for port in [1, 3] {
+        dma.write_port(port, 0x10);
+        dma.write_port(port, 0x80);
+    }
+    let served = |dma: &mut Dma, ram: &mut Dram| {
+        let before = (a.borrow().sink.len(), b.borrow().sink.len());
+        dma.bus_cycle(ram);
+        if a.borrow().sink.len() > before.0 { 0 } else { 1 }
+    };
+    dma.write_port(8, 0x03);
+    assert_eq!([0, 0].map(|_| served(&mut dma, &mut ram)), [0, 0]);
+    dma.write_port(8, 0x13);
+    assert_eq!([0, 0, 0].map(|_| served(&mut dma, &mut ram)), [1, 0, 1]);
+}
@@ -0,0 +1,125 @@
+use crate::cpu::*;
+use crate::utils::{flagged_add, flagged_sub};
+
+fn flag_byte(carry: bool, parity: bool, aux: bool, zero: bool, sign: bool) -> u8 {
+    u8::from(sign) << SIGN_BIT | u8::from(zero) << ZERO_BIT | u8::from(aux) << AUXILIARY_CARRY_BIT
+        | u8::from(parity) << PARITY_BIT | 0x02 | u8::from(carry) << CARRY_BIT
+}
+
+// The accumulator and flags each op left when every flag was worked out
+// as it ran.
+fn eager(op: u8, a: u8, b: u8, c: bool) -> (u8, u8) {
+    let ci = u8::from(c);
+    let logical = |res: u8, aux| (res, flag_byte(false, res.count_ones().is_multiple_of(2), aux, res == 0, res >= 0x80));
+    match op {
+        0x80 | 0x88 => {
+            let ci = if op == 0x88 { ci } else { 0 };
+            let (res, _, parity, _, zero, sign) = flagged_add(a, b.wrapping_add(ci));
+            let carry = u16::from(a) + u16::from(b) + u16::from(ci) > 0xff;
+            (res, flag_byte(carry, parity, (a & 0xf) + (b & 0xf) + ci > 0xf, zero, sign))
+        },
+        0x90 | 0x98 | 0xb8 => {
+            let ci = if op == 0x98 { ci } else { 0 };
+            let (res, _, parity, _, zero, sign) = flagged_sub(a, b.wrapping_add(ci));
+            let carry = u16::from(a) < u16::from(b) + u16::from(ci);
+            let aux = (a as i8 & 0x0f) - (b as i8 & 0x0f) - (ci as i8) >= 0;
+            (if op == 0xb8 { a } else { res }, flag_byte(carry, parity, aux, zero, sign))
+        },
+        0xa0 => logical(a & b, (a | b) & 0x08 != 0),
+        0xa8 => logical(a ^ b, false),
+        0xb0 => logical(a | b, false),
+        0x3c => {
+            let (res, _, parity, aux, zero, sign) = flagged_add(a, 1);
+            (res, flag_byte(c, parity, aux, zero, sign))
+        },
+        0x3d => {
+            let (res, _, parity, aux, zero, sign) = flagged_sub(a, 1);
+            (res, flag_byte(c, parity, aux, zero, sign))
+        },
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_lazy_flags_match_eager() {
+    // ADD, ADC, SUB, SBB, ANA, XRA, ORA, CMP B, INR A and DCR A.
+    let ops = [0x80, 0x88, 0x90, 0x98, 0xa0, 0xa8, 0xb0, 0xb8, 0x3c, 0x3d];
+    let mut cpu = Cpu::new();
+    for op in ops {
+        cpu.ram.save_byte(0, op);
+        for a in 0..=255 {
+            for b in 0..=255 {
+                for c in [false, true] {
+                    (cpu.pc, cpu.a, cpu.b) = (0, a, b);
+                    cpu.set_flag_byte(0x02 | u8::from(c));
+                    cpu.next().unwrap();
+                    assert_eq!((cpu.a, cpu.flags()), eager(op, a, b, c), "op {op:02x} a {a:02x} b {b:02x} c {c}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_conditionals_read_pending_flags() {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0x1000;
+    cpu.ram.load_at(0, &[
+        0x3e, 0x7f,       // MVI A,7FH
+        0xc6, 0x01,       // ADI 1
+        0xfa, 0x09, 0x00, // JM 0009H
+        0x00, 0x00,
+        0xe2, 0x0e, 0x00, // JPO 000EH
+        0x00, 0x00,
+        0x3d,             // DCR A
+        0x3f,             // CMC
+        0xca, 0x14, 0x00, // JZ 0014H
+        0xf5,             // PUSH PSW
+    ]);
+    for _ in 0..8 {
+        cpu.next().unwrap();
+    }
+    assert_eq!(cpu.pc, 0x0014);
+    // 7FH + 1 is negative with odd parity. DCR A gives 7FH, with no
+    // auxiliary carry and odd parity, and CMC sets carry without disturbing
+    // them.
+    assert_eq!(cpu.sp, 0x0ffe);
+    assert_eq!(cpu.ram.load_word(cpu.sp), 0x7f03);
+    assert_eq!(cpu.flags(), 0x03);
+}
+
+#[test]
+fn test_pop_psw_replaces_pending_result() {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0x1000;
+    cpu.ram.save_word(0x1000, 0x00d7);
+    cpu.ram.load_at(0, &[
+        0xaf, // XRA A
+        0xf1, // POP PSW
+    ]);
+    cpu.next().unwrap();
+    assert!(cpu.get_flag(ZERO_BIT));
+    cpu.next().unwrap();
+    // Zero and sign together, which no single result gives.
+    assert_eq!(cpu.flags(), 0xd7);
+    assert!(cpu.get_flag(ZERO_BIT) && cpu.get_flag(SIGN_BIT));
+}
+
+#[test]
+fn test_step_back_restores_flags() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[
+        0x3e, 0x0f, // MVI A,0FH
+        0xc6, 0x01, // ADI 1
+        0xe6, 0x00, // ANI 0
+    ]);
+    cpu.record(8);
+    for _ in 0..3 {
+        cpu.next().unwrap();
+    }
+    assert_eq!(cpu.flags(), 0x46);
+    cpu.step_back();
+    // 0FH + 1: auxiliary carry.
+    assert_eq!((cpu.a, cpu.flags()), (0x10, 0x12));
+    assert!(cpu.get_flag(AUXILIARY_CARRY_BIT));
+}
@@ -0,0 +1,73 @@
+use std::io::Cursor;
+
+use crate::cpu::*;
+use crate::debugger::*;
+use crate::gdb;
+
+fn packet(data: &str) -> String {
+    let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${}#{:02x}", data, sum)
+}
+
+// Runs a session and returns the reply packets, acks stripped.
+fn session(cpu: Cpu, packets: &[&str]) -> (Vec<String>, Debugger) {
+    let input = packets.iter().map(|p| packet(p)).collect::<String>();
+    let mut out = Vec::new();
+    let dbg = gdb::serve(Debugger::new(cpu), Cursor::new(input.into_bytes()), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let replies = out.split('$')
+        .skip(1)
+        .map(|p| p.split('#').next().unwrap().to_string())
+        .collect();
+    (replies, dbg)
+}
+
+#[test]
+fn test_registers() {
+    let mut cpu = Cpu::new();
+    cpu.a = 0x12;
+    cpu.h = 0x34;
+    cpu.sp = 0xabcd;
+    cpu.pc = 0x0100;
+    let (replies, dbg) = session(cpu, &["g", "p8", "P9=0002", "P0=ff", "g"]);
+    assert_eq!(replies[0], "1202000000003400cdab0001");
+    assert_eq!(replies[1], "cdab");
+    assert_eq!(replies[2], "OK");
+    assert_eq!(replies[3], "OK");
+    assert_eq!(replies[4], "ff02000000003400cdab0002");
+    assert_eq!(dbg.cpu.pc, 0x0200);
+}
+
+#[test]
+fn test_memory() {
+    let (replies, dbg) = session(Cpu::new(), &["M2000,3:aabbcc", "m1fff,5", "mffff,2"]);
+    assert_eq!(replies, ["OK", "00aabbcc00", "E01"]);
+    assert_eq!(dbg.cpu.ram.load_byte(0x2001), 0xbb);
+}
+
+#[test]
+fn test_step_and_breakpoints() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x3c; 16]);
+    let (replies, dbg) = session(cpu, &["s", "Z0,8,1", "c", "z0,8,1", "Z1,c,1", "c", "bc", "bs"]);
+    assert_eq!(replies[..6], ["S05", "OK", "S05", "OK", "OK", "S05"]);
+    // Stopped at 000c, then reverse-continued to the start of the journal
+    // (the 0008 breakpoint was removed), where a reverse step goes nowhere.
+    assert_eq!(replies[6..], ["T05replaylog:begin;", "T05replaylog:begin;"]);
+    assert_eq!(dbg.cpu.pc, 0x0000);
+    assert_eq!(dbg.cpu.a, 0);
+}
+
+#[test]
+fn test_queries_and_detach() {
+    let (replies, _) = session(Cpu::new(), &["qSupported:swbreak+", "QStartNoAckMode", "vMustReplyEmpty", "?", "D", "g"]);
+    assert!(replies[0].contains("ReverseContinue+"));
+    assert_eq!(replies[1..], ["OK", "", "S05", "OK"]);
+}
+
+#[test]
+fn test_bad_checksum_is_nacked() {
+    let mut out = Vec::new();
+    gdb::serve(Debugger::new(Cpu::new()), Cursor::new(b"$g#00".to_vec()), &mut out).unwrap();
+    assert_eq!(out, b"-");
+}
@@ -0,0 +1,212 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::disasm::*;
+use crate::i8085::Input;
+use crate::symbols::Symbols;
+
+fn i8085(program: &[u8]) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.variant = Variant::I8085;
+    cpu.sp = 0x1000;
+    cpu.ram.load_at(0, program);
+    cpu
+}
+
+fn step(cpu: &mut Cpu, n: usize) {
+    for _ in 0..n {
+        cpu.next().unwrap();
+    }
+}
+
+#[test]
+fn test_decode_and_timings() {
+    let mut cpu = i8085(&[0x41, 0xcd, 0x00, 0x01, 0x20, 0xdd, 0x34, 0x12]);
+    assert_eq!(disassemble(&cpu.ram, 0x0004, &Symbols::new(), Variant::I8085).0, "RIM");
+    assert_eq!(disassemble(&cpu.ram, 0x0005, &Symbols::new(), Variant::I8085), ("JNK 1234H".to_string(), 3));
+    assert_eq!(disassemble(&cpu.ram, 0x0004, &Symbols::new(), Variant::I8080).0, "NOP");
+
+    step(&mut cpu, 2);
+    assert_eq!(cpu.cycles, 4 + 18);
+    cpu.variant = Variant::I8080;
+    cpu.pc = 0;
+    step(&mut cpu, 2);
+    assert_eq!(cpu.cycles, 22 + 5 + 17);
+}
+
+#[test]
+fn test_conditional_timings() {
+    // With Z set: JNZ, CNZ, RNZ and RSTV fall through, then JZ, CZ and RZ
+    // are taken.
+    let mut cpu = i8085(&[
+        0xc2, 0x00, 0x00, // JNZ 0000H
+        0xc4, 0x00, 0x00, // CNZ 0000H
+        0xc0,             // RNZ
+        0xcb,             // RSTV
+        0xca, 0x0b, 0x00, // JZ 000BH
+        0xcc, 0x10, 0x00, // CZ 0010H
+    ]);
+    cpu.ram.save_byte(0x0010, 0xc8); // RZ
+    cpu.set_flag(ZERO_BIT, true);
+    cpu.set_flag(OVERFLOW_BIT, false);
+    let mut states = Vec::new();
+    for _ in 0..7 {
+        let cycles = cpu.cycles;
+        cpu.next().unwrap();
+        states.push(cpu.cycles - cycles);
+    }
+    assert_eq!(states, [7, 9, 6, 6, 10, 18, 12]);
+    assert_eq!(cpu.pc, 0x000e);
+
+    // A jump not taken leaves out the read of its high address byte.
+    let mut cpu = i8085(&[0xc2, 0x00, 0x00]);
+    cpu.set_flag(ZERO_BIT, true);
+    let cycles = cpu.step_instruction_cycles().unwrap();
+    assert_eq!(cycles.iter().map(|c| (c.address, c.states)).collect::<Vec<_>>(), [(0x0000, 4), (0x0001, 3)]);
+}
+
+#[test]
+fn test_undocumented() {
+    let mut cpu = i8085(&[
+        0x08,             // DSUB
+        0x10,             // ARHL
+        0x18,             // RDEL
+        0x28, 0x10,       // LDHI 10H
+        0xd9,             // SHLX
+        0x38, 0x02,       // LDSI 02H
+        0xed,             // LHLX
+    ]);
+    (cpu.h, cpu.l, cpu.b, cpu.c) = (0x90, 0x00, 0x10, 0x01);
+    step(&mut cpu, 1);
+    assert_eq!((cpu.h, cpu.l), (0x7f, 0xff));
+    // 9000H - 1001H overflows into a positive result.
+    assert!(cpu.get_flag(OVERFLOW_BIT) && !cpu.get_flag(SIGN_BIT) && cpu.get_flag(K_BIT));
+    assert!(!cpu.get_flag(CARRY_BIT));
+
+    step(&mut cpu, 1);
+    assert_eq!((cpu.h, cpu.l), (0x3f, 0xff));
+    assert!(cpu.get_flag(CARRY_BIT));
+    (cpu.d, cpu.e) = (0x80, 0x01);
+    step(&mut cpu, 1);
+    assert_eq!((cpu.d, cpu.e), (0x00, 0x03));
+    assert!(cpu.get_flag(CARRY_BIT));
+
+    step(&mut cpu, 2);
+    assert_eq!((cpu.d, cpu.e), (0x40, 0x0f));
+    assert_eq!(cpu.ram.load_word(0x400f), 0x3fff);
+    cpu.ram.save_word(0x1002, 0xbeef);
+    step(&mut cpu, 2);
+    assert_eq!((cpu.h, cpu.l), (0xbe, 0xef));
+}
+
+#[test]
+fn test_v_and_k() {
+    let mut cpu = i8085(&[
+        0x3e, 0x7f,       // MVI A,7FH
+        0xc6, 0x01,       // ADI 1
+        0xcb,             // RSTV
+    ]);
+    step(&mut cpu, 2);
+    assert!(cpu.get_flag(OVERFLOW_BIT) && !cpu.get_flag(K_BIT));
+    let cycles = cpu.cycles;
+    step(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0040);
+    assert_eq!(cpu.cycles - cycles, 12);
+
+    // K after DCX from 0, for counted loops.
+    let mut cpu = i8085(&[0x01, 0x01, 0x00, 0x0b, 0xdd, 0x03, 0x00, 0x76]);
+    let mut loops = 0;
+    while cpu.pc != 0x0007 {
+        cpu.next().unwrap();
+        loops += (cpu.pc == 0x0003) as u32;
+    }
+    assert_eq!(loops, 2);
+    assert_eq!((cpu.b, cpu.c), (0xff, 0xff));
+
+    // On the 8080 the same bits stay as they were.
+    let mut cpu = i8085(&[0x3e, 0x7f, 0xc6, 0x01]);
+    cpu.variant = Variant::I8080;
+    step(&mut cpu, 2);
+    assert_eq!(cpu.flags() & 0x22, 0x02);
+}
+
+#[test]
+fn test_memory_operand_read_once() {
+    // V and K come from the byte ADD M read, not a second read of it.
+    let mut cpu = i8085(&[0x21, 0x00, 0x20, 0x86]); // LXI H,2000H; ADD M
+    cpu.ram.save_byte(0x2000, 0x01);
+    cpu.ram.set_wait_states(0x2000..0x2001, 2);
+    cpu.a = 0x7f;
+    step(&mut cpu, 1);
+    let cycles = cpu.cycles;
+    step(&mut cpu, 1);
+    assert_eq!(cpu.cycles - cycles, 7 + 2);
+    assert!(cpu.get_flag(OVERFLOW_BIT));
+}
+
+#[test]
+fn test_and_sets_aux() {
+    let mut cpu = i8085(&[
+        0xe6, 0x00, // ANI 0
+        0xa0,       // ANA B
+    ]);
+    step(&mut cpu, 1);
+    assert!(cpu.get_flag(AUXILIARY_CARRY_BIT));
+    (cpu.a, cpu.b) = (0xf0, 0x30);
+    cpu.set_flag_byte(0x02);
+    step(&mut cpu, 1);
+    assert_eq!(cpu.flags() & 0x10, 0x10);
+}
+
+#[test]
+fn test_interrupt_inputs() {
+    let mut cpu = i8085(&[
+        0x3e, 0x0b, 0x30, // SIM: unmask 7.5 only
+        0xfb,             // EI
+        0x00, 0x00,
+        0x20,             // RIM
+        0xc3, 0x07, 0x00, // JMP $
+    ]);
+    cpu.pins.borrow_mut().set(Input::Rst65, true);
+    cpu.pins.borrow_mut().set(Input::Rst75, true);
+    cpu.pins.borrow_mut().set(Input::Rst75, false);
+    step(&mut cpu, 3);
+    // EI waits one instruction.
+    step(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0005);
+    let cycles = cpu.cycles;
+    step(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x003c);
+    assert_eq!(cpu.cycles - cycles, 12);
+    assert_eq!(cpu.ram.load_word(cpu.sp), 0x0005);
+    assert!(!cpu.inte);
+
+    // TRAP goes through with interrupts off; RIM then reports IE as
+    // it was before.
+    cpu.inte = true;
+    cpu.pc = 0x0006;
+    cpu.pins.borrow_mut().set(Input::Trap, true);
+    step(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0024);
+    cpu.pc = 0x0006;
+    step(&mut cpu, 1);
+    assert_eq!(cpu.a, 0x2b);
+    step(&mut cpu, 1);
+    assert_eq!(cpu.pc, 0x0007);
+}
+
+#[test]
+fn test_serial_pins() {
+    let mut cpu = i8085(&[0x3e, 0xc0, 0x30, 0x3e, 0x40, 0x30, 0x20]);
+    let sod = Rc::new(Cell::new(false));
+    let s = sod.clone();
+    cpu.pins.borrow_mut().connect_sod(move |level| s.set(level));
+    step(&mut cpu, 2);
+    assert!(sod.get());
+    step(&mut cpu, 2);
+    assert!(!sod.get());
+    cpu.pins.borrow_mut().set(Input::Sid, true);
+    step(&mut cpu, 1);
+    assert_eq!(cpu.a, 0x87);
+}
@@ -146,11 +146,11 @@ fn test_adc_3() {
      
     let mut cpu =  Cpu::new( );
     cpu.a = 0x3f;
-    cpu.flag = 0xd3;
+    cpu.set_flag_byte(0xd3);
     cpu.ram.save_byte(0x0000, 0x8f);
     cpu.next().unwrap();
     assert_eq!(cpu.a, 0x7f);
-    assert_eq!(cpu.flag, 0x12);
+    assert_eq!(cpu.flags(), 0x12);
 }
 
 #[test]
@@ -380,7 +380,7 @@ fn test_stack_pop_2() {
     cpu.ram.save_byte(0x0000, 0xf1);
     cpu.next().unwrap();
     assert_eq!(cpu.a, 0xff);
-    assert_eq!(cpu.flag, 0xc3);
+    assert_eq!(cpu.flags(), 0xc3);
     assert_eq!(cpu.get_flag(SIGN_BIT), true);
     assert_eq!(cpu.get_flag(ZERO_BIT), true);
     assert_eq!(cpu.get_flag(AUXILIARY_CARRY_BIT), false);
@@ -711,3 +711,149 @@ fn test_pchl() {
     cpu.next().unwrap();
     assert_eq!(cpu.pc, 0x413e);
 }
+
+// Every opcode with operand bytes 34H 12H, as the 8080 manual's opcode
+// map lists it, and its length. Undefined opcodes run as their aliases.
+const OPCODES: [(&str, usize); 256] = [
+    /* 00 */ ("NOP", 1), ("LXI B,1234H", 3), ("STAX B", 1), ("INX B", 1),
+    /* 04 */ ("INR B", 1), ("DCR B", 1), ("MVI B,34H", 2), ("RLC", 1),
+    /* 08 */ ("NOP", 1), ("DAD B", 1), ("LDAX B", 1), ("DCX B", 1),
+    /* 0c */ ("INR C", 1), ("DCR C", 1), ("MVI C,34H", 2), ("RRC", 1),
+    /* 10 */ ("NOP", 1), ("LXI D,1234H", 3), ("STAX D", 1), ("INX D", 1),
+    /* 14 */ ("INR D", 1), ("DCR D", 1), ("MVI D,34H", 2), ("RAL", 1),
+    /* 18 */ ("NOP", 1), ("DAD D", 1), ("LDAX D", 1), ("DCX D", 1),
+    /* 1c */ ("INR E", 1), ("DCR E", 1), ("MVI E,34H", 2), ("RAR", 1),
+    /* 20 */ ("NOP", 1), ("LXI H,1234H", 3), ("SHLD 1234H", 3), ("INX H", 1),
+    /* 24 */ ("INR H", 1), ("DCR H", 1), ("MVI H,34H", 2), ("DAA", 1),
+    /* 28 */ ("NOP", 1), ("DAD H", 1), ("LHLD 1234H", 3), ("DCX H", 1),
+    /* 2c */ ("INR L", 1), ("DCR L", 1), ("MVI L,34H", 2), ("CMA", 1),
+    /* 30 */ ("NOP", 1), ("LXI SP,1234H", 3), ("STA 1234H", 3), ("INX SP", 1),
+    /* 34 */ ("INR M", 1), ("DCR M", 1), ("MVI M,34H", 2), ("STC", 1),
+    /* 38 */ ("NOP", 1), ("DAD SP", 1), ("LDA 1234H", 3), ("DCX SP", 1),
+    /* 3c */ ("INR A", 1), ("DCR A", 1), ("MVI A,34H", 2), ("CMC", 1),
+    /* 40 */ ("MOV B,B", 1), ("MOV B,C", 1), ("MOV B,D", 1), ("MOV B,E", 1),
+    /* 44 */ ("MOV B,H", 1), ("MOV B,L", 1), ("MOV B,M", 1), ("MOV B,A", 1),
+    /* 48 */ ("MOV C,B", 1), ("MOV C,C", 1), ("MOV C,D", 1), ("MOV C,E", 1),
+    /* 4c */ ("MOV C,H", 1), ("MOV C,L", 1), ("MOV C,M", 1), ("MOV C,A", 1),
+    /* 50 */ ("MOV D,B", 1), ("MOV D,C", 1), ("MOV D,D", 1), ("MOV D,E", 1),
+    /* 54 */ ("MOV D,H", 1), ("MOV D,L", 1), ("MOV D,M", 1), ("MOV D,A", 1),
+    /* 58 */ ("MOV E,B", 1), ("MOV E,C", 1), ("MOV E,D", 1), ("MOV E,E", 1),
+    /* 5c */ ("MOV E,H", 1), ("MOV E,L", 1), ("MOV E,M", 1), ("MOV E,A", 1),
+    /* 60 */ ("MOV H,B", 1), ("MOV H,C", 1), ("MOV H,D", 1), ("MOV H,E", 1),
+    /* 64 */ ("MOV H,H", 1), ("MOV H,L", 1), ("MOV H,M", 1), ("MOV H,A", 1),
+    /* 68 */ ("MOV L,B", 1), ("MOV L,C", 1), ("MOV L,D", 1), ("MOV L,E", 1),
+    /* 6c */ ("MOV L,H", 1), ("MOV L,L", 1), ("MOV L,M", 1), ("MOV L,A", 1),
+    /* 70 */ ("MOV M,B", 1), ("MOV M,C", 1), ("MOV M,D", 1), ("MOV M,E", 1),
+    /* 74 */ ("MOV M,H", 1), ("MOV M,L", 1), ("HLT", 1), ("MOV M,A", 1),
+    /* 78 */ ("MOV A,B", 1), ("MOV A,C", 1), ("MOV A,D", 1), ("MOV A,E", 1),
+    /* 7c */ ("MOV A,H", 1), ("MOV A,L", 1), ("MOV A,M", 1), ("MOV A,A", 1),
+    /* 80 */ ("ADD B", 1), ("ADD C", 1), ("ADD D", 1), ("ADD E", 1),
+    /* 84 */ ("ADD H", 1), ("ADD L", 1), ("ADD M", 1), ("ADD A", 1),
+    /* 88 */ ("ADC B", 1), ("ADC C", 1), ("ADC D", 1), ("ADC E", 1),
+    /* 8c */ ("ADC H", 1), ("ADC L", 1), ("ADC M", 1), ("ADC A", 1),
+    /* 90 */ ("SUB B", 1), ("SUB C", 1), ("SUB D", 1), ("SUB E", 1),
+    /* 94 */ ("SUB H", 1), ("SUB L", 1), ("SUB M", 1), ("SUB A", 1),
+    /* 98 */ ("SBB B", 1), ("SBB C", 1), ("SBB D", 1), ("SBB E", 1),
+    /* 9c */ ("SBB H", 1), ("SBB L", 1), ("SBB M", 1), ("SBB A", 1),
+    /* a0 */ ("ANA B", 1), ("ANA C", 1), ("ANA D", 1), ("ANA E", 1),
+    /* a4 */ ("ANA H", 1), ("ANA L", 1), ("ANA M", 1), ("ANA A", 1),
+    /* a8 */ ("XRA B", 1), ("XRA C", 1), ("XRA D", 1), ("XRA E", 1),
+    /* ac */ ("XRA H", 1), ("XRA L", 1), ("XRA M", 1), ("XRA A", 1),
+    /* b0 */ ("ORA B", 1), ("ORA C", 1), ("ORA D", 1), ("ORA E", 1),
+    /* b4 */ ("ORA H", 1), ("ORA L", 1), ("ORA M", 1), ("ORA A", 1),
+    /* b8 */ ("CMP B", 1), ("CMP C", 1), ("CMP D", 1), ("CMP E", 1),
+    /* bc */ ("CMP H", 1), ("CMP L", 1), ("CMP M", 1), ("CMP A", 1),
+    /* c0 */ ("RNZ", 1), ("POP B", 1), ("JNZ 1234H", 3), ("JMP 1234H", 3),
+    /* c4 */ ("CNZ 1234H", 3), ("PUSH B", 1), ("ADI 34H", 2), ("RST 0", 1),
+    /* c8 */ ("RZ", 1), ("RET", 1), ("JZ 1234H", 3), ("JMP 1234H", 3),
+    /* cc */ ("CZ 1234H", 3), ("CALL 1234H", 3), ("ACI 34H", 2), ("RST 1", 1),
+    /* d0 */ ("RNC", 1), ("POP D", 1), ("JNC 1234H", 3), ("OUT 34H", 2),
+    /* d4 */ ("CNC 1234H", 3), ("PUSH D", 1), ("SUI 34H", 2), ("RST 2", 1),
+    /* d8 */ ("RC", 1), ("RET", 1), ("JC 1234H", 3), ("IN 34H", 2),
+    /* dc */ ("CC 1234H", 3), ("CALL 1234H", 3), ("SBI 34H", 2), ("RST 3", 1),
+    /* e0 */ ("RPO", 1), ("POP H", 1), ("JPO 1234H", 3), ("XTHL", 1),
+    /* e4 */ ("CPO 1234H", 3), ("PUSH H", 1), ("ANI 34H", 2), ("RST 4", 1),
+    /* e8 */ ("RPE", 1), ("PCHL", 1), ("JPE 1234H", 3), ("XCHG", 1),
+    /* ec */ ("CPE 1234H", 3), ("CALL 1234H", 3), ("XRI 34H", 2), ("RST 5", 1),
+    /* f0 */ ("RP", 1), ("POP PSW", 1), ("JP 1234H", 3), ("DI", 1),
+    /* f4 */ ("CP 1234H", 3), ("PUSH PSW", 1), ("ORI 34H", 2), ("RST 6", 1),
+    /* f8 */ ("RM", 1), ("SPHL", 1), ("JM 1234H", 3), ("EI", 1),
+    /* fc */ ("CM 1234H", 3), ("CALL 1234H", 3), ("CPI 34H", 2), ("RST 7", 1),
+];
+
+#[test]
+fn test_decode_table_matches_rules() {
+    for op in 0..=255u8 {
+        let bytes = [op, 0x34, 0x12];
+        let (mut table, mut rules) = (bytes.iter().copied(), bytes.iter().copied());
+        let by_table = decode(|| table.next().unwrap()).unwrap();
+        let by_rules = decode_by_rules(|| rules.next().unwrap()).unwrap();
+        assert_eq!((by_table.to_string().as_str(), 3 - table.len()), OPCODES[op as usize], "opcode {op:02x}");
+        assert_eq!((by_rules.to_string().as_str(), 3 - rules.len()), OPCODES[op as usize], "opcode {op:02x}");
+    }
+}
+
+#[test]
+fn test_undefined_opcodes() {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0x1000;
+    cpu.ram.load_at(0, &[
+        0x08,             // NOP
+        0xcb, 0x05, 0x00, // JMP 0005H
+        0x00,
+        0xdd, 0x0a, 0x00, // CALL 000AH
+        0x76,             // HLT
+        0x00,
+        0xd9,             // RET
+    ]);
+    cpu.run().unwrap();
+    assert_eq!(cpu.pc, 0x0009);
+    assert_eq!(cpu.cycles, 4 + 10 + 17 + 10 + 7);
+}
+
+#[test]
+fn test_conditional_timings() {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0x1000;
+    cpu.ram.load_at(0, &[
+        0xcc, 0x10, 0x00, // CZ 0010H, not taken
+        0xc4, 0x10, 0x00, // CNZ 0010H, taken
+    ]);
+    cpu.ram.load_at(0x10, &[0xc8, 0xc0]); // RZ, RNZ
+    let mut costs = Vec::new();
+    for _ in 0..4 {
+        let before = cpu.cycles;
+        cpu.next().unwrap();
+        costs.push(cpu.cycles - before);
+    }
+    assert_eq!(costs, [11, 17, 5, 11]);
+    assert_eq!(cpu.pc, 0x0006);
+}
+
+#[test]
+fn test_ani_aux_and_pop_psw() {
+    let mut cpu = Cpu::new();
+    cpu.sp = 0x1000;
+    cpu.ram.load_at(0, &[0x3e, 0x08, 0xe6, 0x01, 0xf1]); // MVI A,08H; ANI 01H; POP PSW
+    cpu.next().unwrap();
+    cpu.next().unwrap();
+    // AC is bit 3 of either operand.
+    assert!(cpu.get_flag(AUXILIARY_CARRY_BIT));
+    assert!(cpu.get_flag(ZERO_BIT));
+    // Bits 1, 3 and 5 of the flags are fixed.
+    cpu.ram.save_word(cpu.sp, 0x12ff);
+    cpu.next().unwrap();
+    assert_eq!((cpu.a, cpu.flags()), (0x12, 0xd7));
+}
+
+#[test]
+fn test_cpu_test_rom() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0x0100, include_bytes!("../test_roms/CPUTEST.COM"));
+    cpu.prepare_cpm();
+    let mut console = Vec::new();
+    while !cpu.cpm_hook(&mut console) {
+        cpu.next().unwrap();
+    }
+    assert!(String::from_utf8_lossy(&console).contains("CPU TESTS OK"));
+}
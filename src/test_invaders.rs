@@ -0,0 +1,85 @@
+use crate::invaders::*;
+
+// Counts RST 1 at 2000H and RST 2 at 2001H; the vblank handler also
+// copies input port 1 to the first byte of video RAM.
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; ROM_SIZE];
+    let mut put = |addr: usize, bytes: &[u8]| rom[addr..addr + bytes.len()].copy_from_slice(bytes);
+    put(0x0000, &[0x31, 0x00, 0x24, 0xfb, 0xc3, 0x04, 0x00]); // LXI SP,2400H; EI; JMP $
+    put(0x0008, &[0xc3, 0x20, 0x00]);
+    put(0x0010, &[0xc3, 0x30, 0x00]);
+    put(0x0020, &[0xf5, 0x3a, 0x00, 0x20, 0x3c, 0x32, 0x00, 0x20, 0xf1, 0xfb, 0xc9]);
+    put(0x0030, &[
+        0xf5, 0x3a, 0x01, 0x20, 0x3c, 0x32, 0x01, 0x20,
+        0xdb, 0x01, 0x32, 0x00, 0x24, 0xf1, 0xfb, 0xc9,
+    ]);
+    rom
+}
+
+#[test]
+fn test_interrupts() {
+    let mut machine = Invaders::new(&rom());
+    for _ in 0..3 {
+        machine.run_frame().unwrap();
+    }
+    assert_eq!(machine.frame, 3);
+    // The frame ends as the vblank interrupt is taken.
+    assert_eq!(machine.cpu.pc, 0x0010);
+    assert!(!machine.cpu.inte);
+    assert_eq!(machine.cpu.ram.load_byte(0x2000), 3);
+    assert_eq!(machine.cpu.ram.load_byte(0x2001), 2);
+    assert_eq!(machine.cpu.ram.load_byte(0x2400), 0x08);
+}
+
+#[test]
+fn test_script() {
+    let script = Script::parse("# insert a coin\n2 press coin\n1 press start1\n3 release coin\n").unwrap();
+    assert_eq!(script.len(), 3);
+    assert!(Script::parse("1 press nothing\n").is_err());
+    assert!(Script::parse("press coin\n").is_err());
+
+    let mut machine = Invaders::new(&rom());
+    machine.run(3, &script).unwrap();
+    assert_eq!(machine.cpu.ram.load_byte(0x2400), 0x0d);
+    machine.run(1, &script).unwrap();
+    assert_eq!(machine.cpu.ram.load_byte(0x2400), 0x0c);
+}
+
+#[test]
+fn test_shift_register() {
+    let mut rom = vec![
+        0x3e, 0xab, 0xd3, 0x04, // MVI A,0ABH; OUT 4
+        0x3e, 0xcd, 0xd3, 0x04, // MVI A,0CDH; OUT 4
+        0x3e, 0x04, 0xd3, 0x02, // MVI A,4; OUT 2
+        0xdb, 0x03,             // IN 3
+        0x32, 0x00, 0x00,       // STA 0000H
+    ];
+    rom.resize(ROM_SIZE, 0);
+    let mut machine = Invaders::new(&rom);
+    for _ in 0..8 {
+        machine.cpu.next().unwrap();
+    }
+    assert_eq!(machine.cpu.a, 0xda);
+    // ROM ignores the write.
+    assert_eq!(machine.cpu.ram.load_byte(0x0000), 0x3e);
+}
+
+#[test]
+fn test_framebuffer() {
+    let mut machine = Invaders::new(&rom());
+    machine.cpu.ram.save_byte(VRAM, 0x01);
+    machine.cpu.ram.save_byte(VRAM + 32 * 223 + 31, 0x80);
+    assert!(machine.pixel(0, HEIGHT - 1));
+    assert!(machine.pixel(WIDTH - 1, 0));
+    assert!(!machine.pixel(0, 0));
+
+    let mut image = Vec::new();
+    machine.write_pbm(&mut image).unwrap();
+    let header = b"P4\n224 256\n";
+    assert_eq!(&image[..header.len()], header);
+    let pixels = &image[header.len()..];
+    assert_eq!(pixels.len(), WIDTH / 8 * HEIGHT);
+    assert_eq!(pixels[WIDTH / 8 - 1], 0x01);
+    assert_eq!(pixels[WIDTH / 8 * (HEIGHT - 1)], 0x80);
+    assert_eq!(pixels.iter().filter(|&&b| b != 0).count(), 2);
+}
@@ -0,0 +1,122 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::device::PortDevice;
+use crate::interrupt::InterruptSource;
+use crate::pic::*;
+
+// Single, edge triggered, vectors every 4 bytes from 2000H.
+fn pic() -> Pic {
+    let mut pic = Pic::new();
+    pic.write_port(0, 0x16);
+    pic.write_port(1, 0x20);
+    pic
+}
+
+#[test]
+fn test_vectoring() {
+    let mut pic = pic();
+    assert!(!pic.requested());
+    pic.set_irq(3, true);
+    assert!(pic.requested());
+    assert_eq!(pic.acknowledge(), [0xcd, 0x0c, 0x20]);
+    assert_eq!(pic.isr, 0x08);
+    assert_eq!(pic.irr, 0x00);
+
+    // Lower priority waits, higher priority nests.
+    pic.set_irq(5, true);
+    assert!(!pic.requested());
+    pic.set_irq(1, true);
+    assert_eq!(pic.acknowledge(), [0xcd, 0x04, 0x20]);
+    pic.write_port(0, 0x20); // non-specific EOI ends IR1
+    assert_eq!(pic.isr, 0x08);
+    pic.write_port(0, 0x63); // specific EOI for IR3
+    assert_eq!(pic.isr, 0x00);
+    assert_eq!(pic.acknowledge(), [0xcd, 0x14, 0x20]);
+
+    // Held high, an edge triggered line requests only once.
+    pic.write_port(0, 0x20);
+    pic.set_irq(5, true);
+    assert!(!pic.requested());
+}
+
+#[test]
+fn test_interval_8_and_mask() {
+    let mut pic = Pic::new();
+    pic.write_port(0, 0xd3); // A7-A6 = 11, interval 8, single, ICW4
+    pic.write_port(1, 0x10);
+    pic.write_port(1, 0x02); // ICW4: auto EOI
+    pic.write_port(1, 0xfb); // only IR2 unmasked
+    assert_eq!(pic.read_port(1), 0xfb);
+
+    pic.set_irq(0, true);
+    assert!(!pic.requested());
+    pic.set_irq(2, true);
+    assert_eq!(pic.acknowledge(), [0xcd, 0xd0, 0x10]);
+    // Auto EOI leaves nothing in service.
+    assert_eq!(pic.isr, 0x00);
+
+    pic.write_port(0, 0x0a); // read IRR
+    assert_eq!(pic.read_port(0), 0x01);
+    pic.write_port(1, 0x00);
+    pic.write_port(0, 0x0c); // poll
+    assert_eq!(pic.read_port(0), 0x80);
+    pic.write_port(0, 0x0c);
+    assert_eq!(pic.read_port(0), 0x00);
+}
+
+#[test]
+fn test_rotation() {
+    let mut pic = pic();
+    pic.set_irq(2, true);
+    pic.acknowledge();
+    pic.write_port(0, 0xa0); // rotate on non-specific EOI: IR2 goes last
+    pic.set_irq(1, true);
+    pic.set_irq(4, true);
+    assert_eq!(pic.acknowledge(), [0xcd, 0x10, 0x20]);
+    pic.write_port(0, 0xc5); // set priority: IR5 lowest, IR6 highest
+    pic.write_port(0, 0x20);
+    assert_eq!(pic.acknowledge(), [0xcd, 0x04, 0x20]);
+}
+
+#[test]
+fn test_cpu_call() {
+    let pic = Rc::new(RefCell::new(Pic::new()));
+    let mut cpu = Cpu::new();
+    cpu.attach([0x20, 0x21], &pic);
+    cpu.connect_interrupts(&pic);
+    let program = [
+        0x31, 0x00, 0x10,       // LXI SP,1000H
+        0x3e, 0x16, 0xd3, 0x20, // ICW1
+        0x3e, 0x20, 0xd3, 0x21, // ICW2
+        0xfb,                   // EI
+        0xc3, 0x0c, 0x00,       // JMP $
+    ];
+    for (i, b) in program.iter().enumerate() {
+        cpu.ram.save_byte(i as u16, *b);
+    }
+    // IR2 handler at 2008H: EOI, then return with interrupts on.
+    for (i, b) in [0x3e, 0x20, 0xd3, 0x20, 0xfb, 0xc9].iter().enumerate() {
+        cpu.ram.save_byte(0x2008 + i as u16, *b);
+    }
+    for _ in 0..8 {
+        cpu.next().unwrap();
+    }
+    assert_eq!(cpu.pc, 0x000c);
+
+    let mut irq = Pic::irq(&pic, 2);
+    irq(true);
+    let cycles = cpu.cycles;
+    cpu.next().unwrap();
+    assert_eq!(cpu.pc, 0x2008);
+    assert_eq!(cpu.cycles - cycles, 17);
+    assert_eq!(cpu.ram.load_word(cpu.sp), 0x000c);
+    assert!(!cpu.inte);
+    for _ in 0..5 {
+        cpu.next().unwrap();
+    }
+    assert_eq!(cpu.pc, 0x000c);
+    assert_eq!(pic.borrow().isr, 0);
+    assert!(cpu.inte);
+}
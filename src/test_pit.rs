@@ -0,0 +1,148 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::device::PortDevice;
+use crate::interrupt::RstLatch;
+use crate::pit::*;
+
+// OUT of counter 0 after each of `n` clocks.
+fn wave(pit: &mut Pit, n: usize) -> String {
+    (0..n).map(|_| {
+        pit.clock();
+        if pit.out(0) { '1' } else { '0' }
+    }).collect()
+}
+
+#[test]
+fn test_rate_generator() {
+    let mut pit = Pit::new(Model::I8253, 1);
+    pit.write_port(3, 0x34); // counter 0, LSB then MSB, mode 2
+    pit.write_port(0, 5);
+    pit.write_port(0, 0);
+    assert_eq!(wave(&mut pit, 11), "11110111101");
+}
+
+#[test]
+fn test_square_wave() {
+    let mut pit = Pit::new(Model::I8253, 1);
+    pit.write_port(3, 0x16); // counter 0, LSB only, mode 3
+    pit.write_port(0, 5);
+    assert_eq!(wave(&mut pit, 11), "11100111001");
+    // Gate low forces OUT high and stops the counter.
+    pit.set_gate(0, false);
+    assert_eq!(wave(&mut pit, 4), "1111");
+}
+
+#[test]
+fn test_terminal_count() {
+    let mut pit = Pit::new(Model::I8253, 1);
+    pit.write_port(3, 0x30); // mode 0
+    assert!(!pit.out(0));
+    pit.write_port(0, 3);
+    pit.write_port(0, 0);
+    assert_eq!(wave(&mut pit, 6), "000111");
+
+    pit.write_port(3, 0x18); // mode 4, LSB only
+    pit.write_port(0, 2);
+    assert_eq!(wave(&mut pit, 6), "110111");
+}
+
+#[test]
+fn test_latch_and_bcd() {
+    let mut pit = Pit::new(Model::I8254, 1);
+    pit.write_port(3, 0x71); // counter 1, word, mode 0, BCD
+    pit.write_port(1, 0x00);
+    pit.write_port(1, 0x10);
+    for _ in 0..4 {
+        pit.clock();
+    }
+    pit.write_port(3, 0x40); // latch counter 1
+    pit.clock();
+    assert_eq!(pit.read_port(1), 0x97);
+    assert_eq!(pit.read_port(1), 0x09);
+    // Unlatched reads follow the counter.
+    assert_eq!(pit.read_port(1), 0x96);
+
+    pit.write_port(3, 0xe4); // read back status of counter 1
+    assert_eq!(pit.read_port(1), 0x31);
+}
+
+#[test]
+fn test_interrupt() {
+    let pit = Rc::new(RefCell::new(Pit::new(Model::I8253, 1)));
+    let latch = Rc::new(RefCell::new(RstLatch::new(7)));
+    let line = latch.clone();
+    pit.borrow_mut().connect(0, move |level| line.borrow_mut().set(level));
+
+    let mut cpu = Cpu::new();
+    cpu.attach(0x40..=0x43, &pit);
+    cpu.clock(&pit);
+    cpu.connect_interrupts(&latch);
+    let program = [
+        0x31, 0x00, 0x10,       // LXI SP,1000H
+        0x3e, 0x30, 0xd3, 0x43, // mode 0
+        0x3e, 0xc8, 0xd3, 0x40, // count 200
+        0xaf, 0xd3, 0x40,
+        0xfb,                   // EI
+        0xc3, 0x0f, 0x00,       // JMP $
+    ];
+    for (i, b) in program.iter().enumerate() {
+        cpu.ram.save_byte(i as u16, *b);
+    }
+    // RST 7: count and return with interrupts off.
+    for (i, b) in [0x21, 0x00, 0x20, 0x34, 0xc9].iter().enumerate() {
+        cpu.ram.save_byte(0x38 + i as u16, *b);
+    }
+    while cpu.cycles < 1000 {
+        cpu.next().unwrap();
+        if cpu.pc == 0x38 {
+            // Counting starts once the count is written, near cycle 60.
+            assert!((250..280).contains(&cpu.cycles));
+        }
+    }
+    assert_eq!(cpu.ram.load_byte(0x2000), 1);
+    assert!(!cpu.inte);
+}
+
+#[test]
+fn test_halt_until_interrupt() {
+    let pit = Rc::new(RefCell::new(Pit::new(Model::I8253, 1)));
+    let latch = Rc::new(RefCell::new(RstLatch::new(7)));
+    let line = latch.clone();
+    pit.borrow_mut().connect(0, move |level| line.borrow_mut().set(level));
+
+    let mut cpu = Cpu::new();
+    cpu.attach(0x40..=0x43, &pit);
+    cpu.clock(&pit);
+    cpu.connect_interrupts(&latch);
+    cpu.ram.load_at(0, &[
+        0x31, 0x00, 0x10,       // LXI SP,1000H
+        0x3e, 0x30, 0xd3, 0x43, // mode 0
+        0x3e, 0xc8, 0xd3, 0x40, // count 200
+        0xaf, 0xd3, 0x40,
+        0xfb,                   // EI
+        0x76,                   // HLT
+        0xc3, 0x10, 0x00,       // JMP $
+    ]);
+    cpu.ram.load_at(0x38, &[0x21, 0x00, 0x20, 0x34, 0xc9]);
+    while !cpu.halted {
+        cpu.next().unwrap();
+    }
+    assert_eq!(cpu.pc, 0x0010);
+    // Idling four T-states at a time.
+    let cycles = cpu.cycles;
+    cpu.next().unwrap();
+    assert_eq!((cpu.pc, cpu.cycles), (0x0010, cycles + 4));
+    while cpu.halted {
+        cpu.next().unwrap();
+    }
+    assert_eq!(cpu.pc, 0x0038);
+    assert!((250..280).contains(&cpu.cycles));
+    // The interrupt returns past the HLT.
+    assert_eq!(cpu.ram.load_word(cpu.sp), 0x0010);
+    for _ in 0..3 {
+        cpu.next().unwrap();
+    }
+    assert_eq!((cpu.pc, cpu.ram.load_byte(0x2000)), (0x0010, 1));
+}
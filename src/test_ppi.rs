@@ -0,0 +1,132 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::device::{Pins, PortDevice};
+use crate::ppi::*;
+
+#[test]
+fn test_mode_0() {
+    let mut ppi = Ppi::new();
+    let seen = Rc::new(Cell::new(0));
+    let s = seen.clone();
+    ppi.connect(A, move |levels| s.set(levels));
+    ppi.write_port(3, 0x83); // A out, B in, C upper out, C lower in
+    ppi.write_port(0, 0x55);
+    assert_eq!(ppi.sense(A), 0x55);
+    assert_eq!(seen.get(), 0x55);
+
+    ppi.drive(B, 0x3c);
+    assert_eq!(ppi.read_port(1), 0x3c);
+    ppi.drive(C, 0x03);
+    ppi.write_port(2, 0xa5);
+    assert_eq!(ppi.sense(C), 0xa3);
+    assert_eq!(ppi.read_port(2), 0xa3);
+    ppi.write_port(3, 0x0e); // reset PC7
+    ppi.write_port(3, 0x09); // set PC4
+    assert_eq!(ppi.read_port(2), 0x33);
+
+    // A new mode clears the output latches.
+    ppi.write_port(3, 0x80);
+    assert_eq!(ppi.sense(A), 0x00);
+}
+
+#[test]
+fn test_keypad() {
+    // Rows strobed low on PC4-7, columns read back on port B.
+    let keys = [0u8, 0x04, 0, 0];
+    let row = Rc::new(Cell::new(0xffu8));
+    let mut ppi = Ppi::new();
+    let r = row.clone();
+    ppi.connect(C, move |levels| r.set(levels >> 4));
+    let r = row.clone();
+    ppi.connect_input(B, move || {
+        (0..4).filter(|&i| r.get() & 1 << i == 0).fold(0xff, |cols, i| cols & !keys[i])
+    });
+    ppi.write_port(3, 0x83);
+    let scan = |ppi: &mut Ppi, n: u8| {
+        ppi.write_port(2, !(1 << n) << 4);
+        ppi.read_port(1)
+    };
+    assert_eq!(scan(&mut ppi, 0), 0xff);
+    assert_eq!(scan(&mut ppi, 1), 0xfb);
+    assert_eq!(scan(&mut ppi, 2), 0xff);
+}
+
+#[test]
+fn test_strobed_input() {
+    let mut ppi = Ppi::new();
+    let intr = Rc::new(Cell::new(false));
+    let i = intr.clone();
+    ppi.connect(C, move |levels| i.set(levels & 0x08 != 0));
+    ppi.write_port(3, 0xb0); // A mode 1 in
+    ppi.write_port(3, 0x09); // INTE A
+    assert_eq!(ppi.read_port(2) & 0x38, 0x10);
+
+    ppi.drive(A, 0x42);
+    ppi.drive_line(C, 4, false);
+    assert_eq!(ppi.sense(C) & 0x28, 0x20); // IBF, no INTR yet
+    ppi.drive(A, 0x00);
+    ppi.drive_line(C, 4, true);
+    assert!(intr.get());
+    assert_eq!(ppi.read_port(0), 0x42);
+    assert!(!intr.get());
+    assert_eq!(ppi.sense(C) & 0x28, 0x00);
+}
+
+#[test]
+fn test_strobed_output() {
+    let mut ppi = Ppi::new();
+    ppi.write_port(3, 0x84); // B mode 1 out
+    ppi.write_port(3, 0x05); // INTE B
+    assert_eq!(ppi.sense(C) & 0x03, 0x03);
+    ppi.write_port(1, 0x99);
+    assert_eq!(ppi.sense(B), 0x99);
+    assert_eq!(ppi.sense(C) & 0x03, 0x00); // OBF low, no INTR
+    ppi.drive_line(C, 2, false);
+    assert_eq!(ppi.sense(C) & 0x03, 0x02);
+    ppi.drive_line(C, 2, true);
+    assert_eq!(ppi.sense(C) & 0x03, 0x03);
+
+    // Mode 2: port A drives the bus only while ACK is low.
+    ppi.write_port(3, 0xc0);
+    ppi.write_port(0, 0x77);
+    assert_eq!(ppi.sense(A), 0xff);
+    assert_eq!(ppi.sense(C) & 0x80, 0x00);
+    ppi.drive_line(C, 6, false);
+    assert_eq!(ppi.sense(A), 0x77);
+    assert_eq!(ppi.sense(C) & 0x80, 0x80);
+}
+
+#[test]
+fn test_script() {
+    let script = PinScript::parse(
+        "# echo B to A\n\
+         100 drive B 5a\n\
+         400 expect A 5a\n\
+         400 expect A0 0\n\
+         500 expect B 00\n",
+    ).unwrap();
+    assert_eq!(script.len(), 4);
+    assert!(PinScript::parse("10 drive D 00").is_err());
+    assert!(PinScript::parse("10 drive C4 2").is_err());
+
+    let ppi = Rc::new(RefCell::new(Ppi::new()));
+    ppi.borrow_mut().run_script(script);
+    let mut cpu = Cpu::new();
+    cpu.attach(0x10..0x14, &ppi);
+    cpu.clock(&ppi);
+    let program = [
+        0x3e, 0x82, 0xd3, 0x13, // A out, B in
+        0xdb, 0x11,             // IN B
+        0xd3, 0x10,             // OUT A
+        0xc3, 0x04, 0x00,       // JMP
+    ];
+    for (i, b) in program.iter().enumerate() {
+        cpu.ram.save_byte(i as u16, *b);
+    }
+    while cpu.cycles < 600 {
+        cpu.next().unwrap();
+    }
+    assert_eq!(ppi.borrow().failures(), ["cycle 500: port B is 5a, expected 00"]);
+}
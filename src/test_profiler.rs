@@ -0,0 +1,140 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::interrupt::RstLatch;
+use crate::profiler::*;
+use crate::symbols::Symbols;
+
+// 0000: LXI SP,1000h
+// 0003: CALL 0010h
+// 0006: CALL 0010h
+// 0009: NOP
+// 0010: CALL 0020h
+// 0013: RET
+// 0020: INR A
+// 0021: RET
+fn nested_calls() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x31, 0x00, 0x10, 0xcd, 0x10, 0x00, 0xcd, 0x10, 0x00, 0x00]);
+    for (addr, b) in [(0x10, 0xcd), (0x11, 0x20), (0x12, 0x00), (0x13, 0xc9), (0x20, 0x3c), (0x21, 0xc9)] {
+        cpu.ram.save_byte(addr, b);
+    }
+    cpu
+}
+
+#[test]
+fn test_per_address_counts() {
+    let mut cpu = nested_calls();
+    let mut profiler = Profiler::new();
+    while cpu.pc != 0x0009 {
+        profiler.step(&mut cpu).unwrap();
+    }
+    profiler.finish();
+    assert_eq!(cpu.a, 2);
+    assert_eq!(profiler.instructions_at(0x0020), 2);
+    assert_eq!(profiler.cycles_at(0x0020), 2 * 5);
+    assert_eq!(profiler.cycles_at(0x0000), 10);
+    assert_eq!(profiler.total_cycles(), cpu.cycles);
+}
+
+#[test]
+fn test_inclusive_and_exclusive() {
+    let mut cpu = nested_calls();
+    let mut profiler = Profiler::new();
+    while cpu.pc != 0x0009 {
+        profiler.step(&mut cpu).unwrap();
+    }
+    profiler.finish();
+
+    let leaf = profiler.routine(0x0020).unwrap();
+    assert_eq!(leaf.calls, 2);
+    assert_eq!(leaf.exclusive, 2 * (5 + 10));
+    assert_eq!(leaf.inclusive, leaf.exclusive);
+
+    let mid = profiler.routine(0x0010).unwrap();
+    assert_eq!(mid.calls, 2);
+    assert_eq!(mid.exclusive, 2 * (17 + 10));
+    assert_eq!(mid.inclusive, mid.exclusive + leaf.inclusive);
+
+    let root = profiler.routine(0x0000).unwrap();
+    assert_eq!(root.inclusive, profiler.total_cycles());
+}
+
+#[test]
+fn test_folded_stacks() {
+    let mut cpu = nested_calls();
+    let mut profiler = Profiler::new();
+    while cpu.pc != 0x0009 {
+        profiler.step(&mut cpu).unwrap();
+    }
+    profiler.finish();
+
+    let mut out = Vec::new();
+    profiler.write_folded(&mut out, &Symbols::new()).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.lines().any(|l| l == "0000;0010;0020 30"));
+    assert!(out.lines().any(|l| l == "0000;0010 54"));
+}
+
+#[test]
+fn test_interrupt_enters_handler() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[
+        0x31, 0x00, 0x10, // 0000 LXI SP,1000h
+        0xfb,             // 0003 EI
+        0xcd, 0x20, 0x00, // 0004 CALL 0020h
+        0x00,             // 0007 NOP
+    ]);
+    cpu.ram.load_at(0x10, &[0x04, 0xfb, 0xc9]); // 0010 INR B; EI; RET
+    cpu.ram.load_at(0x20, &[0x3c, 0x3c, 0xc9]); // 0020 INR A; INR A; RET
+    let latch = Rc::new(RefCell::new(RstLatch::new(2)));
+    cpu.connect_interrupts(&latch);
+    let mut profiler = Profiler::new();
+    profiler.step(&mut cpu).unwrap();
+    profiler.step(&mut cpu).unwrap();
+    profiler.step(&mut cpu).unwrap();
+    // RST 2 comes in place of the first INR A.
+    latch.borrow_mut().set(true);
+    while cpu.pc != 0x0007 {
+        profiler.step(&mut cpu).unwrap();
+    }
+    profiler.finish();
+
+    assert_eq!((cpu.a, cpu.b), (2, 1));
+    assert_eq!(profiler.instructions_at(0x0020), 1);
+    let handler = profiler.routine(0x0010).unwrap();
+    assert_eq!(handler.calls, 1);
+    assert_eq!(handler.exclusive, 5 + 4 + 10);
+    // The acknowledge is counted against the routine interrupted.
+    let sub = profiler.routine(0x0020).unwrap();
+    assert_eq!(sub.calls, 1);
+    assert_eq!(sub.exclusive, 11 + 2 * 5 + 10);
+    assert_eq!(profiler.total_cycles(), cpu.cycles);
+}
+
+#[test]
+fn test_halt_idling_is_not_an_instruction() {
+    // 0000 EI; HLT, with an interrupt later ending the wait
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0xfb, 0x76, 0x00]);
+    cpu.ram.load_at(0x10, &[0xc9]);
+    cpu.sp = 0x1000;
+    let latch = Rc::new(RefCell::new(RstLatch::new(2)));
+    cpu.connect_interrupts(&latch);
+    let mut profiler = Profiler::new();
+    for _ in 0..5 {
+        profiler.step(&mut cpu).unwrap();
+    }
+    assert!(cpu.halted);
+    assert_eq!(profiler.instructions_at(0x0001), 1);
+    assert_eq!(profiler.instructions_at(0x0002), 0);
+    assert_eq!(profiler.cycles_at(0x0002), 0);
+
+    latch.borrow_mut().set(true);
+    profiler.step(&mut cpu).unwrap();
+    assert_eq!(cpu.pc, 0x0010);
+    profiler.finish();
+    assert_eq!(profiler.instructions_at(0x0002), 0);
+    assert_eq!(profiler.total_cycles(), cpu.cycles);
+}
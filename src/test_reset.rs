@@ -0,0 +1,57 @@
+use crate::cpu::*;
+use crate::i8085::Input;
+
+#[test]
+fn test_reset_keeps_registers() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x31, 0x34, 0x12, 0x3e, 0x42, 0xfb, 0x76]); // LXI SP,1234H; MVI A,42H; EI; HLT
+    for _ in 0..4 {
+        cpu.next().unwrap();
+    }
+    assert!(cpu.halted && cpu.inte);
+    assert_eq!(cpu.pc, 0x0007);
+    cpu.reset();
+    assert_eq!(cpu.pc, 0);
+    assert!(!cpu.inte);
+    assert!(!cpu.halted);
+    assert_eq!((cpu.a, cpu.sp), (0x42, 0x1234));
+}
+
+#[test]
+fn test_reset_8085_masks() {
+    let mut cpu = Cpu::with_variant(Variant::I8085);
+    cpu.pins.borrow_mut().sim(0x18); // unmask all, clear 7.5
+    cpu.pins.borrow_mut().set(Input::Rst75, true);
+    cpu.reset();
+    assert_eq!(cpu.pins.borrow().masks(), 0x07);
+    assert_eq!(cpu.pins.borrow_mut().rim(false) & 0x40, 0);
+}
+
+#[test]
+fn test_power_on_patterns() {
+    let mut cpu = Cpu::new();
+    cpu.power_on(PowerOn::Ones);
+    assert_eq!((cpu.a, cpu.b, cpu.sp), (0xff, 0xff, 0xffff));
+    assert_eq!(cpu.flags(), 0xd7);
+    assert_eq!(cpu.ram.load_byte(0x8000), 0xff);
+    assert_eq!(cpu.pc, 0);
+
+    let image = |seed| {
+        let mut cpu = Cpu::new();
+        cpu.power_on(PowerOn::Random(seed));
+        let ram: Vec<u8> = (0..0x100).map(|addr| cpu.ram.load_byte(addr)).collect();
+        (cpu.a, cpu.h, cpu.l, ram)
+    };
+    assert_eq!(image(7), image(7));
+    assert_ne!(image(7), image(8));
+    let (_, _, _, ram) = image(7);
+    assert!(ram.iter().any(|&b| b != ram[0]));
+}
+
+#[test]
+fn test_power_on_names() {
+    assert_eq!(PowerOn::from_name("zeros"), Some(PowerOn::Zeros));
+    assert_eq!(PowerOn::from_name("random:42"), Some(PowerOn::Random(42)));
+    assert_eq!(PowerOn::from_name("random"), Some(PowerOn::Random(1)));
+    assert_eq!(PowerOn::from_name("random:x"), None);
+}
@@ -0,0 +1,77 @@
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use crate::altair::Acia;
+use crate::device::PortDevice;
+use crate::serial::*;
+use crate::usart::*;
+
+fn wait(mut f: impl FnMut() -> bool) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        if f() {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    false
+}
+
+// A reader on the far end that takes a byte per token.
+struct Gate(Receiver<()>);
+
+impl Write for Gate {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.recv().map_err(|_| io::ErrorKind::BrokenPipe)?;
+        Ok(1)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_flow_control() {
+    let (tokens, gate) = mpsc::channel();
+    let mut usart = Usart::new(Line::queued(Gate(gate)));
+    usart.write_port(1, 0x4e); // async, 8 bits, x16
+    usart.write_port(1, 0x05); // TxEN, RxE
+    for i in 0..OUTPUT_LIMIT {
+        assert_ne!(usart.read_port(1) & TX_RDY, 0, "byte {i}");
+        usart.write_port(0, b'x');
+    }
+    assert_eq!(usart.read_port(1) & (TX_RDY | TX_EMPTY), 0);
+    for _ in 0..OUTPUT_LIMIT {
+        tokens.send(()).unwrap();
+    }
+    assert!(wait(|| usart.read_port(1) & TX_RDY != 0));
+}
+
+#[test]
+fn test_pty() {
+    let (line, path) = Line::pty().unwrap();
+    let mut acia = Acia::new(line);
+    let mut client = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+
+    client.write_all(b"hi\n").unwrap();
+    let mut typed = Vec::new();
+    assert!(wait(|| {
+        if acia.read_port(0) & 0x01 != 0 {
+            typed.push(acia.read_port(1));
+        }
+        typed.len() == 3
+    }));
+    // Raw: no echo, no newline translation.
+    assert_eq!(typed, b"hi\n");
+
+    for &byte in b"ok\r\n" {
+        assert!(wait(|| acia.read_port(0) & 0x02 != 0));
+        acia.write_port(1, byte);
+    }
+    let mut shown = [0; 4];
+    client.read_exact(&mut shown).unwrap();
+    assert_eq!(&shown, b"ok\r\n");
+}
@@ -0,0 +1,69 @@
+use crate::cpu::*;
+use crate::debugger::*;
+use crate::disasm::*;
+use crate::symbols::*;
+
+#[test]
+fn test_parse_sym() {
+    let mut symbols = Symbols::new();
+    symbols.parse_sym("0103 START\t01A3 PRINT_STR\n0200' BUFFER  0005 BDOS\n");
+    assert_eq!(symbols.lookup("start"), Some(0x0103));
+    assert_eq!(symbols.lookup("PRINT_STR"), Some(0x01a3));
+    assert_eq!(symbols.lookup("BUFFER"), Some(0x0200));
+    assert_eq!(symbols.name_of(0x0005), Some("BDOS"));
+}
+
+#[test]
+fn test_parse_text() {
+    let mut symbols = Symbols::new();
+    symbols.parse_text("; comment\nprint_str = 01A3H\nbdos equ 0x0005\nloop: 0110\n");
+    assert_eq!(symbols.lookup("PRINT_STR"), Some(0x01a3));
+    assert_eq!(symbols.lookup("bdos"), Some(0x0005));
+    assert_eq!(symbols.lookup("loop"), Some(0x0110));
+    assert_eq!(symbols.len(), 3);
+}
+
+#[test]
+fn test_parse_prn() {
+    let mut symbols = Symbols::new();
+    symbols.parse_prn(concat!(
+        " 0005 =         BDOS    EQU     5\n",
+        " 0100                   ORG     100H\n",
+        " 0100 C3B201    START:  JMP     INIT\n",
+        " 0103 48454C    MSG:    DB      'HEL'\n",
+        " 01B2 31BD07    INIT:   LXI     SP,STACK    ; set up stack\n",
+    ));
+    assert_eq!(symbols.lookup("BDOS"), Some(0x0005));
+    assert_eq!(symbols.lookup("START"), Some(0x0100));
+    assert_eq!(symbols.lookup("MSG"), Some(0x0103));
+    assert_eq!(symbols.lookup("INIT"), Some(0x01b2));
+    assert_eq!(symbols.len(), 4);
+}
+
+#[test]
+fn test_describe() {
+    let mut symbols = Symbols::new();
+    symbols.add("PRINT_STR", 0x01a3);
+    assert_eq!(symbols.describe(0x01a3), "PRINT_STR");
+    assert_eq!(symbols.describe(0x01a6), "PRINT_STR+3");
+    assert_eq!(symbols.describe(0x0100), "0100");
+}
+
+#[test]
+fn test_symbolic_disassembly_and_breakpoints() {
+    let mut cpu = Cpu::new();
+    // CALL 01A3h
+    cpu.ram.save_byte(0x0000, 0xcd);
+    cpu.ram.save_byte(0x0001, 0xa3);
+    cpu.ram.save_byte(0x0002, 0x01);
+    let mut symbols = Symbols::new();
+    symbols.add("PRINT_STR", 0x01a3);
+    assert_eq!(disassemble(&cpu.ram, 0x0000, &symbols, Variant::I8080).0, "CALL PRINT_STR");
+
+    let mut dbg = Debugger::new(cpu);
+    dbg.symbols = symbols;
+    let addr = dbg.resolve("print_str").unwrap();
+    dbg.add_breakpoint(addr);
+    assert_eq!(dbg.cont().unwrap(), Stop::Breakpoint(0x01a3));
+    assert_eq!(dbg.resolve("1a3"), Some(0x01a3));
+}
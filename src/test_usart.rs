@@ -0,0 +1,85 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::device::PortDevice;
+use crate::serial::Line;
+use crate::test_altair::Terminal;
+use crate::usart::*;
+
+fn usart() -> (Usart, Terminal) {
+    let terminal = Terminal::default();
+    (Usart::new(Line::new(Box::new(terminal.clone()))), terminal)
+}
+
+#[test]
+fn test_async() {
+    let (mut usart, terminal) = usart();
+    // The usual reset sequence: whatever state the chip is in, three zeros
+    // and an internal reset get it back to expecting a mode word.
+    for byte in [0x00, 0x00, 0x00, 0x40] {
+        usart.write_port(0x01, byte);
+    }
+    usart.write_port(0x01, 0x4e); // 8N1, x16
+    usart.write_port(0x01, 0x37); // TxEN, DTR, RxE, ER, RTS
+    assert_eq!(usart.mode(), 0x4e);
+    assert_eq!(usart.command(), 0x37);
+
+    assert_eq!(usart.read_port(0x01) & (TX_RDY | TX_EMPTY | RX_RDY), TX_RDY | TX_EMPTY);
+    usart.write_port(0x00, b'A');
+    usart.line.feed(b"Z");
+    assert_ne!(usart.read_port(0x01) & RX_RDY, 0);
+    assert_eq!(usart.read_port(0x00), b'Z');
+    assert_eq!(usart.read_port(0x01) & RX_RDY, 0);
+    assert_eq!(terminal.0.borrow().as_slice(), b"A");
+}
+
+#[test]
+fn test_sync_mode_and_reset() {
+    let (mut usart, terminal) = usart();
+    usart.write_port(0x01, 0x0c); // sync, 8 bits, two sync characters
+    usart.write_port(0x01, 0x16);
+    usart.write_port(0x01, 0x16);
+    usart.write_port(0x01, 0x04); // RxE only
+    assert_eq!(usart.command(), 0x04);
+
+    // Held until the transmitter is enabled.
+    usart.write_port(0x00, b'x');
+    assert_eq!(usart.read_port(0x01) & TX_RDY, 0);
+    usart.write_port(0x01, 0x05);
+    assert_eq!(usart.read_port(0x01) & TX_RDY, TX_RDY);
+    assert_eq!(terminal.0.borrow().as_slice(), b"x");
+
+    usart.write_port(0x01, 0x40);
+    assert_eq!(usart.command(), 0);
+    usart.write_port(0x01, 0x4a); // 7N1
+    usart.write_port(0x01, 0x01);
+    usart.write_port(0x00, 0xc1);
+    assert_eq!(terminal.0.borrow().as_slice(), b"xA");
+}
+
+#[test]
+fn test_on_bus() {
+    let (usart, terminal) = usart();
+    let usart = Rc::new(RefCell::new(usart));
+    usart.borrow_mut().line.feed(b"hi");
+    let mut cpu = Cpu::new();
+    cpu.attach([0x20, 0x21], &usart);
+    let program = [
+        0x3e, 0x4e, 0xd3, 0x21, // MVI A,4EH; OUT 21H
+        0x3e, 0x37, 0xd3, 0x21, // MVI A,37H; OUT 21H
+        0xdb, 0x21,             // wait: IN 21H
+        0xe6, 0x02,             // ANI RxRDY
+        0xca, 0x08, 0x00,       // JZ wait
+        0xdb, 0x20,             // IN 20H
+        0xd3, 0x20,             // OUT 20H
+        0xc3, 0x08, 0x00,       // JMP wait
+    ];
+    for (i, b) in program.iter().enumerate() {
+        cpu.ram.save_byte(i as u16, *b);
+    }
+    while !usart.borrow().line.is_idle() {
+        cpu.next().unwrap();
+    }
+    assert_eq!(terminal.0.borrow().as_slice(), b"hi");
+}
@@ -0,0 +1,56 @@
+use crate::cpu::*;
+use crate::loader::parse_rk;
+use crate::rk86;
+
+fn vm80a(program: &[u8]) -> Cpu {
+    let mut cpu = Cpu::with_variant(Variant::Kr580Vm80a);
+    cpu.sp = 0x1000;
+    cpu.ram.load_at(0, program);
+    cpu
+}
+
+#[test]
+fn test_runs_as_8080() {
+    let program = [
+        0x08,             // NOP
+        0xcb, 0x05, 0x00, // JMP 0005H
+        0x00,
+        0xdd, 0x0a, 0x00, // CALL 000AH
+        0x76,             // HLT
+        0x00,
+        0xc8,             // RZ, not taken
+        0xd9,             // RET
+    ];
+    let mut cpu = vm80a(&program);
+    cpu.run().unwrap();
+    let mut intel = vm80a(&program);
+    intel.variant = Variant::I8080;
+    intel.run().unwrap();
+    assert_eq!((cpu.pc, cpu.cycles), (0x0009, 4 + 10 + 17 + 5 + 10 + 7));
+    assert_eq!((cpu.registers(), cpu.cycles), (intel.registers(), intel.cycles));
+}
+
+#[test]
+fn test_monitor_program() {
+    // Tape image of a program at 0100H calling the monitor.
+    let mut image = vec![0xe6, 0x01, 0x00, 0x01, 0x10];
+    image.extend([
+        0x0e, b'>',       // MVI C,'>'
+        0xcd, 0x09, 0xf8, // CALL F809H
+        0x21, 0x0e, 0x01, // LXI H,MSG
+        0xcd, 0x18, 0xf8, // CALL F818H
+        0xc3, 0x00, 0xf8, // JMP F800H
+        b'O', b'K', 0x00,
+    ]);
+    image.extend([0x00, 0x00, 0xe6, 0x12, 0x34]);
+    let (start, data) = parse_rk(&image).unwrap();
+    assert_eq!((start, data.len()), (0x0100, 17));
+    assert!(parse_rk(&image[..10]).is_err());
+
+    let mut cpu = Cpu::with_variant(Variant::Kr580Vm80a);
+    cpu.ram.load_at(start, &data);
+    let mut console = Vec::new();
+    rk86::run_with(&mut cpu, start, &mut console, |cpu| cpu.next()).unwrap();
+    assert_eq!(console, b">OK");
+    assert_eq!(cpu.pc, rk86::WARM_START);
+}
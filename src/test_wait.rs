@@ -0,0 +1,85 @@
+use crate::bus::*;
+use crate::cpu::*;
+use crate::device::Device;
+
+struct SlowPort;
+
+impl Device for SlowPort {
+    fn read(&mut self) -> u8 {
+        0x55
+    }
+
+    fn write(&mut self, _byte: u8) {}
+
+    fn wait_states(&self) -> u64 {
+        2
+    }
+}
+
+#[test]
+fn test_slow_memory() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[
+        0x3a, 0x00, 0xf0, // LDA F000H
+        0xc3, 0x00, 0xf0, // JMP F000H
+    ]);
+    cpu.ram.load_at(0xf000, &[0x00]); // NOP
+    // One wait state on the slow EPROM board at F000H.
+    cpu.ram.set_wait_states(0xf000..0x10000, 1);
+    cpu.next().unwrap();
+    assert_eq!(cpu.cycles, 13 + 1);
+    cpu.next().unwrap();
+    cpu.next().unwrap();
+    assert_eq!(cpu.cycles, 14 + 10 + 4 + 1);
+}
+
+#[test]
+fn test_slow_device() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0xdb, 0x10, 0xd3, 0x10]); // IN 10H, OUT 10H
+    cpu.devices[0x10] = Some(Box::new(SlowPort));
+    cpu.next().unwrap();
+    assert_eq!(cpu.a, 0x55);
+    assert_eq!(cpu.cycles, 10 + 2);
+    cpu.next().unwrap();
+    assert_eq!(cpu.cycles, 2 * (10 + 2));
+}
+
+#[test]
+fn test_waits_in_machine_cycles() {
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x3a, 0x00, 0xf0, 0xdb, 0x10]); // LDA F000H, IN 10H
+    cpu.ram.set_wait_states(0xf000..0xf001, 2);
+    cpu.ram.set_wait_states(0x0000..0x0001, 1);
+    cpu.devices[0x10] = Some(Box::new(SlowPort));
+    let states: Vec<_> = cpu.step_instruction_cycles().unwrap().iter().map(|c| c.states).collect();
+    assert_eq!(states, [4 + 1, 3, 3, 3 + 2]);
+    let cycles = cpu.step_instruction_cycles().unwrap();
+    assert_eq!(cycles[2].status, INPUT_READ);
+    assert_eq!(cycles[2].states, 3 + 2);
+    assert_eq!(cpu.cycles, 16 + 12);
+}
+
+#[test]
+fn test_lookers_on_do_not_wait() {
+    let mut cpu = Cpu::new();
+    cpu.ram.set_wait_states(0x0000..0x0100, 3);
+    cpu.ram.load_byte(0x0010);
+    cpu.next().unwrap();
+    assert_eq!(cpu.cycles, 4 + 3);
+}
+
+#[test]
+fn test_read_modify_write_of_rom() {
+    // INR M waits on its read and its write, even when ROM ignores the
+    // write.
+    let mut cpu = Cpu::new();
+    cpu.ram.load_at(0, &[0x21, 0x00, 0xf0, 0x34]); // LXI H,F000H; INR M
+    cpu.ram.load_at(0xf000, &[0x41]);
+    cpu.ram.protect(0xf000..0x10000);
+    cpu.ram.set_wait_states(0xf000..0x10000, 2);
+    cpu.next().unwrap();
+    cpu.next().unwrap();
+    assert_eq!(cpu.cycles, 10 + 10 + 2 * 2);
+    assert_eq!(cpu.ram.peek(0xf000), 0x41);
+}
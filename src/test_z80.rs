@@ -0,0 +1,391 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cpu::*;
+use crate::device::Device;
+use crate::interrupt::RstLatch;
+
+// Z80 flag bits; X and Y are left out of the masks below.
+const C: u8 = 0x01;
+const N: u8 = 0x02;
+const PV: u8 = 0x04;
+const H: u8 = 0x10;
+const Z: u8 = 0x40;
+const S: u8 = 0x80;
+const DOCUMENTED: u8 = S | Z | H | PV | N | C;
+
+fn z80(program: &[u8]) -> Cpu {
+    let mut cpu = Cpu::with_variant(Variant::Z80);
+    cpu.sp = 0x1000;
+    cpu.ram.load_at(0, program);
+    cpu
+}
+
+// Runs `n` instructions, returning the T-states of each.
+fn states(cpu: &mut Cpu, n: usize) -> Vec<u64> {
+    (0..n).map(|_| {
+        let before = cpu.cycles;
+        cpu.next().unwrap();
+        cpu.cycles - before
+    }).collect()
+}
+
+// A port that always reads the same byte.
+struct Fixed(u8);
+
+impl Device for Fixed {
+    fn read(&mut self) -> u8 {
+        self.0
+    }
+
+    fn write(&mut self, _byte: u8) {}
+}
+
+#[test]
+fn test_index_registers() {
+    let mut cpu = z80(&[
+        0xdd, 0x21, 0x00, 0x20, // LD IX,2000H
+        0xdd, 0x36, 0x05, 0x7f, // LD (IX+5),7FH
+        0xdd, 0x34, 0x05,       // INC (IX+5)
+        0xfd, 0x21, 0x10, 0x20, // LD IY,2010H
+        0xfd, 0x7e, 0xf5,       // LD A,(IY-11)
+        0x76,                   // HALT
+    ]);
+    cpu.run().unwrap();
+    assert_eq!(cpu.ram.load_byte(0x2005), 0x80);
+    assert_eq!(cpu.a, 0x80);
+    assert_eq!(cpu.z80.ix, 0x2000);
+    assert_eq!(cpu.flags() & (PV | H | N), PV | H);
+    assert_eq!(cpu.cycles, 14 + 19 + 23 + 14 + 19 + 4);
+}
+
+#[test]
+fn test_alternate_registers() {
+    let mut cpu = z80(&[
+        0x01, 0x34, 0x12, // LD BC,1234H
+        0x3e, 0x55,       // LD A,55H
+        0x08,             // EX AF,AF'
+        0xd9,             // EXX
+        0x01, 0x78, 0x56, // LD BC,5678H
+        0xd9,             // EXX
+        0x76,             // HALT
+    ]);
+    cpu.record(16);
+    cpu.run().unwrap();
+    assert_eq!((cpu.b, cpu.c), (0x12, 0x34));
+    assert_eq!(cpu.z80.bc_, 0x5678);
+    assert_eq!(cpu.z80.af_ >> 8, 0x55);
+    assert_eq!(cpu.a, 0);
+
+    cpu.step_back(); // HALT
+    cpu.step_back(); // EXX
+    assert_eq!(cpu.z80.bc_, 0x1234);
+}
+
+#[test]
+fn test_block_move_and_djnz() {
+    let mut cpu = z80(&[
+        0x21, 0x00, 0x01, // LD HL,0100H
+        0x11, 0x00, 0x02, // LD DE,0200H
+        0x01, 0x04, 0x00, // LD BC,4
+        0xed, 0xb0,       // LDIR
+        0x06, 0x03,       // LD B,3
+        0x3c,             // INC A
+        0x10, 0xfd,       // DJNZ -3
+        0x76,             // HALT
+    ]);
+    cpu.ram.load_at(0x100, b"Z80!");
+    cpu.run().unwrap();
+    let copied: Vec<u8> = (0x200..0x204).map(|addr| cpu.ram.load_byte(addr)).collect();
+    assert_eq!(copied, b"Z80!");
+    assert_eq!(cpu.get_hl_addr(), 0x104);
+    assert_eq!(cpu.flags() & PV, 0);
+    assert_eq!(cpu.a, 3);
+    assert_eq!(cpu.cycles, 3 * 10 + 3 * 21 + 16 + 7 + 3 * 4 + 2 * 13 + 8 + 4);
+}
+
+#[test]
+fn test_flags() {
+    // 7FH + 1 overflows, where the 8080 would report parity.
+    let mut cpu = z80(&[0x3e, 0x7f, 0xc6, 0x01]);
+    cpu.next().unwrap();
+    cpu.next().unwrap();
+    assert_eq!(cpu.flags() & (PV | N), PV);
+
+    // DAA corrects a subtraction, using N.
+    let mut cpu = z80(&[0x3e, 0x15, 0xd6, 0x06, 0x27]);
+    for _ in 0..3 {
+        cpu.next().unwrap();
+    }
+    assert_eq!(cpu.a, 0x09);
+    assert_ne!(cpu.flags() & N, 0);
+
+    // NEG, then BIT and SET on (IY+d).
+    let mut cpu = z80(&[
+        0x3e, 0x01,             // LD A,1
+        0xed, 0x44,             // NEG
+        0xfd, 0x21, 0x00, 0x30, // LD IY,3000H
+        0xfd, 0xcb, 0x02, 0xc6, // SET 0,(IY+2)
+        0xfd, 0xcb, 0x02, 0x46, // BIT 0,(IY+2)
+    ]);
+    cpu.next().unwrap();
+    cpu.next().unwrap();
+    assert_eq!(cpu.a, 0xff);
+    assert!(cpu.get_flag(CARRY_BIT));
+    for _ in 0..3 {
+        cpu.next().unwrap();
+    }
+    assert_eq!(cpu.ram.load_byte(0x3002), 0x01);
+    assert!(!cpu.get_flag(ZERO_BIT));
+}
+
+#[test]
+fn test_interrupt_modes() {
+    let latch = Rc::new(RefCell::new(RstLatch::new(2)));
+    let mut cpu = z80(&[
+        0xed, 0x5e, // IM 2
+        0x3e, 0x30, // LD A,30H
+        0xed, 0x47, // LD I,A
+        0xfb,       // EI
+        0x76,       // HALT
+    ]);
+    cpu.connect_interrupts(&latch);
+    // RST 2 (D7H) on the bus picks the table entry at 30D7H.
+    cpu.ram.save_word(0x30d7, 0x0050);
+    for _ in 0..5 {
+        cpu.next().unwrap();
+    }
+    assert!(cpu.halted);
+    latch.borrow_mut().set(true);
+    let before = cpu.cycles;
+    cpu.next().unwrap();
+    assert_eq!(cpu.pc, 0x0050);
+    assert_eq!(cpu.cycles - before, 19);
+    assert_eq!(cpu.ram.load_word(cpu.sp), 0x0008);
+    assert!(!cpu.inte);
+
+    // Mode 1 always goes to 38H.
+    let latch = Rc::new(RefCell::new(RstLatch::new(2)));
+    let mut cpu = z80(&[0xed, 0x56, 0xfb, 0x00]);
+    cpu.connect_interrupts(&latch);
+    for _ in 0..3 {
+        cpu.next().unwrap();
+    }
+    latch.borrow_mut().set(true);
+    cpu.next().unwrap();
+    assert_eq!(cpu.pc, 0x0038);
+}
+
+#[test]
+fn test_daa_after_subtract() {
+    // 10H - 01H borrows from the low digit: 0FH becomes 09H.
+    let mut cpu = z80(&[0x3e, 0x10, 0xd6, 0x01, 0x27]);
+    states(&mut cpu, 2);
+    assert_eq!(cpu.flags() & DOCUMENTED, H | N);
+    states(&mut cpu, 1);
+    assert_eq!(cpu.a, 0x09);
+    assert_eq!(cpu.flags() & DOCUMENTED, PV | N);
+
+    // 00H - 01H borrows from both: FFH becomes 99H, with carry.
+    let mut cpu = z80(&[0xaf, 0xd6, 0x01, 0x27]);
+    states(&mut cpu, 3);
+    assert_eq!(cpu.a, 0x99);
+    assert_eq!(cpu.flags() & DOCUMENTED, S | PV | N | C);
+}
+
+#[test]
+fn test_adc_sbc_hl() {
+    // 7FFFH + 0 + carry: overflow, and a carry out of bit 11.
+    let mut cpu = z80(&[0x21, 0xff, 0x7f, 0x11, 0x00, 0x00, 0x37, 0xed, 0x5a]);
+    assert_eq!(states(&mut cpu, 4)[3], 15);
+    assert_eq!(cpu.get_hl_addr(), 0x8000);
+    assert_eq!(cpu.flags() & DOCUMENTED, S | H | PV);
+
+    // 8000H - 1: overflow, and a borrow into bit 11.
+    let mut cpu = z80(&[0x21, 0x00, 0x80, 0x11, 0x01, 0x00, 0xb7, 0xed, 0x52]);
+    assert_eq!(states(&mut cpu, 4)[3], 15);
+    assert_eq!(cpu.get_hl_addr(), 0x7fff);
+    assert_eq!(cpu.flags() & DOCUMENTED, H | PV | N);
+
+    // Z looks at all 16 bits.
+    let mut cpu = z80(&[0x21, 0x00, 0x01, 0x11, 0x00, 0x01, 0xb7, 0xed, 0x52]);
+    states(&mut cpu, 4);
+    assert_eq!(cpu.get_hl_addr(), 0x0000);
+    assert_eq!(cpu.flags() & DOCUMENTED, Z | N);
+}
+
+#[test]
+fn test_neg() {
+    for (a, result, flags) in [
+        (0x00, 0x00, Z | N),
+        (0x01, 0xff, S | H | N | C),
+        (0x80, 0x80, S | PV | N | C),
+        (0x10, 0xf0, S | N | C),
+    ] {
+        let mut cpu = z80(&[0x3e, a, 0xed, 0x44]);
+        assert_eq!(states(&mut cpu, 2)[1], 8);
+        assert_eq!(cpu.a, result, "NEG {a:02x}");
+        assert_eq!(cpu.flags() & DOCUMENTED, flags, "NEG {a:02x}");
+    }
+}
+
+#[test]
+fn test_rld_rrd() {
+    let mut cpu = z80(&[
+        0x21, 0x00, 0x50, // LD HL,5000H
+        0x37,             // SCF
+        0xed, 0x6f,       // RLD
+        0xed, 0x67,       // RRD
+    ]);
+    cpu.ram.save_byte(0x5000, 0x31);
+    cpu.a = 0x7a;
+    assert_eq!(states(&mut cpu, 3)[2], 18);
+    assert_eq!((cpu.a, cpu.ram.load_byte(0x5000)), (0x73, 0x1a));
+    // Carry is kept; P/V is parity.
+    assert_eq!(cpu.flags() & DOCUMENTED, C);
+
+    cpu.a = 0x84;
+    cpu.ram.save_byte(0x5000, 0x20);
+    assert_eq!(states(&mut cpu, 1)[0], 18);
+    assert_eq!((cpu.a, cpu.ram.load_byte(0x5000)), (0x80, 0x42));
+    assert_eq!(cpu.flags() & DOCUMENTED, S | C);
+}
+
+#[test]
+fn test_cpi_cpir() {
+    // CPI: 10H - 01H half-borrows, and BC runs out.
+    let mut cpu = z80(&[0x21, 0x00, 0x40, 0x01, 0x01, 0x00, 0x3e, 0x10, 0xed, 0xa1]);
+    cpu.ram.save_byte(0x4000, 0x01);
+    assert_eq!(states(&mut cpu, 4)[3], 16);
+    assert_eq!(cpu.get_hl_addr(), 0x4001);
+    assert_eq!((cpu.b, cpu.c), (0, 0));
+    assert_eq!(cpu.flags() & DOCUMENTED, H | N);
+
+    // CPIR stops on the match with BC left, and keeps the carry.
+    let mut cpu = z80(&[0x21, 0x00, 0x40, 0x01, 0x03, 0x00, 0x3e, 0x22, 0x37, 0xed, 0xb1]);
+    cpu.ram.load_at(0x4000, &[0x11, 0x22, 0x33]);
+    states(&mut cpu, 4);
+    assert_eq!(states(&mut cpu, 2), [21, 16]);
+    assert_eq!(cpu.pc, 0x000b);
+    assert_eq!(cpu.get_hl_addr(), 0x4002);
+    assert_eq!((cpu.b, cpu.c), (0, 1));
+    assert_eq!(cpu.flags() & DOCUMENTED, Z | PV | N | C);
+}
+
+#[test]
+fn test_in_r_c() {
+    // IN sets S, Z and parity from the byte, clears H and N, keeps carry.
+    let mut cpu = z80(&[0x0e, 0x20, 0x37, 0xed, 0x50, 0xed, 0x78]);
+    cpu.devices[0x20] = Some(Box::new(Fixed(0x00)));
+    assert_eq!(states(&mut cpu, 3)[2], 12);
+    assert_eq!(cpu.d, 0x00);
+    assert_eq!(cpu.flags() & DOCUMENTED, Z | PV | C);
+
+    cpu.devices[0x20] = Some(Box::new(Fixed(0x83)));
+    states(&mut cpu, 1);
+    assert_eq!(cpu.a, 0x83);
+    assert_eq!(cpu.flags() & DOCUMENTED, S | C);
+}
+
+#[test]
+fn test_ld_a_i_r() {
+    // P/V copies IFF2.
+    let mut cpu = z80(&[
+        0x3e, 0x80,       // LD A,80H
+        0xed, 0x47,       // LD I,A
+        0xaf,             // XOR A
+        0xed, 0x57,       // LD A,I
+        0xfb,             // EI
+        0xed, 0x57,       // LD A,I
+        0xed, 0x5f,       // LD A,R
+    ]);
+    assert_eq!(states(&mut cpu, 4)[3], 9);
+    assert_eq!(cpu.a, 0x80);
+    assert_eq!(cpu.flags() & DOCUMENTED, S);
+    states(&mut cpu, 2);
+    assert_eq!(cpu.flags() & DOCUMENTED, S | PV);
+    assert_eq!(states(&mut cpu, 1)[0], 9);
+    // R counted every opcode fetch, the two of each ED instruction
+    // included.
+    assert_eq!(cpu.a, 11);
+    assert_eq!(cpu.flags() & DOCUMENTED, PV);
+}
+
+#[test]
+fn test_im2_vectoring() {
+    let latch = Rc::new(RefCell::new(RstLatch::new(4)));
+    let mut cpu = z80(&[
+        0xed, 0x5e, // IM 2
+        0x3e, 0x40, // LD A,40H
+        0xed, 0x47, // LD I,A
+        0xfb,       // EI
+        0x00,       // NOP
+        0x00,       // NOP
+    ]);
+    cpu.connect_interrupts(&latch);
+    // RST 4 (E7H) on the bus reads the vector from 40E7H.
+    cpu.ram.save_word(0x40e7, 0x1234);
+    latch.borrow_mut().set(true);
+    states(&mut cpu, 4);
+    // EI holds the interrupt off for one more instruction.
+    assert_eq!(states(&mut cpu, 1)[0], 4);
+    assert_eq!(cpu.pc, 0x0008);
+    assert_eq!(states(&mut cpu, 1)[0], 19);
+    assert_eq!(cpu.pc, 0x1234);
+    assert_eq!(cpu.ram.load_word(cpu.sp), 0x0008);
+    assert!(!cpu.inte && !cpu.z80.iff2);
+}
+
+#[test]
+fn test_dd_cb_writes_back_to_register() {
+    let mut cpu = z80(&[
+        0xdd, 0x21, 0x00, 0x20, // LD IX,2000H
+        0xdd, 0xcb, 0x05, 0x00, // RLC (IX+5),B
+        0xdd, 0xcb, 0x05, 0xff, // SET 7,(IX+5),A
+        0xdd, 0xcb, 0x05, 0x46, // BIT 0,(IX+5)
+    ]);
+    cpu.ram.save_byte(0x2005, 0x41);
+    cpu.e = 0x55;
+    assert_eq!(states(&mut cpu, 4), [14, 23, 23, 20]);
+    assert_eq!(cpu.b, 0x82);
+    assert_eq!(cpu.a, 0x82);
+    assert_eq!(cpu.ram.load_byte(0x2005), 0x82);
+    // BIT writes nothing back.
+    assert_eq!(cpu.e, 0x55);
+    assert!(cpu.get_flag(ZERO_BIT));
+}
+
+#[test]
+fn test_prefixed_timings() {
+    let mut cpu = z80(&[
+        0xdd, 0x21, 0x00, 0x20, // LD IX,2000H       14
+        0xdd, 0x09,             // ADD IX,BC         15
+        0xdd, 0x23,             // INC IX            10
+        0xdd, 0xe5,             // PUSH IX           15
+        0xdd, 0xe3,             // EX (SP),IX        23
+        0xdd, 0xe1,             // POP IX            14
+        0xdd, 0x36, 0x01, 0x00, // LD (IX+1),0       19
+        0xdd, 0x34, 0x01,       // INC (IX+1)        23
+        0xdd, 0x86, 0x01,       // ADD A,(IX+1)      19
+        0xdd, 0x26, 0x12,       // LD IXH,12H        11
+        0xdd, 0xf9,             // LD SP,IX          10
+        0xcb, 0x00,             // RLC B              8
+        0xcb, 0x06,             // RLC (HL)          15
+        0xcb, 0x46,             // BIT 0,(HL)        12
+        0xed, 0x43, 0x00, 0x30, // LD (3000H),BC     20
+        0xed, 0x4b, 0x00, 0x30, // LD BC,(3000H)     20
+        0xed, 0x56,             // IM 1               8
+        0xed, 0xa0,             // LDI               16
+        0xdd, 0x21, 0x35, 0x00, // LD IX,0035H       14
+        0xdd, 0xe9,             // JP (IX)            8
+        0x00,                   // NOP                4
+    ]);
+    cpu.sp = 0x1000;
+    (cpu.h, cpu.l) = (0x30, 0x10);
+    (cpu.d, cpu.e) = (0x30, 0x20);
+    assert_eq!(states(&mut cpu, 20), [
+        14, 15, 10, 15, 23, 14, 19, 23, 19, 11, 10, 8, 15, 12, 20, 20, 8, 16, 14, 8,
+    ]);
+    assert_eq!(cpu.pc, 0x0035);
+    assert_eq!(states(&mut cpu, 1), [4]);
+}
@@ -0,0 +1,148 @@
+#![allow(unused)]
+
+// Intel 8251 USART. C/D is wired to A0, so the device takes a port pair:
+// even for data, odd for mode/command (out) and status (in).
+//
+// After reset the first control write is the mode word. A synchronous
+// mode (baud factor 00) is followed by one or two sync characters; after
+// that every control write is a command, until an internal reset.
+
+use crate::device::PortDevice;
+use crate::serial::Line;
+
+// Status bits.
+pub const TX_RDY: u8 = 0x01;
+pub const RX_RDY: u8 = 0x02;
+pub const TX_EMPTY: u8 = 0x04;
+const PARITY_ERROR: u8 = 0x08;
+const OVERRUN_ERROR: u8 = 0x10;
+const FRAMING_ERROR: u8 = 0x20;
+const DSR: u8 = 0x80;
+
+// Command bits.
+const TX_ENABLE: u8 = 0x01;
+const RX_ENABLE: u8 = 0x04;
+const ERROR_RESET: u8 = 0x10;
+const INTERNAL_RESET: u8 = 0x40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    Mode,
+    Sync(u8),
+    Command,
+}
+
+pub struct Usart {
+    pub line: Line,
+    expect: Expect,
+    mode: u8,
+    sync: [u8; 2],
+    command: u8,
+    errors: u8,
+    // A byte written while the transmitter was disabled.
+    pending: Option<u8>,
+}
+
+impl Usart {
+    pub fn new(line: Line) -> Self {
+        Self {
+            line,
+            expect: Expect::Mode,
+            mode: 0,
+            sync: [0; 2],
+            command: 0,
+            errors: 0,
+            pending: None,
+        }
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.mode
+    }
+
+    pub fn command(&self) -> u8 {
+        self.command
+    }
+
+    fn reset(&mut self) {
+        self.expect = Expect::Mode;
+        self.command = 0;
+        self.errors = 0;
+        self.pending = None;
+    }
+
+    // Characters are 5 to 8 bits wide.
+    fn char_mask(&self) -> u8 {
+        0xff >> (3 - (self.mode >> 2 & 3))
+    }
+
+    fn control(&mut self, byte: u8) {
+        match self.expect {
+            Expect::Mode => {
+                self.mode = byte;
+                self.expect = if byte & 3 == 0 {
+                    // Bit 7 set means a single sync character.
+                    Expect::Sync(if byte & 0x80 != 0 { 1 } else { 2 })
+                } else {
+                    Expect::Command
+                };
+            },
+            Expect::Sync(left) => {
+                let count = if self.mode & 0x80 != 0 { 1 } else { 2 };
+                self.sync[(count - left) as usize] = byte;
+                self.expect = if left > 1 { Expect::Sync(left - 1) } else { Expect::Command };
+            },
+            Expect::Command => {
+                if byte & INTERNAL_RESET != 0 {
+                    self.reset();
+                    return;
+                }
+                self.command = byte;
+                if byte & ERROR_RESET != 0 {
+                    self.errors = 0;
+                }
+                if byte & TX_ENABLE != 0
+                    && let Some(pending) = self.pending.take() {
+                    self.line.write(pending);
+                }
+            },
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        let mut status = DSR | self.errors;
+        if self.pending.is_none() && self.line.tx_ready() {
+            status |= TX_RDY | TX_EMPTY;
+        }
+        if self.command & RX_ENABLE != 0 && self.line.ready() {
+            status |= RX_RDY;
+        }
+        status
+    }
+}
+
+impl PortDevice for Usart {
+    fn read_port(&mut self, port: u8) -> u8 {
+        if port & 1 == 0 {
+            if self.command & RX_ENABLE == 0 {
+                return 0;
+            }
+            self.line.read().unwrap_or(0) & self.char_mask()
+        } else {
+            self.status()
+        }
+    }
+
+    fn write_port(&mut self, port: u8, byte: u8) {
+        if port & 1 == 0 {
+            let byte = byte & self.char_mask();
+            if self.command & TX_ENABLE != 0 {
+                self.line.write(byte);
+            } else {
+                self.pending = Some(byte);
+            }
+        } else {
+            self.control(byte);
+        }
+    }
+}
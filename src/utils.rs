@@ -2,11 +2,11 @@
 
 use crate::instruction::{RegPair, Src};
 
-pub fn bitmatch(bits: u8, pattern: u8, mask: u8) -> bool {
+pub const fn bitmatch(bits: u8, pattern: u8, mask: u8) -> bool {
     (bits & mask) == (pattern & mask)
 }
 
-pub fn bittest(bits: u8, n: u8) -> bool {
+pub const fn bittest(bits: u8, n: u8) -> bool {
     (bits & (1 << n)) != 0
 }
 
@@ -39,7 +39,17 @@ pub fn flagged_sub(x: u8, y: u8) -> (u8, bool, bool, bool, bool, bool) {
     bittest(res, 7))
 }
 
-pub fn idx2src(idx: u8) -> Src {
+// CALL, Ccc and RST.
+pub fn is_call(opcode: u8) -> bool {
+    opcode == 0xcd || bitmatch(opcode, 0b11000100, 0b11000111) || bitmatch(opcode, 0b11000111, 0b11000111)
+}
+
+// RET and Rcc.
+pub fn is_ret(opcode: u8) -> bool {
+    opcode == 0xc9 || bitmatch(opcode, 0b11000000, 0b11000111)
+}
+
+pub const fn idx2src(idx: u8) -> Src {
     match idx {
         0 => Src::B,
         1 => Src::C,
@@ -53,7 +63,7 @@ pub fn idx2src(idx: u8) -> Src {
     }
 }
 
-pub fn idx2rp_psw(idx: u8) -> RegPair {
+pub const fn idx2rp_psw(idx: u8) -> RegPair {
     match idx {
         0 => RegPair::BC,
         1 => RegPair::DE,
@@ -63,7 +73,7 @@ pub fn idx2rp_psw(idx: u8) -> RegPair {
     }
 }
 
-pub fn idx2rp_sp(idx: u8) -> RegPair {
+pub const fn idx2rp_sp(idx: u8) -> RegPair {
     match idx {
         0 => RegPair::BC,
         1 => RegPair::DE,
@@ -79,4 +89,15 @@ pub fn get_u16(high: u8, low: u8) -> u16 {
 
 pub fn split_u16(val: u16) -> (u8, u8) {
     ((val >> 8) as u8, (val & 0xff) as u8)
-}
\ No newline at end of file
+}
+
+// Accepts `1a3`, `0x1a3` and `01A3H`.
+pub fn parse_hex(s: &str) -> Option<u16> {
+    let s = s.trim();
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    let s = s.strip_suffix(['h', 'H']).unwrap_or(s);
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(s, 16).ok()
+}
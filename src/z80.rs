@@ -0,0 +1,893 @@
+#![allow(unused)]
+
+// The Z80 personality of `Cpu`. It shares A-L, SP, PC, the flag byte,
+// memory and ports with the 8080 core and keeps the rest in `Regs`: the
+// alternate set, IX/IY, I and R, IFF2 and the interrupt mode. IFF1 is
+// `Cpu::inte`.
+//
+// The flag bits sit where the 8080's do: S Z Y H X P/V N C. The 8080's
+// fixed bit 1 becomes N, P/V is overflow after arithmetic, and the
+// undocumented X and Y copy bits 3 and 5 of the result.
+
+use crate::bus;
+use crate::cpu::Cpu;
+use crate::dram::Dram;
+use crate::error::Error;
+use crate::utils::{get_u16, split_u16};
+
+const C: u8 = 0x01;
+const N: u8 = 0x02;
+const PV: u8 = 0x04;
+const X: u8 = 0x08;
+const H: u8 = 0x10;
+const Y: u8 = 0x20;
+const Z: u8 = 0x40;
+const S: u8 = 0x80;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Regs {
+    pub af_: u16,
+    pub bc_: u16,
+    pub de_: u16,
+    pub hl_: u16,
+    pub ix: u16,
+    pub iy: u16,
+    pub i: u8,
+    pub r: u8,
+    pub iff2: bool,
+    pub im: u8,
+}
+
+// What stands in for HL: a DD or FD prefix swaps in IX or IY, H and L for
+// their halves, and (HL) for (IX+d) or (IY+d).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Index {
+    HL,
+    IX,
+    IY,
+}
+
+fn szxy(v: u8) -> u8 {
+    v & (S | X | Y) | if v == 0 { Z } else { 0 }
+}
+
+fn szxyp(v: u8) -> u8 {
+    szxy(v) | if v.count_ones().is_multiple_of(2) { PV } else { 0 }
+}
+
+// Length in bytes of the instruction at `addr`, prefixes included. A run
+// of DD and FD prefixes makes one instruction, as `z80_execute` runs it.
+pub(crate) fn length(ram: &Dram, addr: u16) -> u16 {
+    let byte = |i: u16| ram.load_byte(addr.wrapping_add(i));
+    let mut at = 0;
+    while matches!(byte(at), 0xdd | 0xfd) {
+        at += 1;
+    }
+    let indexed = at > 0;
+    let op = byte(at);
+    let (x, y, z) = (op >> 6, op >> 3 & 7, op & 7);
+    // (IX+d) and (IY+d) in place of (HL); HALT has none.
+    let disp = indexed && match (x, z) {
+        (0, 4..=6) => y == 6,
+        (1, _) => (y == 6) != (z == 6),
+        (2, 6) => true,
+        _ => false,
+    };
+    let operands = match (x, z) {
+        (0, 0) => u16::from(y >= 2),
+        // LD rr,nn; LD (nn),HL, LD HL,(nn) and the like
+        (0, 1) if y & 1 == 0 => 2,
+        (0, 2) if y >= 4 => 2,
+        (0, 6) | (3, 6) => 1,
+        (3, 2) | (3, 4) => 2,
+        (3, 3) => match y {
+            0 => 2,
+            // CB op, or DD CB d op
+            1 => if indexed { 2 } else { 1 },
+            2 | 3 => 1,
+            _ => 0,
+        },
+        (3, 5) => match y {
+            1 => 2,
+            // ED op, and ED LD (nn),rr / LD rr,(nn)
+            5 => if byte(at + 1) & 0xc7 == 0x43 { 3 } else { 1 },
+            _ => 0,
+        },
+        _ => 0,
+    };
+    at + 1 + u16::from(disp) + operands
+}
+
+impl Cpu {
+    // One Z80 instruction, or an interrupt taken in its place.
+    pub(crate) fn z80_step(&mut self) -> Result<(), Error> {
+        // The Z80 executor writes the flag byte directly; only an 8080
+        // instruction from an IM 0 bus leaves a result pending.
+        self.materialize_flags();
+        if let Some(bus) = self.acknowledge() {
+            return self.z80_interrupt(&bus);
+        }
+        // HALT runs NOPs until an interrupt comes.
+        if self.halted {
+            self.ei_delay = false;
+            self.z80_refresh();
+            self.cycles += 4;
+            return Ok(());
+        }
+        self.journaled(|cpu| {
+            cpu.ei_delay = false;
+            let op = cpu.z80_fetch_op();
+            cpu.z80_execute(op, Index::HL)
+        })
+    }
+
+    fn z80_interrupt(&mut self, bus: &[u8]) -> Result<(), Error> {
+        self.halted = false;
+        self.z80.iff2 = false;
+        self.z80_refresh();
+        match self.z80.im {
+            // Mode 0 runs what is on the bus like an 8080 does, two T-states
+            // slower.
+            0 => {
+                self.cycles += 2;
+                self.interrupt(bus).map(|_| ())
+            },
+            1 => {
+                self.inte = false;
+                self.cycles += 13;
+                self.journaled(|cpu| {
+                    cpu.push(cpu.pc);
+                    cpu.pc = 0x38;
+                    Ok(())
+                })
+            },
+            // Mode 2 calls through the table entry I:bus.
+            _ => {
+                self.inte = false;
+                self.cycles += 19;
+                let entry = get_u16(self.z80.i, bus[0]);
+                self.journaled(|cpu| {
+                    cpu.push(cpu.pc);
+                    cpu.pc = cpu.ram.load_word(entry);
+                    Ok(())
+                })
+            },
+        }
+    }
+
+    // The low seven bits of R count opcode fetches.
+    fn z80_refresh(&mut self) {
+        let r = self.z80.r;
+        self.z80.r = r & 0x80 | r.wrapping_add(1) & 0x7f;
+    }
+
+    fn z80_fetch_op(&mut self) -> u8 {
+        self.z80_refresh();
+        self.next_byte()
+    }
+
+    fn z80_imm16(&mut self) -> u16 {
+        let lo = self.next_byte();
+        get_u16(self.next_byte(), lo)
+    }
+
+    fn z80_execute(&mut self, op: u8, idx: Index) -> Result<(), Error> {
+        let (x, y, z) = (op >> 6, op >> 3 & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+        // Extra T-states for (IX+d) over (HL).
+        let disp = if idx == Index::HL { 0 } else { 8 };
+        if idx != Index::HL {
+            self.cycles += 4;
+        }
+        match (x, z) {
+            (0, 0) => match y {
+                0 => self.cycles += 4,
+                1 => {
+                    let af = get_u16(self.a, self.flag);
+                    (self.a, self.flag) = split_u16(self.z80.af_);
+                    self.z80.af_ = af;
+                    self.cycles += 4;
+                },
+                2 => {
+                    let d = self.next_byte();
+                    self.b = self.b.wrapping_sub(1);
+                    self.z80_jr(d, self.b != 0, 13, 8);
+                },
+                3 => {
+                    let d = self.next_byte();
+                    self.z80_jr(d, true, 12, 12);
+                },
+                _ => {
+                    let d = self.next_byte();
+                    let taken = self.z80_condition(y - 4);
+                    self.z80_jr(d, taken, 12, 7);
+                },
+            },
+            (0, 1) => {
+                if q == 0 {
+                    let nn = self.z80_imm16();
+                    self.z80_set_rp(p, idx, nn);
+                    self.cycles += 10;
+                } else {
+                    let sum = self.z80_add16(self.z80_index(idx), self.z80_rp(p, idx));
+                    self.z80_set_index(idx, sum);
+                    self.cycles += 11;
+                }
+            },
+            (0, 2) => match (p, q) {
+                (0, 0) => self.z80_store(self.get_bc_addr(), self.a, 7),
+                (1, 0) => self.z80_store(self.get_de_addr(), self.a, 7),
+                (2, 0) => {
+                    let nn = self.z80_imm16();
+                    self.ram.save_word(nn, self.z80_index(idx));
+                    self.cycles += 16;
+                },
+                (3, 0) => {
+                    let nn = self.z80_imm16();
+                    self.z80_store(nn, self.a, 13);
+                },
+                (0, _) => self.z80_load_a(self.get_bc_addr(), 7),
+                (1, _) => self.z80_load_a(self.get_de_addr(), 7),
+                (2, _) => {
+                    let nn = self.z80_imm16();
+                    self.z80_set_index(idx, self.ram.load_word(nn));
+                    self.cycles += 16;
+                },
+                _ => {
+                    let nn = self.z80_imm16();
+                    self.z80_load_a(nn, 13);
+                },
+            },
+            (0, 3) => {
+                let rp = self.z80_rp(p, idx);
+                let rp = if q == 0 { rp.wrapping_add(1) } else { rp.wrapping_sub(1) };
+                self.z80_set_rp(p, idx, rp);
+                self.cycles += 6;
+            },
+            (0, 4) | (0, 5) => {
+                let inc = z == 4;
+                if y == 6 {
+                    let addr = self.z80_operand(idx);
+                    let v = self.ram.load_byte(addr);
+                    let v = if inc { self.z80_inc(v) } else { self.z80_dec(v) };
+                    self.ram.save_byte(addr, v);
+                    self.cycles += 11 + disp;
+                } else {
+                    let v = self.z80_reg(y, idx);
+                    let v = if inc { self.z80_inc(v) } else { self.z80_dec(v) };
+                    self.z80_set_reg(y, idx, v);
+                    self.cycles += 4;
+                }
+            },
+            (0, 6) => {
+                if y == 6 {
+                    let addr = self.z80_operand(idx);
+                    let n = self.next_byte();
+                    self.ram.save_byte(addr, n);
+                    self.cycles += if idx == Index::HL { 10 } else { 15 };
+                } else {
+                    let n = self.next_byte();
+                    self.z80_set_reg(y, idx, n);
+                    self.cycles += 7;
+                }
+            },
+            (0, _) => {
+                self.z80_accumulator(y);
+                self.cycles += 4;
+            },
+            (1, _) => {
+                if y == 6 && z == 6 {
+                    self.halted = true;
+                    self.cycles += 4;
+                } else if z == 6 {
+                    let addr = self.z80_operand(idx);
+                    let v = self.ram.load_byte(addr);
+                    self.z80_set_reg(y, Index::HL, v);
+                    self.cycles += 7 + disp;
+                } else if y == 6 {
+                    let addr = self.z80_operand(idx);
+                    self.ram.save_byte(addr, self.z80_reg(z, Index::HL));
+                    self.cycles += 7 + disp;
+                } else {
+                    let v = self.z80_reg(z, idx);
+                    self.z80_set_reg(y, idx, v);
+                    self.cycles += 4;
+                }
+            },
+            (2, _) => {
+                if z == 6 {
+                    let addr = self.z80_operand(idx);
+                    self.z80_alu(y, self.ram.load_byte(addr));
+                    self.cycles += 7 + disp;
+                } else {
+                    self.z80_alu(y, self.z80_reg(z, idx));
+                    self.cycles += 4;
+                }
+            },
+            (3, 0) => {
+                if self.z80_condition(y) {
+                    self.pc = self.pop();
+                    self.cycles += 11;
+                } else {
+                    self.cycles += 5;
+                }
+            },
+            (3, 1) => match (q, p) {
+                (0, _) => {
+                    let word = self.pop();
+                    self.z80_set_rp2(p, idx, word);
+                    self.cycles += 10;
+                },
+                (_, 0) => {
+                    self.pc = self.pop();
+                    self.cycles += 10;
+                },
+                (_, 1) => {
+                    let (bc, de, hl) = (self.get_bc_addr(), self.get_de_addr(), self.get_hl_addr());
+                    (self.b, self.c) = split_u16(self.z80.bc_);
+                    (self.d, self.e) = split_u16(self.z80.de_);
+                    (self.h, self.l) = split_u16(self.z80.hl_);
+                    (self.z80.bc_, self.z80.de_, self.z80.hl_) = (bc, de, hl);
+                    self.cycles += 4;
+                },
+                (_, 2) => {
+                    self.pc = self.z80_index(idx);
+                    self.cycles += 4;
+                },
+                _ => {
+                    self.sp = self.z80_index(idx);
+                    self.cycles += 6;
+                },
+            },
+            (3, 2) => {
+                let nn = self.z80_imm16();
+                if self.z80_condition(y) {
+                    self.pc = nn;
+                }
+                self.cycles += 10;
+            },
+            (3, 3) => match y {
+                0 => {
+                    self.pc = self.z80_imm16();
+                    self.cycles += 10;
+                },
+                1 => self.z80_cb(idx),
+                2 => {
+                    let n = self.next_byte();
+                    self.z80_out(n, self.a);
+                    self.cycles += 11;
+                },
+                3 => {
+                    let n = self.next_byte();
+                    self.a = self.z80_in(n);
+                    self.cycles += 11;
+                },
+                4 => {
+                    let top = self.ram.load_word(self.sp);
+                    self.ram.save_word(self.sp, self.z80_index(idx));
+                    self.z80_set_index(idx, top);
+                    self.cycles += 19;
+                },
+                5 => {
+                    (self.d, self.e, self.h, self.l) = (self.h, self.l, self.d, self.e);
+                    self.cycles += 4;
+                },
+                6 => {
+                    self.inte = false;
+                    self.z80.iff2 = false;
+                    self.cycles += 4;
+                },
+                _ => {
+                    self.inte = true;
+                    self.z80.iff2 = true;
+                    self.ei_delay = true;
+                    self.cycles += 4;
+                },
+            },
+            (3, 4) => {
+                let nn = self.z80_imm16();
+                if self.z80_condition(y) {
+                    self.push(self.pc);
+                    self.pc = nn;
+                    self.cycles += 17;
+                } else {
+                    self.cycles += 10;
+                }
+            },
+            (3, 5) => match (q, p) {
+                (0, _) => {
+                    self.push(self.z80_rp2(p, idx));
+                    self.cycles += 11;
+                },
+                (_, 0) => {
+                    let nn = self.z80_imm16();
+                    self.push(self.pc);
+                    self.pc = nn;
+                    self.cycles += 17;
+                },
+                (_, 1) => {
+                    let op = self.z80_fetch_op();
+                    return self.z80_execute(op, Index::IX);
+                },
+                (_, 2) => self.z80_ed(),
+                _ => {
+                    let op = self.z80_fetch_op();
+                    return self.z80_execute(op, Index::IY);
+                },
+            },
+            (3, 6) => {
+                let n = self.next_byte();
+                self.z80_alu(y, n);
+                self.cycles += 7;
+            },
+            _ => {
+                self.push(self.pc);
+                self.pc = (y as u16) << 3;
+                self.cycles += 11;
+            },
+        }
+        Ok(())
+    }
+
+    // CB: rotates and shifts, BIT, RES and SET. Behind DD or FD the
+    // displacement comes before the opcode, and the result also lands in
+    // the register the opcode names (undocumented).
+    fn z80_cb(&mut self, idx: Index) {
+        let indexed = idx != Index::HL;
+        let addr = indexed.then(|| self.z80_operand(idx));
+        let op = if indexed { self.next_byte() } else { self.z80_fetch_op() };
+        let (x, y, z) = (op >> 6, op >> 3 & 7, op & 7);
+        let addr = addr.or((z == 6).then(|| self.get_hl_addr()));
+        let v = match addr {
+            Some(addr) => self.ram.load_byte(addr),
+            None => self.z80_reg(z, Index::HL),
+        };
+        self.cycles += match (indexed, addr.is_some(), x == 1) {
+            (true, _, true) => 16,
+            (true, _, false) => 19,
+            (false, true, true) => 12,
+            (false, true, false) => 15,
+            _ => 8,
+        };
+        let result = match x {
+            0 => self.z80_shift(y, v),
+            1 => {
+                let bit = v & 1 << y;
+                // X and Y come from the operand, or for memory from the
+                // high byte of the address.
+                let xy = addr.map_or(v, |addr| (addr >> 8) as u8);
+                self.flag = self.flag & C | H | bit & S | xy & (X | Y)
+                    | if bit == 0 { Z | PV } else { 0 };
+                return;
+            },
+            2 => v & !(1 << y),
+            _ => v | 1 << y,
+        };
+        if let Some(addr) = addr {
+            self.ram.save_byte(addr, result);
+        }
+        if addr.is_none() || indexed && z != 6 {
+            self.z80_set_reg(z, Index::HL, result);
+        }
+    }
+
+    // ED: 16-bit arithmetic, block moves, I/O through C and the interrupt
+    // registers. Undefined ED opcodes do nothing.
+    fn z80_ed(&mut self) {
+        let op = self.z80_fetch_op();
+        let (x, y, z) = (op >> 6, op >> 3 & 7, op & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match (x, z) {
+            (1, 0) => {
+                let v = self.z80_in(self.c);
+                self.flag = self.flag & C | szxyp(v);
+                // ED 70 only sets the flags.
+                if y != 6 {
+                    self.z80_set_reg(y, Index::HL, v);
+                }
+                self.cycles += 12;
+            },
+            (1, 1) => {
+                let v = if y == 6 { 0 } else { self.z80_reg(y, Index::HL) };
+                self.z80_out(self.c, v);
+                self.cycles += 12;
+            },
+            (1, 2) => {
+                self.z80_adc16(self.z80_rp(p, Index::HL), q == 0);
+                self.cycles += 15;
+            },
+            (1, 3) => {
+                let nn = self.z80_imm16();
+                if q == 0 {
+                    self.ram.save_word(nn, self.z80_rp(p, Index::HL));
+                } else {
+                    let word = self.ram.load_word(nn);
+                    self.z80_set_rp(p, Index::HL, word);
+                }
+                self.cycles += 20;
+            },
+            (1, 4) => {
+                let v = self.a;
+                self.a = 0;
+                self.z80_alu(2, v);
+                self.cycles += 8;
+            },
+            // RETN and RETI.
+            (1, 5) => {
+                self.inte = self.z80.iff2;
+                self.pc = self.pop();
+                self.cycles += 14;
+            },
+            (1, 6) => {
+                self.z80.im = [0, 0, 1, 2][y as usize & 3];
+                self.cycles += 8;
+            },
+            (1, 7) => match y {
+                0 => {
+                    self.z80.i = self.a;
+                    self.cycles += 9;
+                },
+                1 => {
+                    self.z80.r = self.a;
+                    self.cycles += 9;
+                },
+                2 | 3 => {
+                    self.a = if y == 2 { self.z80.i } else { self.z80.r };
+                    self.flag = self.flag & C | szxy(self.a) | if self.z80.iff2 { PV } else { 0 };
+                    self.cycles += 9;
+                },
+                4 | 5 => {
+                    let addr = self.get_hl_addr();
+                    let m = self.ram.load_byte(addr);
+                    let (m, a) = if y == 4 {
+                        (self.a << 4 | m >> 4, self.a & 0xf0 | m & 0x0f)
+                    } else {
+                        (m << 4 | self.a & 0x0f, self.a & 0xf0 | m >> 4)
+                    };
+                    self.ram.save_byte(addr, m);
+                    self.a = a;
+                    self.flag = self.flag & C | szxyp(a);
+                    self.cycles += 18;
+                },
+                _ => self.cycles += 8,
+            },
+            (2, 0..=3) if y >= 4 => self.z80_block(y, z),
+            _ => self.cycles += 8,
+        }
+    }
+
+    // LDI, CPI, INI and OUTI, their decrementing forms, and the repeating
+    // ones, which run again from the same PC until done.
+    fn z80_block(&mut self, y: u8, z: u8) {
+        let step = if y & 1 == 0 { 1 } else { 0xffff };
+        let hl = self.get_hl_addr();
+        (self.h, self.l) = split_u16(hl.wrapping_add(step));
+        let again = match z {
+            0 => {
+                let v = self.ram.load_byte(hl);
+                let de = self.get_de_addr();
+                self.ram.save_byte(de, v);
+                (self.d, self.e) = split_u16(de.wrapping_add(step));
+                let bc = self.get_bc_addr().wrapping_sub(1);
+                (self.b, self.c) = split_u16(bc);
+                let n = v.wrapping_add(self.a);
+                self.flag = self.flag & (S | Z | C) | n & X | n << 4 & Y
+                    | if bc != 0 { PV } else { 0 };
+                bc != 0
+            },
+            1 => {
+                let v = self.ram.load_byte(hl);
+                let res = self.a.wrapping_sub(v);
+                let half = (self.a ^ v ^ res) & H;
+                let bc = self.get_bc_addr().wrapping_sub(1);
+                (self.b, self.c) = split_u16(bc);
+                let n = res.wrapping_sub(half >> 4);
+                self.flag = self.flag & C | N | half | res & S | if res == 0 { Z } else { 0 }
+                    | n & X | n << 4 & Y | if bc != 0 { PV } else { 0 };
+                bc != 0 && res != 0
+            },
+            2 => {
+                let v = self.z80_in(self.c);
+                self.ram.save_byte(hl, v);
+                self.b = self.b.wrapping_sub(1);
+                self.flag = self.flag & C | szxy(self.b) | N;
+                self.b != 0
+            },
+            _ => {
+                self.b = self.b.wrapping_sub(1);
+                let v = self.ram.load_byte(hl);
+                self.z80_out(self.c, v);
+                self.flag = self.flag & C | szxy(self.b) | N;
+                self.b != 0
+            },
+        };
+        if y >= 6 && again {
+            self.pc = self.pc.wrapping_sub(2);
+            self.cycles += 21;
+        } else {
+            self.cycles += 16;
+        }
+    }
+
+    fn z80_jr(&mut self, d: u8, taken: bool, taken_cycles: u64, cycles: u64) {
+        if taken {
+            self.pc = self.pc.wrapping_add(d as i8 as u16);
+            self.cycles += taken_cycles;
+        } else {
+            self.cycles += cycles;
+        }
+    }
+
+    // NZ, Z, NC, C, PO, PE, P, M.
+    fn z80_condition(&self, cc: u8) -> bool {
+        let flag = [Z, C, PV, S][cc as usize >> 1];
+        (self.flag & flag != 0) == (cc & 1 != 0)
+    }
+
+    fn z80_store(&mut self, addr: u16, v: u8, cycles: u64) {
+        self.ram.save_byte(addr, v);
+        self.cycles += cycles;
+    }
+
+    fn z80_load_a(&mut self, addr: u16, cycles: u64) {
+        self.a = self.ram.load_byte(addr);
+        self.cycles += cycles;
+    }
+
+    // The address of the (HL) operand; (IX+d) and (IY+d) fetch their
+    // displacement here.
+    fn z80_operand(&mut self, idx: Index) -> u16 {
+        if idx == Index::HL {
+            return self.get_hl_addr();
+        }
+        let d = self.next_byte() as i8;
+        self.z80_index(idx).wrapping_add(d as u16)
+    }
+
+    fn z80_index(&self, idx: Index) -> u16 {
+        match idx {
+            Index::HL => self.get_hl_addr(),
+            Index::IX => self.z80.ix,
+            Index::IY => self.z80.iy,
+        }
+    }
+
+    fn z80_set_index(&mut self, idx: Index, word: u16) {
+        match idx {
+            Index::HL => (self.h, self.l) = split_u16(word),
+            Index::IX => self.z80.ix = word,
+            Index::IY => self.z80.iy = word,
+        }
+    }
+
+    // B, C, D, E, H, L, -, A; H and L are the halves of IX or IY behind a
+    // prefix (undocumented).
+    fn z80_reg(&self, r: u8, idx: Index) -> u8 {
+        match r {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => (self.z80_index(idx) >> 8) as u8,
+            5 => self.z80_index(idx) as u8,
+            _ => self.a,
+        }
+    }
+
+    fn z80_set_reg(&mut self, r: u8, idx: Index, v: u8) {
+        match r {
+            0 => self.b = v,
+            1 => self.c = v,
+            2 => self.d = v,
+            3 => self.e = v,
+            4 | 5 => {
+                let word = self.z80_index(idx);
+                let word = if r == 4 { word & 0x00ff | (v as u16) << 8 } else { word & 0xff00 | v as u16 };
+                self.z80_set_index(idx, word);
+            },
+            _ => self.a = v,
+        }
+    }
+
+    // BC, DE, HL, SP.
+    fn z80_rp(&self, p: u8, idx: Index) -> u16 {
+        match p {
+            0 => self.get_bc_addr(),
+            1 => self.get_de_addr(),
+            2 => self.z80_index(idx),
+            _ => self.sp,
+        }
+    }
+
+    fn z80_set_rp(&mut self, p: u8, idx: Index, word: u16) {
+        match p {
+            0 => (self.b, self.c) = split_u16(word),
+            1 => (self.d, self.e) = split_u16(word),
+            2 => self.z80_set_index(idx, word),
+            _ => self.sp = word,
+        }
+    }
+
+    // BC, DE, HL, AF, as PUSH and POP name them.
+    fn z80_rp2(&self, p: u8, idx: Index) -> u16 {
+        if p == 3 { get_u16(self.a, self.flag) } else { self.z80_rp(p, idx) }
+    }
+
+    fn z80_set_rp2(&mut self, p: u8, idx: Index, word: u16) {
+        if p == 3 {
+            (self.a, self.flag) = split_u16(word);
+        } else {
+            self.z80_set_rp(p, idx, word);
+        }
+    }
+
+    fn z80_in(&mut self, port: u8) -> u8 {
+        if let Some(device) = &mut self.devices[port as usize] {
+            let v = device.read();
+            let waits = device.wait_states();
+            self.cycles += waits;
+            self.log_io(bus::INPUT_READ, port, v, waits);
+            v
+        } else {
+            eprintln!("No such device.");
+            self.halted = true;
+            0xff
+        }
+    }
+
+    fn z80_out(&mut self, port: u8, v: u8) {
+        if let Some(device) = &mut self.devices[port as usize] {
+            device.write(v);
+            let waits = device.wait_states();
+            self.cycles += waits;
+            self.log_io(bus::OUTPUT_WRITE, port, v, waits);
+        } else {
+            eprintln!("No such device.");
+            self.halted = true;
+        }
+    }
+
+    // ADD, ADC, SUB, SBC, AND, XOR, OR, CP.
+    fn z80_alu(&mut self, op: u8, v: u8) {
+        let a = self.a;
+        let carry = self.flag & C;
+        match op {
+            0 | 1 => {
+                let sum = a as u16 + v as u16 + if op == 1 { carry as u16 } else { 0 };
+                let res = sum as u8;
+                let overflow = !(a ^ v) & (a ^ res) & 0x80 != 0;
+                self.flag = szxy(res) | (a ^ v ^ res) & H | if overflow { PV } else { 0 }
+                    | if sum > 0xff { C } else { 0 };
+                self.a = res;
+            },
+            2 | 3 | 7 => {
+                let diff = (a as u16).wrapping_sub(v as u16)
+                    .wrapping_sub(if op == 3 { carry as u16 } else { 0 });
+                let res = diff as u8;
+                let overflow = (a ^ v) & (a ^ res) & 0x80 != 0;
+                // CP takes X and Y from the operand.
+                let xy = if op == 7 { v } else { res };
+                self.flag = szxy(res) & (S | Z) | xy & (X | Y) | (a ^ v ^ res) & H | N
+                    | if overflow { PV } else { 0 } | if diff > 0xff { C } else { 0 };
+                if op != 7 {
+                    self.a = res;
+                }
+            },
+            4 => {
+                self.a &= v;
+                self.flag = szxyp(self.a) | H;
+            },
+            5 => {
+                self.a ^= v;
+                self.flag = szxyp(self.a);
+            },
+            _ => {
+                self.a |= v;
+                self.flag = szxyp(self.a);
+            },
+        }
+    }
+
+    fn z80_inc(&mut self, v: u8) -> u8 {
+        let res = v.wrapping_add(1);
+        self.flag = self.flag & C | szxy(res) | if res & 0x0f == 0 { H } else { 0 }
+            | if res == 0x80 { PV } else { 0 };
+        res
+    }
+
+    fn z80_dec(&mut self, v: u8) -> u8 {
+        let res = v.wrapping_sub(1);
+        self.flag = self.flag & C | szxy(res) | N | if v & 0x0f == 0 { H } else { 0 }
+            | if res == 0x7f { PV } else { 0 };
+        res
+    }
+
+    // ADD HL,rp: only H and C (and X/Y from the high byte) change.
+    fn z80_add16(&mut self, lhs: u16, rhs: u16) -> u16 {
+        let sum = lhs as u32 + rhs as u32;
+        let res = sum as u16;
+        self.flag = self.flag & (S | Z | PV) | (res >> 8) as u8 & (X | Y)
+            | if (lhs ^ rhs ^ res) & 0x1000 != 0 { H } else { 0 }
+            | if sum > 0xffff { C } else { 0 };
+        res
+    }
+
+    // ADC HL,rp and SBC HL,rp.
+    fn z80_adc16(&mut self, v: u16, subtract: bool) {
+        let hl = self.get_hl_addr();
+        let carry = (self.flag & C) as u32;
+        let wide = if subtract {
+            (hl as u32).wrapping_sub(v as u32).wrapping_sub(carry)
+        } else {
+            hl as u32 + v as u32 + carry
+        };
+        let res = wide as u16;
+        let overflow = if subtract { (hl ^ v) & (hl ^ res) } else { !(hl ^ v) & (hl ^ res) } & 0x8000 != 0;
+        self.flag = (res >> 8) as u8 & (S | X | Y) | if res == 0 { Z } else { 0 }
+            | if (hl ^ v ^ res) & 0x1000 != 0 { H } else { 0 }
+            | if overflow { PV } else { 0 } | if subtract { N } else { 0 }
+            | if wide > 0xffff { C } else { 0 };
+        (self.h, self.l) = split_u16(res);
+    }
+
+    // RLC, RRC, RL, RR, SLA, SRA, SLL (undocumented), SRL.
+    fn z80_shift(&mut self, op: u8, v: u8) -> u8 {
+        let carry = self.flag & C;
+        let (res, out) = match op {
+            0 => (v.rotate_left(1), v >> 7),
+            1 => (v.rotate_right(1), v & 1),
+            2 => (v << 1 | carry, v >> 7),
+            3 => (v >> 1 | carry << 7, v & 1),
+            4 => (v << 1, v >> 7),
+            5 => (v >> 1 | v & 0x80, v & 1),
+            6 => (v << 1 | 1, v >> 7),
+            _ => (v >> 1, v & 1),
+        };
+        self.flag = szxyp(res) | out;
+        res
+    }
+
+    // RLCA, RRCA, RLA, RRA, DAA, CPL, SCF, CCF.
+    fn z80_accumulator(&mut self, op: u8) {
+        let (a, carry) = (self.a, self.flag & C);
+        let keep = self.flag & (S | Z | PV);
+        match op {
+            0..=3 => {
+                let (res, out) = match op {
+                    0 => (a.rotate_left(1), a >> 7),
+                    1 => (a.rotate_right(1), a & 1),
+                    2 => (a << 1 | carry, a >> 7),
+                    _ => (a >> 1 | carry << 7, a & 1),
+                };
+                self.a = res;
+                self.flag = keep | res & (X | Y) | out;
+            },
+            4 => {
+                let half = self.flag & H != 0;
+                let mut adjust = 0;
+                let mut carry = carry != 0;
+                if half || a & 0x0f > 9 {
+                    adjust |= 0x06;
+                }
+                if carry || a > 0x99 {
+                    adjust |= 0x60;
+                    carry = true;
+                }
+                let (res, half) = if self.flag & N != 0 {
+                    (a.wrapping_sub(adjust), half && a & 0x0f < 6)
+                } else {
+                    (a.wrapping_add(adjust), a & 0x0f > 9)
+                };
+                self.a = res;
+                self.flag = szxyp(res) | self.flag & N | if half { H } else { 0 }
+                    | if carry { C } else { 0 };
+            },
+            5 => {
+                self.a = !a;
+                self.flag = self.flag & (S | Z | PV | C) | self.a & (X | Y) | H | N;
+            },
+            6 => self.flag = keep | a & (X | Y) | C,
+            _ => self.flag = keep | a & (X | Y) | if carry != 0 { H } else { C },
+        }
+    }
+}